@@ -120,7 +120,18 @@ fn common_pcl_params(price_scale: Decimal) -> ConcentratedPoolParams {
     }
 }
 
-const FACTORY_ADDRESS: &str = include_str!("../../../pair_concentrated/src/factory_address");
+const CHAIN_ID: &str = "osmosis-1";
+
+/// Returns the factory address pinned for [`CHAIN_ID`] in the PCL wasm binary
+fn factory_address() -> &'static str {
+    include_str!("../../../pair_concentrated/src/factory_addresses")
+        .lines()
+        .find_map(|line| {
+            let (chain_id, factory) = line.split_once(' ')?;
+            (chain_id == CHAIN_ID).then_some(factory.trim())
+        })
+        .unwrap()
+}
 
 #[derive(Default)]
 struct HackyAddressGenerator<'a> {
@@ -141,7 +152,7 @@ impl<'a> AddressGenerator for HackyAddressGenerator<'a> {
     ) -> AnyResult<Addr> {
         if Self::FACTORY_MARKER.may_load(storage).unwrap().is_some() {
             Self::FACTORY_MARKER.remove(storage);
-            Ok(Addr::unchecked(FACTORY_ADDRESS))
+            Ok(Addr::unchecked(factory_address()))
         } else {
             Ok(Addr::unchecked(format!("contract{instance_id}")))
         }
@@ -175,6 +186,7 @@ impl Helper {
                     .init_balance(storage, owner, coins(1_000_000_000_000, "uosmo"))
                     .unwrap()
             });
+        app.update_block(|block| block.chain_id = CHAIN_ID.to_string());
 
         let pair_code_id = app.store_code(pair_contract());
         let factory_code_id = app.store_code(factory_contract());
@@ -238,7 +250,7 @@ impl Helper {
             coin_registry_address: coin_registry.to_string(),
         };
 
-        // Set marker in storage that the next contract is factory. We need this to have exact FACTORY_ADDRESS constant
+        // Set marker in storage that the next contract is factory. We need this to have exact factory address
         // which is hardcoded in the PCL code.
         app.init_modules(|_, _, storage| HackyAddressGenerator::FACTORY_MARKER.save(storage, &()))
             .unwrap();
@@ -377,6 +389,8 @@ impl Helper {
             slippage_tolerance: Some(f64_to_dec(0.5)),
             auto_stake: None,
             receiver: None,
+            min_lp_to_receive: None,
+            deadline: None,
         };
 
         self.app
@@ -22,7 +22,7 @@ use osmosis_std::types::osmosis::poolmanager::v1beta1::{
     TotalPoolLiquidityRequest,
 };
 use osmosis_std::types::osmosis::tokenfactory::v1beta1::{
-    MsgBurn, MsgCreateDenom, MsgCreateDenomResponse, MsgMint,
+    MsgBurn, MsgCreateDenom, MsgCreateDenomResponse, MsgMint, MsgSetDenomMetadata,
 };
 
 use astroport_on_osmosis::pair_pcl;
@@ -111,6 +111,7 @@ impl Stargate for OsmosisStargate {
                 };
                 Ok(submsg_response.into())
             }
+            MsgSetDenomMetadata::TYPE_URL => Ok(AppResponse::default()),
             MsgMint::TYPE_URL => {
                 let tf_msg: MsgMint = value.try_into()?;
                 let mint_coins = tf_msg
@@ -1,62 +1,103 @@
+use std::ops::RangeInclusive;
 use std::vec;
 
 use astroport::asset::AssetInfoExt;
 use astroport::asset::{
-    addr_opt_validate, Asset, AssetInfo, CoinsExt, Decimal256Ext, PairInfo,
-    MINIMUM_LIQUIDITY_AMOUNT,
+    addr_opt_validate, native_asset_info, Asset, AssetInfo, CoinsExt, Decimal256Ext, DecimalAsset,
+    PairInfo, MINIMUM_LIQUIDITY_AMOUNT,
 };
 use astroport::common::{claim_ownership, drop_ownership_proposal, propose_new_owner};
 use astroport::cosmwasm_ext::{AbsDiff, DecimalToInteger, IntegerToDecimal};
 use astroport::factory::PairType;
 use astroport::observation::{PrecommitObservation, OBSERVATIONS_SIZE};
-use astroport::pair::{InstantiateMsg, MIN_TRADE_SIZE};
-use astroport::pair_concentrated::{
-    ConcentratedPoolParams, ConcentratedPoolUpdateParams, UpdatePoolParams,
-};
-use astroport::querier::{query_factory_config, query_fee_info};
+use astroport::pair::{FeeShareConfig, InstantiateMsg, MAX_FEE_SHARE_BPS, MIN_TRADE_SIZE};
+use astroport::pair_concentrated::{ConcentratedPoolUpdateParams, UpdatePoolParams};
+use astroport::querier::query_factory_config;
 use astroport_circular_buffer::BufferManager;
 use astroport_pcl_common::state::{
     AmpGamma, Config, PoolParams, PoolState, Precisions, PriceState,
 };
 use astroport_pcl_common::utils::{
-    assert_max_spread, assert_slippage_tolerance, before_swap_check, calc_provide_fee,
-    check_assets, check_pair_registered, compute_swap, get_share_in_assets,
+    assert_max_spread, before_swap_check, check_assets, check_pair_registered, compute_swap,
+    get_share_in_assets,
 };
 use astroport_pcl_common::{calc_d, get_xcp};
 #[cfg(not(feature = "library"))]
 use cosmwasm_std::entry_point;
 use cosmwasm_std::{
-    attr, coin, ensure, from_json, to_json_binary, Addr, Binary, Decimal, Decimal256, DepsMut,
-    Empty, Env, MessageInfo, Reply, Response, StdError, StdResult, SubMsg, Uint128,
+    attr, coin, ensure, from_json, to_json_binary, wasm_execute, Addr, BankMsg, Binary, CosmosMsg,
+    Decimal, Decimal256, Deps, DepsMut, Env, MessageInfo, Reply, Response, StdError, StdResult,
+    SubMsg, SubMsgResult, Uint128,
 };
 use cw2::set_contract_version;
-use cw_utils::must_pay;
+use cw_utils::{must_pay, nonpayable, one_coin};
 use itertools::Itertools;
-use osmosis_std::types::osmosis::poolmanager::v1beta1::{MsgSwapExactAmountIn, SwapAmountInRoute};
+use osmosis_std::types::osmosis::poolmanager::v1beta1::{
+    MsgSwapExactAmountIn, MsgSwapExactAmountOut, SwapAmountInRoute, SwapAmountOutRoute,
+};
 use osmosis_std::types::osmosis::tokenfactory::v1beta1::{
-    MsgBurn, MsgCreateDenom, MsgCreateDenomResponse, MsgMint,
+    MsgBurn, MsgCreateDenom, MsgCreateDenomResponse,
 };
 
-use astroport_on_osmosis::pair_pcl::{ExecuteMsg, SwapExactAmountInResponseData};
+use astroport_on_osmosis::pair_pcl::{
+    ConcentratedPoolInitParams, ExecuteMsg, FlashSwapCallbackMsg, SwapExactAmountInResponseData,
+    SwapHookMsg, ZapInSwap,
+};
 
 use crate::error::ContractError;
 use crate::state::{
-    SwapParams, BALANCES, CONFIG, OBSERVATIONS, OWNERSHIP_PROPOSAL, POOL_ID, SWAP_PARAMS,
+    ExactOutRefund, FlashSwapState, SwapParams, ZapInParams, BALANCES, CONFIG, EXACT_OUT_REFUND,
+    FLASH_SWAP, MAKER_FEE_OVERRIDE, MAX_PRICE_DEVIATION, OBSERVATIONS, OWNERSHIP_PROPOSAL,
+    PAUSE_FLAGS, POOL_ID, SWAP_HOOKS, SWAP_PARAMS, ZAP_IN_PARAMS,
+};
+use crate::utils::{
+    accumulate_prices, accumulate_swap_sizes, apply_fee_ratio, calc_single_asset_withdraw,
+    calc_withdraw_share, compute_provide, default_lp_token_metadata, ensure_deadline,
+    ensure_min_assets_to_receive, ensure_no_flash_swap, ensure_owner, ensure_price_deviation,
+    ensure_referral, external_fee_ratio, fee_shares, get_withdraw_amounts, is_pool_active,
+    is_trusted_factory, load_pause_flags, mint_liquidity_token_message, prepare_provide_assets,
+    query_native_supply, query_pair_fee_info, query_pools, resize_observations,
+    set_lp_metadata_msg, swap_hook_messages, validate_lp_token_metadata, ProvideResult,
 };
-use crate::utils::{accumulate_swap_sizes, query_native_supply, query_pools};
 
 /// Contract name that is used for migration.
-const CONTRACT_NAME: &str = env!("CARGO_PKG_NAME");
+pub(crate) const CONTRACT_NAME: &str = env!("CARGO_PKG_NAME");
 /// Contract version that is used for migration.
-const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
-/// Hardcoded factory address
-const FACTORY_ADDRESS: &str = include_str!("factory_address");
+pub(crate) const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
+/// Trusted factory addresses pinned into the wasm binary.
+/// Each line contains a chain id and a factory address separated by a space.
+pub(crate) const TRUSTED_FACTORIES: &str = include_str!("factory_addresses");
 /// Tokenfactory LP token subdenom
 const LP_SUBDENOM: &str = "astroport/share";
 /// Reply ID for create denom reply
 const CREATE_DENOM_REPLY_ID: u64 = 1;
+/// Reply ID for the last zap-in swap reply
+const ZAP_IN_REPLY_ID: u64 = 2;
+/// Reply ID for failed swap hook calls
+pub(crate) const SWAP_HOOK_REPLY_ID: u64 = 3;
+/// Reply ID for the flash swap callback
+const FLASH_SWAP_REPLY_ID: u64 = 4;
+/// Reply ID for the exact out swap dispatched by the pair
+const SWAP_EXACT_OUT_REPLY_ID: u64 = 5;
 /// An LP token's precision.
 pub(crate) const LP_TOKEN_PRECISION: u8 = 6;
+/// Maximum length of a bank denom. It limits the LP token display denom
+pub(crate) const MAX_DENOM_LENGTH: usize = 128;
+/// Maximum allowed maker fee override (in bps). Maker fee is the share of the total swap fee.
+pub const MAX_MAKER_FEE_BPS: u16 = 10000;
+/// Maximum allowed referral commission. It is the share of the offer amount.
+pub const MAX_REFERRAL_COMMISSION: Decimal = Decimal::percent(1);
+/// Maximum number of swap hooks which can be registered
+pub const MAX_SWAP_HOOKS: usize = 5;
+/// Gas limit for each swap hook call. Out of gas errors are caught only for submessages with a gas limit,
+/// so a hook exceeding it fails on its own without reverting the swap.
+pub const SWAP_HOOK_GAS_LIMIT: u64 = 500_000;
+/// Maximum TWAP window (in seconds). Older price points are pruned
+pub const MAX_TWAP_WINDOW: u64 = 7 * 86400;
+/// Maximum number of expired price points removed in a single state update
+pub(crate) const MAX_PRUNED_TWAP_POINTS: usize = 10;
+/// Allowed observation buffer capacity
+pub const OBSERVATIONS_SIZE_LIMITS: RangeInclusive<u32> = 10..=10_000;
 
 /// Creates a new contract with the specified parameters in the [`InstantiateMsg`].
 #[cfg_attr(not(feature = "library"), entry_point)]
@@ -91,10 +132,15 @@ pub fn instantiate(
             .map_err(|_| StdError::generic_err(format!("Denom {denom} doesn't exist on chain")))?;
     }
 
-    let params: ConcentratedPoolParams = from_json(
+    let init_params: ConcentratedPoolInitParams = from_json(
         msg.init_params
             .ok_or(ContractError::InitParamsNotFound {})?,
     )?;
+    let observations_size = init_params.observations_size.unwrap_or(OBSERVATIONS_SIZE);
+    if !OBSERVATIONS_SIZE_LIMITS.contains(&observations_size) {
+        return Err(ContractError::InvalidObservationsSize {});
+    }
+    let params = init_params.params;
     ensure!(
         !params.price_scale.is_zero(),
         StdError::generic_err("Initial price scale can not be zero")
@@ -102,11 +148,14 @@ pub fn instantiate(
 
     set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
 
-    // We pin factory address into wasm binary to prevent using Astroport PCL pools on Osmosis without
+    // We pin trusted factory addresses into wasm binary to prevent using Astroport PCL pools on Osmosis without
     // paying fees to Astroport protocol.
     // Users are discouraged from instantiating PCL pools using usual Osmosis tools as such pool won't be included
     // in Astroport routing as well as swap and withdraw endpoints will be broken forever.
-    let factory_addr = Addr::unchecked(FACTORY_ADDRESS);
+    if !is_trusted_factory(&env.block.chain_id, &msg.factory_addr) {
+        return Err(ContractError::UntrustedFactory(msg.factory_addr));
+    }
+    let factory_addr = Addr::unchecked(&msg.factory_addr);
     Precisions::store_precisions(deps.branch(), &msg.asset_infos, &factory_addr)?;
 
     let mut pool_params = PoolParams::default();
@@ -158,9 +207,10 @@ pub fn instantiate(
         }
     }
 
+    accumulate_prices(deps.storage, &env, config.pool_state.price_state.last_price)?;
     CONFIG.save(deps.storage, &config)?;
 
-    BufferManager::init(deps.storage, OBSERVATIONS, OBSERVATIONS_SIZE)?;
+    BufferManager::init(deps.storage, OBSERVATIONS, observations_size)?;
 
     // create lp denom
     let msg_create_lp_denom = SubMsg::reply_on_success(
@@ -184,7 +234,7 @@ pub fn instantiate(
 }
 
 #[cfg_attr(not(feature = "library"), entry_point)]
-pub fn reply(deps: DepsMut, _env: Env, msg: Reply) -> Result<Response, ContractError> {
+pub fn reply(deps: DepsMut, env: Env, msg: Reply) -> Result<Response, ContractError> {
     match msg.id {
         CREATE_DENOM_REPLY_ID => {
             let MsgCreateDenomResponse { new_token_denom } = msg.result.try_into()?;
@@ -199,7 +249,115 @@ pub fn reply(deps: DepsMut, _env: Env, msg: Reply) -> Result<Response, ContractE
                 }
             })?;
 
-            Ok(Response::new().add_attribute("lp_denom", new_token_denom))
+            let config = CONFIG.load(deps.storage)?;
+            let metadata = default_lp_token_metadata(&config.pair_info.asset_infos);
+            let set_metadata_msg = set_lp_metadata_msg(
+                &env.contract.address,
+                &new_token_denom,
+                &config.pair_info.asset_infos,
+                metadata,
+            );
+
+            Ok(Response::new()
+                .add_message(set_metadata_msg)
+                .add_attribute("lp_denom", new_token_denom))
+        }
+        ZAP_IN_REPLY_ID => {
+            let params = ZAP_IN_PARAMS.load(deps.storage)?;
+            ZAP_IN_PARAMS.remove(deps.storage);
+
+            // Provide everything the pair received from zap-in swaps
+            let assets = params
+                .balances_before
+                .into_iter()
+                .map(|coin| -> StdResult<_> {
+                    let balance = deps
+                        .querier
+                        .query_balance(&env.contract.address, &coin.denom)?;
+                    Ok(native_asset_info(coin.denom).with_balance(balance.amount - coin.amount))
+                })
+                .filter_ok(|asset| !asset.amount.is_zero())
+                .collect::<StdResult<Vec<_>>>()?;
+            let info = MessageInfo {
+                sender: params.sender,
+                funds: assets
+                    .iter()
+                    .map(|asset| asset.as_coin())
+                    .collect::<StdResult<_>>()?,
+            };
+
+            provide_liquidity(
+                deps,
+                env,
+                info,
+                assets,
+                params.slippage_tolerance,
+                false,
+                params.receiver,
+                Some(params.min_lp_to_receive),
+                None,
+            )
+            .map(|response| response.add_attribute("method", "zap_in"))
+        }
+        // Swap hook failures must not revert the swap
+        SWAP_HOOK_REPLY_ID => match msg.result {
+            SubMsgResult::Err(err) => Ok(Response::new()
+                .add_attributes([attr("action", "swap_hook_failed"), attr("error", err)])),
+            SubMsgResult::Ok(_) => Ok(Response::new()),
+        },
+        FLASH_SWAP_REPLY_ID => {
+            let FlashSwapState {
+                offer_asset,
+                offer_balance_before,
+                swap_hooks,
+            } = FLASH_SWAP.load(deps.storage)?;
+            FLASH_SWAP.remove(deps.storage);
+
+            let offer_balance = deps
+                .querier
+                .query_balance(&env.contract.address, offer_asset.info.to_string())?
+                .amount;
+            let received = offer_balance.saturating_sub(offer_balance_before);
+            if received < offer_asset.amount {
+                return Err(ContractError::FlashSwapNotRepaid {
+                    expected: offer_asset.amount,
+                    received,
+                });
+            }
+
+            Ok(Response::new().add_submessages(swap_hooks).add_attributes([
+                attr("action", "flash_swap_repaid"),
+                attr("repaid_amount", received),
+            ]))
+        }
+        SWAP_EXACT_OUT_REPLY_ID => {
+            let ExactOutRefund {
+                receiver,
+                pool_balance,
+            } = EXACT_OUT_REFUND.load(deps.storage)?;
+            EXACT_OUT_REFUND.remove(deps.storage);
+
+            // Everything above the pool balance is unused offer amount minus the Osmosis taker fee
+            let offer_balance = deps
+                .querier
+                .query_balance(&env.contract.address, pool_balance.info.to_string())?
+                .amount;
+            let refund_amount = offer_balance.checked_sub(pool_balance.amount)?;
+
+            let mut response = Response::new().add_attributes([
+                attr("action", "refund_excess"),
+                attr("refund_amount", refund_amount),
+            ]);
+            if !refund_amount.is_zero() {
+                response = response.add_message(
+                    pool_balance
+                        .info
+                        .with_balance(refund_amount)
+                        .into_msg(receiver)?,
+                );
+            }
+
+            Ok(response)
         }
         _ => Err(StdError::generic_err(format!("Unknown reply id: {}", msg.id)).into()),
     }
@@ -213,22 +371,99 @@ pub fn execute(
     info: MessageInfo,
     msg: ExecuteMsg,
 ) -> Result<Response, ContractError> {
+    // Pair state must not change until the flash swap is repaid
+    ensure_no_flash_swap(deps.storage)?;
+
     match msg {
-        ExecuteMsg::SetPoolId { pool_id } => set_pool_id(deps, info, pool_id),
+        ExecuteMsg::SetPoolId { pool_id } => set_pool_id(deps, env, info, pool_id),
         ExecuteMsg::ProvideLiquidity {
             assets,
             slippage_tolerance,
+            auto_stake,
             receiver,
-            ..
-        } => provide_liquidity(deps, env, info, assets, slippage_tolerance, receiver),
+            min_lp_to_receive,
+            deadline,
+        } => provide_liquidity(
+            deps,
+            env,
+            info,
+            assets,
+            slippage_tolerance,
+            auto_stake.unwrap_or(false),
+            receiver,
+            min_lp_to_receive,
+            deadline,
+        ),
         ExecuteMsg::Swap {
             offer_asset,
             belief_price,
             max_spread,
             to,
+            deadline,
+            referral_address,
+            referral_commission,
             ..
-        } => execute_swap(deps, env, info, offer_asset, belief_price, max_spread, to),
-        ExecuteMsg::WithdrawLiquidity { assets } => withdraw_liquidity(deps, env, info, assets),
+        } => execute_swap(
+            deps,
+            env,
+            info,
+            offer_asset,
+            belief_price,
+            max_spread,
+            to,
+            deadline,
+            referral_address,
+            referral_commission,
+        ),
+        ExecuteMsg::SwapExactOut {
+            ask_asset,
+            max_offer,
+            to,
+        } => execute_swap_exact_out(deps, env, info, ask_asset, max_offer, to),
+        ExecuteMsg::FlashSwap {
+            offer_asset,
+            belief_price,
+            max_spread,
+            to,
+            msg,
+        } => execute_flash_swap(
+            deps,
+            env,
+            info,
+            offer_asset,
+            belief_price,
+            max_spread,
+            to,
+            msg,
+        ),
+        ExecuteMsg::ZapIn {
+            swaps,
+            slippage_tolerance,
+            min_lp_to_receive,
+            receiver,
+        } => zap_in(
+            deps,
+            env,
+            info,
+            swaps,
+            slippage_tolerance,
+            min_lp_to_receive,
+            receiver,
+        ),
+        ExecuteMsg::WithdrawLiquidity {
+            assets,
+            receiver,
+            min_assets_to_receive,
+            single_asset,
+        } => withdraw_liquidity(
+            deps,
+            env,
+            info,
+            assets,
+            receiver,
+            min_assets_to_receive,
+            single_asset,
+        ),
         ExecuteMsg::UpdateConfig { params } => update_config(deps, env, info, params),
         ExecuteMsg::ProposeNewOwner { owner, expires_in } => {
             let config = CONFIG.load(deps.storage)?;
@@ -257,6 +492,45 @@ pub fn execute(
             )
             .map_err(Into::into)
         }
+        ExecuteMsg::SetPauseFlags {
+            provide,
+            swap,
+            withdraw,
+        } => set_pause_flags(deps, info, provide, swap, withdraw),
+        ExecuteMsg::SetMaxPriceDeviation { max_deviation } => {
+            set_max_price_deviation(deps, info, max_deviation)
+        }
+        ExecuteMsg::SetMakerFeeOverride { maker_fee_bps } => {
+            set_maker_fee_override(deps, info, maker_fee_bps)
+        }
+        ExecuteMsg::UpdateSwapHooks { add, remove } => update_swap_hooks(deps, info, add, remove),
+        ExecuteMsg::UpdateLpTokenMetadata { metadata } => {
+            let config = CONFIG.load(deps.storage)?;
+            ensure_owner(deps.querier, &config, &info.sender)?;
+            validate_lp_token_metadata(&metadata)?;
+
+            let set_metadata_msg = set_lp_metadata_msg(
+                &env.contract.address,
+                config.pair_info.liquidity_token.as_str(),
+                &config.pair_info.asset_infos,
+                metadata,
+            );
+
+            Ok(Response::new()
+                .add_message(set_metadata_msg)
+                .add_attribute("action", "update_lp_token_metadata"))
+        }
+        ExecuteMsg::ResizeObservations { new_size } => {
+            let config = CONFIG.load(deps.storage)?;
+            ensure_owner(deps.querier, &config, &info.sender)?;
+
+            resize_observations(deps.storage, new_size)?;
+
+            Ok(Response::new().add_attributes([
+                attr("action", "resize_observations"),
+                attr("new_size", new_size.to_string()),
+            ]))
+        }
         ExecuteMsg::ClaimOwnership {} => {
             claim_ownership(deps, info, env, OWNERSHIP_PROPOSAL, |deps, new_owner| {
                 CONFIG.update::<_, StdError>(deps.storage, |mut config| {
@@ -271,8 +545,145 @@ pub fn execute(
     }
 }
 
-fn set_pool_id(deps: DepsMut, info: MessageInfo, pool_id: u64) -> Result<Response, ContractError> {
-    if info.sender.as_str() != FACTORY_ADDRESS {
+/// Registers and unregisters swap hook contracts.
+/// Only the pair owner (the factory owner if the pair owner is not set) can execute this.
+/// Hooks are removed first, then new ones are added.
+fn update_swap_hooks(
+    deps: DepsMut,
+    info: MessageInfo,
+    add: Vec<String>,
+    remove: Vec<String>,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    ensure_owner(deps.querier, &config, &info.sender)?;
+
+    let mut hooks = SWAP_HOOKS.may_load(deps.storage)?.unwrap_or_default();
+
+    for hook in remove {
+        let hook = deps.api.addr_validate(&hook)?;
+        let (ind, _) = hooks
+            .iter()
+            .find_position(|addr| **addr == hook)
+            .ok_or_else(|| ContractError::SwapHookNotFound(hook.to_string()))?;
+        hooks.remove(ind);
+    }
+
+    for hook in add {
+        let hook = deps.api.addr_validate(&hook)?;
+        if hooks.contains(&hook) {
+            return Err(ContractError::SwapHookAlreadyExists(hook.to_string()));
+        }
+        hooks.push(hook);
+    }
+
+    if hooks.len() > MAX_SWAP_HOOKS {
+        return Err(ContractError::TooManySwapHooks {});
+    }
+
+    SWAP_HOOKS.save(deps.storage, &hooks)?;
+
+    Ok(Response::new().add_attributes([
+        attr("action", "update_swap_hooks"),
+        attr("swap_hooks", hooks.iter().join(",")),
+    ]))
+}
+
+/// Pauses or unpauses pool operations.
+/// Only the pair owner (the factory owner if the pair owner is not set) can execute this.
+/// Omitted flags are left unchanged.
+fn set_pause_flags(
+    deps: DepsMut,
+    info: MessageInfo,
+    provide: Option<bool>,
+    swap: Option<bool>,
+    withdraw: Option<bool>,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    ensure_owner(deps.querier, &config, &info.sender)?;
+
+    let mut flags = load_pause_flags(deps.storage)?;
+    flags.provide = provide.unwrap_or(flags.provide);
+    flags.swap = swap.unwrap_or(flags.swap);
+    flags.withdraw = withdraw.unwrap_or(flags.withdraw);
+    PAUSE_FLAGS.save(deps.storage, &flags)?;
+
+    Ok(Response::new().add_attributes([
+        attr("action", "set_pause_flags"),
+        attr("provide", flags.provide.to_string()),
+        attr("swap", flags.swap.to_string()),
+        attr("withdraw", flags.withdraw.to_string()),
+    ]))
+}
+
+/// Sets the maximum allowed deviation of the post-trade price from the EMA oracle price.
+/// Only the pair owner (the factory owner if the pair owner is not set) can execute this.
+fn set_max_price_deviation(
+    deps: DepsMut,
+    info: MessageInfo,
+    max_deviation: Option<Decimal>,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    ensure_owner(deps.querier, &config, &info.sender)?;
+
+    match max_deviation {
+        Some(max_deviation) => {
+            if max_deviation.is_zero() {
+                return Err(ContractError::InvalidMaxPriceDeviation {});
+            }
+            MAX_PRICE_DEVIATION.save(deps.storage, &max_deviation)?;
+        }
+        None => MAX_PRICE_DEVIATION.remove(deps.storage),
+    }
+
+    Ok(Response::new().add_attributes([
+        attr("action", "set_max_price_deviation"),
+        attr(
+            "max_deviation",
+            max_deviation.map_or_else(|| "none".to_string(), |dev| dev.to_string()),
+        ),
+    ]))
+}
+
+/// Overrides the maker fee configured in the factory for this pair.
+/// Only the factory owner can execute this.
+fn set_maker_fee_override(
+    deps: DepsMut,
+    info: MessageInfo,
+    maker_fee_bps: Option<u16>,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    let factory_config = query_factory_config(&deps.querier, &config.factory_addr)?;
+
+    if info.sender != factory_config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    match maker_fee_bps {
+        Some(maker_fee_bps) => {
+            if maker_fee_bps > MAX_MAKER_FEE_BPS {
+                return Err(ContractError::MakerFeeOverrideOutOfBounds {});
+            }
+            MAKER_FEE_OVERRIDE.save(deps.storage, &maker_fee_bps)?;
+        }
+        None => MAKER_FEE_OVERRIDE.remove(deps.storage),
+    }
+
+    Ok(Response::new().add_attributes([
+        attr("action", "set_maker_fee_override"),
+        attr(
+            "maker_fee_bps",
+            maker_fee_bps.map_or_else(|| "none".to_string(), |bps| bps.to_string()),
+        ),
+    ]))
+}
+
+fn set_pool_id(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    pool_id: u64,
+) -> Result<Response, ContractError> {
+    if !is_trusted_factory(&env.block.chain_id, info.sender.as_str()) {
         return Err(ContractError::Unauthorized {});
     }
 
@@ -294,16 +705,32 @@ fn set_pool_id(deps: DepsMut, info: MessageInfo, pool_id: u64) -> Result<Respons
 /// * **slippage_tolerance** is an optional parameter which is used to specify how much
 /// the pool price can move until the provide liquidity transaction goes through.
 ///
+/// * **auto_stake** determines whether the resulting LP tokens are automatically staked in
+/// the incentives contract to receive token incentives.
+///
 /// * **receiver** is an optional parameter which defines the receiver of the LP tokens.
 /// If no custom receiver is specified, the pair will mint LP tokens for the function caller.
+///
+/// * **min_lp_to_receive** is an optional parameter which defines the minimum amount of LP tokens
+/// the receiver must get.
+///
+/// * **deadline** is an optional block time (in seconds) after which the provide is rejected.
+#[allow(clippy::too_many_arguments)]
 pub fn provide_liquidity(
     deps: DepsMut,
     env: Env,
     info: MessageInfo,
-    mut assets: Vec<Asset>,
+    assets: Vec<Asset>,
     slippage_tolerance: Option<Decimal>,
+    auto_stake: bool,
     receiver: Option<String>,
+    min_lp_to_receive: Option<Uint128>,
+    deadline: Option<u64>,
 ) -> Result<Response, ContractError> {
+    if load_pause_flags(deps.storage)?.provide {
+        return Err(ContractError::ProvidePaused {});
+    }
+
     check_assets(deps.api, &assets)?;
 
     let mut config = CONFIG.load(deps.storage)?;
@@ -315,30 +742,7 @@ pub fn provide_liquidity(
         return Err(ContractError::PairIsNotRegistered {});
     }
 
-    match assets.len() {
-        0 => {
-            return Err(StdError::generic_err("Nothing to provide").into());
-        }
-        1 => {
-            // Append omitted asset with explicit zero amount
-            let (given_ind, _) = config
-                .pair_info
-                .asset_infos
-                .iter()
-                .find_position(|pool| pool.equal(&assets[0].info))
-                .ok_or_else(|| ContractError::InvalidAsset(assets[0].info.to_string()))?;
-            assets.push(Asset {
-                info: config.pair_info.asset_infos[1 ^ given_ind].clone(),
-                amount: Uint128::zero(),
-            });
-        }
-        2 => {}
-        _ => {
-            return Err(ContractError::InvalidNumberOfAssets(
-                config.pair_info.asset_infos.len(),
-            ))
-        }
-    }
+    let assets = prepare_provide_assets(&config, assets)?;
 
     info.funds
         .assert_coins_properly_sent(&assets, &config.pair_info.asset_infos)?;
@@ -346,10 +750,6 @@ pub fn provide_liquidity(
     let precisions = Precisions::new(deps.storage)?;
     let mut pools = query_pools(deps.querier, &env.contract.address, &config, &precisions)?;
 
-    if pools[0].info.equal(&assets[1].info) {
-        assets.swap(0, 1);
-    }
-
     // precisions.get_precision() also validates that the asset belongs to the pool
     let deposits = [
         Decimal256::with_precision(assets[0].amount, precisions.get_precision(&assets[0].info)?)?,
@@ -359,12 +759,7 @@ pub fn provide_liquidity(
     let total_share = query_native_supply(&deps.querier, &config.pair_info.liquidity_token)?
         .to_decimal256(LP_TOKEN_PRECISION)?;
 
-    // Initial provide can not be one-sided
-    if total_share.is_zero() && (deposits[0].is_zero() || deposits[1].is_zero()) {
-        return Err(ContractError::InvalidZeroAmount {});
-    }
-
-    let mut messages = vec![];
+    let mut messages: Vec<CosmosMsg> = vec![];
     for (i, pool) in pools.iter_mut().enumerate() {
         match &pool.info {
             AssetInfo::NativeToken { .. } => {
@@ -376,101 +771,60 @@ pub fn provide_liquidity(
         }
     }
 
-    let mut new_xp = pools
-        .iter()
-        .enumerate()
-        .map(|(ind, pool)| pool.amount + deposits[ind])
-        .collect_vec();
-    new_xp[1] *= config.pool_state.price_state.price_scale;
-
-    let amp_gamma = config.pool_state.get_amp_gamma(&env);
-    let new_d = calc_d(&new_xp, &amp_gamma)?;
-
-    let share = if total_share.is_zero() {
-        let xcp = get_xcp(new_d, config.pool_state.price_state.price_scale);
-        let mint_amount =
-            xcp.saturating_sub(MINIMUM_LIQUIDITY_AMOUNT.to_decimal256(LP_TOKEN_PRECISION)?);
-
-        // share cannot become zero after minimum liquidity subtraction
-        if mint_amount.is_zero() {
-            return Err(ContractError::MinimumLiquidityAmountError {});
-        }
-
-        messages.push(MsgMint {
-            sender: env.contract.address.to_string(),
-            amount: Some(
-                coin(
-                    MINIMUM_LIQUIDITY_AMOUNT.u128(),
-                    config.pair_info.liquidity_token.to_string(),
-                )
-                .into(),
-            ),
-            mint_to_address: env.contract.address.to_string(),
-        });
-
-        config.pool_state.price_state.xcp_profit_real = Decimal256::one();
-        config.pool_state.price_state.xcp_profit = Decimal256::one();
-
-        mint_amount
-    } else {
-        let mut old_xp = pools.iter().map(|a| a.amount).collect_vec();
-        old_xp[1] *= config.pool_state.price_state.price_scale;
-        let old_d = calc_d(&old_xp, &amp_gamma)?;
-        let share = (total_share * new_d / old_d).saturating_sub(total_share);
-
-        let mut ideposits = deposits;
-        ideposits[1] *= config.pool_state.price_state.price_scale;
-
-        share * (Decimal256::one() - calc_provide_fee(&ideposits, &new_xp, &config.pool_params))
-    };
-
-    // calculate accrued share
-    let share_ratio = share / (total_share + share);
-    let balanced_share = [
-        new_xp[0] * share_ratio,
-        new_xp[1] * share_ratio / config.pool_state.price_state.price_scale,
-    ];
-    let assets_diff = [
-        deposits[0].diff(balanced_share[0]),
-        deposits[1].diff(balanced_share[1]),
-    ];
-
-    let mut slippage = Decimal256::zero();
-
-    // If deposit doesn't diverge too much from the balanced share, we don't update the price
-    if assets_diff[0] >= MIN_TRADE_SIZE && assets_diff[1] >= MIN_TRADE_SIZE {
-        slippage = assert_slippage_tolerance(
-            &deposits,
-            share,
-            &config.pool_state.price_state,
-            slippage_tolerance,
+    let xs = pools.iter().map(|pool| pool.amount).collect_vec();
+    let ProvideResult {
+        share,
+        slippage,
+        price_updated,
+        ..
+    } = compute_provide(
+        &mut config,
+        &env,
+        &xs,
+        deposits,
+        total_share,
+        slippage_tolerance,
+    )?;
+    if price_updated {
+        ensure_price_deviation(
+            deps.storage,
+            config.pool_state.price_state.oracle_price,
+            config.pool_state.price_state.last_price,
         )?;
+    }
 
-        let last_price = assets_diff[0] / assets_diff[1];
-        config.pool_state.update_price(
-            &config.pool_params,
-            &env,
-            total_share + share,
-            &new_xp,
-            last_price,
-        )?;
+    if total_share.is_zero() {
+        // Lock minimum liquidity on the pair contract
+        messages.extend(mint_liquidity_token_message(
+            deps.querier,
+            &config,
+            &env.contract.address,
+            &env.contract.address,
+            MINIMUM_LIQUIDITY_AMOUNT,
+            false,
+        )?);
     }
 
     let share_uint128 = share.to_uint(LP_TOKEN_PRECISION)?;
 
+    ensure_deadline(&env, deadline)?;
+    if let Some(min_lp_to_receive) = min_lp_to_receive {
+        ensure!(
+            share_uint128 >= min_lp_to_receive,
+            ContractError::ProvideSlippageViolation(share_uint128, min_lp_to_receive)
+        );
+    }
+
     // Mint LP tokens for the sender or for the receiver (if set)
     let receiver = addr_opt_validate(deps.api, &receiver)?.unwrap_or_else(|| info.sender.clone());
-    messages.push(MsgMint {
-        sender: env.contract.address.to_string(),
-        amount: Some(
-            coin(
-                share_uint128.u128(),
-                config.pair_info.liquidity_token.to_string(),
-            )
-            .into(),
-        ),
-        mint_to_address: receiver.to_string(),
-    });
+    messages.extend(mint_liquidity_token_message(
+        deps.querier,
+        &config,
+        &env.contract.address,
+        &receiver,
+        share_uint128,
+        auto_stake,
+    )?);
 
     if config.track_asset_balances {
         for (i, pool) in pools.iter().enumerate() {
@@ -486,6 +840,7 @@ pub fn provide_liquidity(
         }
     }
 
+    accumulate_prices(deps.storage, &env, config.pool_state.price_state.last_price)?;
     CONFIG.save(deps.storage, &config)?;
 
     let attrs = vec![
@@ -495,20 +850,188 @@ pub fn provide_liquidity(
         attr("assets", format!("{}, {}", &assets[0], &assets[1])),
         attr("share", share_uint128),
         attr("slippage", slippage.to_string()),
+        attr("auto_stake", auto_stake.to_string()),
     ];
 
     Ok(Response::new().add_messages(messages).add_attributes(attrs))
 }
 
+/// Provides liquidity with a single coin which doesn't belong to the pool.
+/// The coin is swapped into pool assets via the Osmosis pool manager. The last swap replies
+/// with [`ZAP_IN_REPLY_ID`] where received amounts are provided through [`provide_liquidity`].
+///
+/// * **swaps** are pool manager routes from the sent coin to pool assets.
+///
+/// * **slippage_tolerance** is an optional parameter which is used to specify how much
+/// the pool price can move until the provide liquidity transaction goes through.
+///
+/// * **min_lp_to_receive** defines the minimum amount of LP tokens the receiver must get.
+/// It is the only slippage protection for pool manager swaps thus it must not be zero.
+///
+/// * **receiver** is an optional parameter which defines the receiver of the LP tokens.
+pub fn zap_in(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    swaps: Vec<ZapInSwap>,
+    slippage_tolerance: Option<Decimal>,
+    min_lp_to_receive: Uint128,
+    receiver: Option<String>,
+) -> Result<Response, ContractError> {
+    if load_pause_flags(deps.storage)?.provide {
+        return Err(ContractError::ProvidePaused {});
+    }
+    if min_lp_to_receive.is_zero() {
+        return Err(ContractError::ZapInMinLpNotSet {});
+    }
+    // Zap-in parameters must not be overwritten before the reply
+    if ZAP_IN_PARAMS.may_load(deps.storage)?.is_some() {
+        return Err(ContractError::ZapInInProgress {});
+    }
+
+    let config = CONFIG.load(deps.storage)?;
+    let pool_denoms = config
+        .pair_info
+        .asset_infos
+        .iter()
+        .map(|asset_info| asset_info.to_string())
+        .collect_vec();
+
+    let coin_in = one_coin(&info)?;
+    ensure!(
+        !pool_denoms.contains(&coin_in.denom),
+        StdError::generic_err(format!(
+            "Zap-in coin {} must not belong to the pool. Use provide instead",
+            coin_in.denom
+        ))
+    );
+    ensure!(
+        (1..=2).contains(&swaps.len()),
+        StdError::generic_err("Zap-in requires one or two swaps")
+    );
+    ensure!(
+        swaps.iter().map(|swap| swap.amount).sum::<Uint128>() == coin_in.amount,
+        StdError::generic_err("Sum of zap-in swap amounts must be equal to the sent amount")
+    );
+
+    let pool_id = POOL_ID.may_load(deps.storage)?;
+    let mut out_denoms = vec![];
+    for swap in &swaps {
+        ensure!(
+            !swap.amount.is_zero(),
+            StdError::generic_err("Zap-in swap amount must not be zero")
+        );
+        let out_denom = swap
+            .route
+            .last()
+            .map(|hop| hop.token_out_denom.clone())
+            .ok_or_else(|| StdError::generic_err("Zap-in swap route must not be empty"))?;
+        ensure!(
+            pool_denoms.contains(&out_denom) && !out_denoms.contains(&out_denom),
+            StdError::generic_err(format!(
+                "Each zap-in route must end with a distinct pool asset. Got {out_denom}"
+            ))
+        );
+        // Swapping through this pool would mess up pool reserves
+        ensure!(
+            swap.route.iter().all(|hop| Some(hop.pool_id) != pool_id),
+            StdError::generic_err("Zap-in route must not include this pool")
+        );
+        out_denoms.push(out_denom);
+    }
+
+    let balances_before = pool_denoms
+        .iter()
+        .map(|denom| deps.querier.query_balance(&env.contract.address, denom))
+        .collect::<StdResult<Vec<_>>>()?;
+    ZAP_IN_PARAMS.save(
+        deps.storage,
+        &ZapInParams {
+            sender: info.sender,
+            receiver,
+            slippage_tolerance,
+            min_lp_to_receive,
+            balances_before,
+        },
+    )?;
+
+    let last_ind = swaps.len() - 1;
+    let submessages = swaps
+        .into_iter()
+        .enumerate()
+        .map(|(ind, swap)| {
+            let swap_msg = MsgSwapExactAmountIn {
+                sender: env.contract.address.to_string(),
+                routes: swap
+                    .route
+                    .into_iter()
+                    .map(|hop| SwapAmountInRoute {
+                        pool_id: hop.pool_id,
+                        token_out_denom: hop.token_out_denom,
+                    })
+                    .collect(),
+                token_in: Some(coin(swap.amount.u128(), &coin_in.denom).into()),
+                // Resulting amount is guarded by min_lp_to_receive
+                token_out_min_amount: "1".to_string(),
+            };
+
+            // Provide only after the last swap is executed
+            if ind == last_ind {
+                SubMsg::reply_on_success(swap_msg, ZAP_IN_REPLY_ID)
+            } else {
+                SubMsg::new(swap_msg)
+            }
+        })
+        .collect_vec();
+
+    Ok(Response::new()
+        .add_submessages(submessages)
+        .add_attributes([
+            attr("action", "zap_in"),
+            attr("coin_in", coin_in.to_string()),
+        ]))
+}
+
 /// Withdraw liquidity from the pool.
 ///
 /// * **assets** defines number of coins a user wants to withdraw per each asset.
+/// If empty, the user receives their share of all pool assets. Otherwise, the pair burns only
+/// the amount of LP tokens needed to withdraw exactly these coins and refunds the rest.
+///
+/// * **receiver** is an optional parameter which defines the receiver of the withdrawn assets.
+/// If no custom receiver is specified, the pair sends assets to the function caller.
+///
+/// * **min_assets_to_receive** is an optional parameter which defines the minimum amount
+/// of each specified asset the receiver must get.
+///
+/// * **single_asset** if set, the pair burns all sent LP tokens and returns only this asset.
+///
+/// Imbalanced and single asset withdrawals trade against the pool,
+/// thus they are rejected while swaps are paused or the pool is inactive.
+#[allow(clippy::too_many_arguments)]
 pub fn withdraw_liquidity(
     deps: DepsMut,
     env: Env,
     info: MessageInfo,
     assets: Vec<Asset>,
+    receiver: Option<String>,
+    min_assets_to_receive: Option<Vec<Asset>>,
+    single_asset: Option<AssetInfo>,
 ) -> Result<Response, ContractError> {
+    let pause_flags = load_pause_flags(deps.storage)?;
+    if pause_flags.withdraw {
+        return Err(ContractError::WithdrawPaused {});
+    }
+    if !assets.is_empty() || single_asset.is_some() {
+        if !is_pool_active(deps.storage)? {
+            return Err(ContractError::PoolIsNotActive {});
+        }
+        if pause_flags.swap {
+            return Err(ContractError::SwapPaused {});
+        }
+    }
+
+    let receiver = addr_opt_validate(deps.api, &receiver)?.unwrap_or_else(|| info.sender.clone());
     let mut config = CONFIG.load(deps.storage)?;
     let share_denom = config.pair_info.liquidity_token.as_str();
 
@@ -526,11 +1049,84 @@ pub fn withdraw_liquidity(
     let total_share = query_native_supply(&deps.querier, &config.pair_info.liquidity_token)?;
     let mut messages = vec![];
 
-    let refund_assets = if assets.is_empty() {
+    let (refund_assets, burn_amount, last_price) = if assets.is_empty() && single_asset.is_none() {
         // Usual withdraw (balanced)
-        get_share_in_assets(&pools, amount.saturating_sub(Uint128::one()), total_share)
+        let refund_assets =
+            get_share_in_assets(&pools, amount.saturating_sub(Uint128::one()), total_share);
+        (refund_assets, amount, None)
     } else {
-        return Err(StdError::generic_err("Imbalanced withdraw is currently disabled").into());
+        let (withdraw_amounts, burn_amount, new_xp) = match single_asset {
+            // Single asset withdraw
+            Some(asset_info) => {
+                ensure!(
+                    assets.is_empty(),
+                    StdError::generic_err(
+                        "Single asset withdraw can not be combined with imbalanced withdraw"
+                    )
+                );
+                let asset_ind = pools
+                    .iter()
+                    .position(|pool| pool.info == asset_info)
+                    .ok_or_else(|| ContractError::InvalidAsset(asset_info.to_string()))?;
+                let (withdraw_amounts, new_xp) = calc_single_asset_withdraw(
+                    &config,
+                    &env,
+                    &pools,
+                    asset_ind,
+                    amount,
+                    total_share,
+                )?;
+
+                (withdraw_amounts, amount, new_xp)
+            }
+            // Imbalanced withdraw
+            None => {
+                let withdraw_amounts = get_withdraw_amounts(&assets, &pools, &precisions)?;
+                let (burn_amount, new_xp) =
+                    calc_withdraw_share(&config, &env, &pools, &withdraw_amounts, total_share)?;
+                ensure!(
+                    burn_amount <= amount,
+                    ContractError::InsufficientWithdrawShare {
+                        needed: burn_amount,
+                        sent: amount,
+                    }
+                );
+
+                (withdraw_amounts, burn_amount, new_xp)
+            }
+        };
+
+        // If withdraw doesn't diverge too much from the balanced share, we don't update the price
+        let share_ratio = Decimal256::from_ratio(burn_amount, total_share);
+        let assets_diff = [
+            withdraw_amounts[0].diff(pools[0].amount * share_ratio),
+            withdraw_amounts[1].diff(pools[1].amount * share_ratio),
+        ];
+        let last_price = if assets_diff[0] >= MIN_TRADE_SIZE && assets_diff[1] >= MIN_TRADE_SIZE {
+            // Imbalanced part of the withdraw is an implicit swap, thus it is reflected in observations
+            accumulate_swap_sizes(deps.storage, &env)?;
+            PrecommitObservation::save(
+                deps.storage,
+                &env,
+                assets_diff[0].to_uint(precisions.get_precision(&pools[0].info)?)?,
+                assets_diff[1].to_uint(precisions.get_precision(&pools[1].info)?)?,
+            )?;
+
+            Some((assets_diff[0] / assets_diff[1], new_xp))
+        } else {
+            None
+        };
+
+        let refund_assets = pools
+            .iter()
+            .zip(withdraw_amounts)
+            .map(|(pool, amount)| DecimalAsset {
+                info: pool.info.clone(),
+                amount,
+            })
+            .collect_vec();
+
+        (refund_assets, burn_amount, last_price)
     };
 
     // decrease XCP
@@ -541,9 +1137,24 @@ pub fn withdraw_liquidity(
     xs[1] *= config.pool_state.price_state.price_scale;
     let amp_gamma = config.pool_state.get_amp_gamma(&env);
     let d = calc_d(&xs, &amp_gamma)?;
+    let total_share_after = (total_share - burn_amount).to_decimal256(LP_TOKEN_PRECISION)?;
     config.pool_state.price_state.xcp_profit_real =
-        get_xcp(d, config.pool_state.price_state.price_scale)
-            / (total_share - amount).to_decimal256(LP_TOKEN_PRECISION)?;
+        get_xcp(d, config.pool_state.price_state.price_scale) / total_share_after;
+
+    if let Some((last_price, new_xp)) = last_price {
+        config.pool_state.update_price(
+            &config.pool_params,
+            &env,
+            total_share_after,
+            &new_xp,
+            last_price,
+        )?;
+        ensure_price_deviation(
+            deps.storage,
+            config.pool_state.price_state.oracle_price,
+            config.pool_state.price_state.last_price,
+        )?;
+    }
 
     let refund_assets = refund_assets
         .into_iter()
@@ -553,21 +1164,34 @@ pub fn withdraw_liquidity(
         })
         .collect::<StdResult<Vec<_>>>()?;
 
+    ensure_min_assets_to_receive(&refund_assets, min_assets_to_receive)?;
+
     messages.extend(
         refund_assets
             .iter()
+            .filter(|asset| !asset.amount.is_zero())
             .cloned()
-            .map(|asset| asset.into_msg(&info.sender))
+            .map(|asset| asset.into_msg(&receiver))
             .collect::<StdResult<Vec<_>>>()?,
     );
     messages.push(
         MsgBurn {
             sender: env.contract.address.to_string(),
-            amount: Some(coin(amount.u128(), share_denom).into()),
+            amount: Some(coin(burn_amount.u128(), share_denom).into()),
             burn_from_address: env.contract.address.to_string(), // pair contract itself already holding these tokens
         }
         .into(),
     );
+    // Return unused LP tokens back to the sender
+    if burn_amount < amount {
+        messages.push(
+            BankMsg::Send {
+                to_address: info.sender.to_string(),
+                amount: vec![coin((amount - burn_amount).u128(), share_denom)],
+            }
+            .into(),
+        );
+    }
 
     if config.track_asset_balances {
         for (i, pool) in pools.iter().enumerate() {
@@ -583,12 +1207,14 @@ pub fn withdraw_liquidity(
         }
     }
 
+    accumulate_prices(deps.storage, &env, config.pool_state.price_state.last_price)?;
     CONFIG.save(deps.storage, &config)?;
 
     Ok(Response::new().add_messages(messages).add_attributes(vec![
         attr("action", "withdraw_liquidity"),
-        attr("receiver", info.sender),
-        attr("withdrawn_share", amount),
+        attr("sender", info.sender),
+        attr("receiver", receiver),
+        attr("withdrawn_share", burn_amount),
         attr("refund_assets", refund_assets.iter().join(", ")),
     ]))
 }
@@ -605,6 +1231,14 @@ pub fn withdraw_liquidity(
 /// * **max_spread** sets the maximum spread of the swap operation.
 ///
 /// * **to** sets the recipient of the swap operation.
+///
+/// * **deadline** is an optional block time (in seconds) after which the swap is rejected.
+///
+/// * **referral_address** is an optional address which receives the referral commission.
+///
+/// * **referral_commission** is the share of the offer amount sent to the referral address.
+/// It must be specified together with **referral_address**.
+#[allow(clippy::too_many_arguments)]
 pub fn execute_swap(
     deps: DepsMut,
     env: Env,
@@ -613,9 +1247,19 @@ pub fn execute_swap(
     belief_price: Option<Decimal>,
     max_spread: Option<Decimal>,
     to: Option<String>,
+    deadline: Option<u64>,
+    referral_address: Option<String>,
+    referral_commission: Option<Decimal>,
 ) -> Result<Response, ContractError> {
     offer_asset.assert_sent_native_token_balance(&info)?;
 
+    if !is_pool_active(deps.storage)? {
+        return Err(ContractError::PoolIsNotActive {});
+    }
+    if load_pause_flags(deps.storage)?.swap {
+        return Err(ContractError::SwapPaused {});
+    }
+
     let config = CONFIG.load(deps.storage)?;
     if !config.pair_info.asset_infos.contains(&offer_asset.info) {
         return Err(ContractError::InvalidAsset(offer_asset.info.to_string()));
@@ -629,6 +1273,8 @@ pub fn execute_swap(
         .unwrap();
     let token_out_denom = config.pair_info.asset_infos[1 ^ offer_ind].to_string();
 
+    ensure_referral(&referral_address, referral_commission)?;
+
     SWAP_PARAMS.save(
         deps.storage,
         &SwapParams {
@@ -636,6 +1282,10 @@ pub fn execute_swap(
             max_spread,
             sender: info.sender,
             to: addr_opt_validate(deps.api, &to)?,
+            deadline,
+            referral_address: addr_opt_validate(deps.api, &referral_address)?,
+            referral_commission,
+            pools_before: pools_before_offer(deps.as_ref(), &env, &config, &offer_asset)?,
         },
     )?;
     let dispatch_swap_msg = MsgSwapExactAmountIn {
@@ -656,6 +1306,194 @@ pub fn execute_swap(
         .add_message(dispatch_swap_msg))
 }
 
+/// Performs a swap operation which returns exactly **ask_asset** to the receiver.
+///
+/// Same as [`execute_swap`] it forwards swap request to the Osmosis DEX module.
+/// Swap parameters are passed to the sudo call through [`SWAP_PARAMS`].
+///
+/// * **ask_asset** the exact asset to receive.
+///
+/// * **max_offer** the maximum offer asset amount the sender is willing to pay.
+/// Unused amount minus the Osmosis taker fee is refunded to the sender in the [`SWAP_EXACT_OUT_REPLY_ID`] reply.
+///
+/// * **to** sets the recipient of the swap operation.
+pub fn execute_swap_exact_out(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    ask_asset: Asset,
+    max_offer: Asset,
+    to: Option<String>,
+) -> Result<Response, ContractError> {
+    max_offer.assert_sent_native_token_balance(&info)?;
+
+    if !is_pool_active(deps.storage)? {
+        return Err(ContractError::PoolIsNotActive {});
+    }
+    if load_pause_flags(deps.storage)?.swap {
+        return Err(ContractError::SwapPaused {});
+    }
+
+    let config = CONFIG.load(deps.storage)?;
+    for asset_info in [&ask_asset.info, &max_offer.info] {
+        if !config.pair_info.asset_infos.contains(asset_info) {
+            return Err(ContractError::InvalidAsset(asset_info.to_string()));
+        }
+    }
+    ensure!(
+        ask_asset.info != max_offer.info,
+        StdError::generic_err(format!(
+            "Invalid swap: {} to {}",
+            max_offer.info, ask_asset.info
+        ))
+    );
+
+    SWAP_PARAMS.save(
+        deps.storage,
+        &SwapParams {
+            belief_price: None,
+            max_spread: None,
+            sender: info.sender,
+            to: addr_opt_validate(deps.api, &to)?,
+            deadline: None,
+            referral_address: None,
+            referral_commission: None,
+            pools_before: pools_before_offer(deps.as_ref(), &env, &config, &max_offer)?,
+        },
+    )?;
+    let dispatch_swap_msg = MsgSwapExactAmountOut {
+        sender: env.contract.address.to_string(),
+        routes: vec![SwapAmountOutRoute {
+            pool_id: POOL_ID.load(deps.storage)?,
+            token_in_denom: max_offer.info.to_string(),
+        }],
+        token_in_max_amount: max_offer.amount.to_string(),
+        token_out: Some(ask_asset.as_coin()?.into()),
+    };
+    Ok(Response::new()
+        .add_attribute("action", "dispatch_swap_exact_out")
+        .add_submessage(SubMsg::reply_on_success(
+            dispatch_swap_msg,
+            SWAP_EXACT_OUT_REPLY_ID,
+        )))
+}
+
+/// Performs a swap which is paid after the ask asset is received.
+///
+/// The swap is executed directly through [`internal_swap`] as if **offer_asset** was already sent.
+/// Then the receiver gets [`FlashSwapCallbackMsg`] and must send **offer_asset** back to the pair.
+/// Repayment is verified in the [`FLASH_SWAP_REPLY_ID`] reply. Other pair operations are rejected
+/// and swap hooks are deferred until then.
+///
+/// Flash swaps bypass the Osmosis pool manager, thus the Osmosis taker fee is not charged.
+/// Only the pair fees are applied.
+///
+/// * **offer_asset** proposed asset for swapping.
+///
+/// * **belief_price** is used to calculate the maximum swap spread.
+///
+/// * **max_spread** sets the maximum spread of the swap operation.
+///
+/// * **to** sets the receiver contract. It receives the ask asset and the callback.
+///
+/// * **msg** is forwarded to the receiver in the callback.
+#[allow(clippy::too_many_arguments)]
+pub fn execute_flash_swap(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    offer_asset: Asset,
+    belief_price: Option<Decimal>,
+    max_spread: Option<Decimal>,
+    to: Option<String>,
+    msg: Binary,
+) -> Result<Response, ContractError> {
+    nonpayable(&info)?;
+
+    let config = CONFIG.load(deps.storage)?;
+    let (offer_ind, _) = config
+        .pair_info
+        .asset_infos
+        .iter()
+        .find_position(|asset_info| asset_info.equal(&offer_asset.info))
+        .ok_or_else(|| ContractError::InvalidAsset(offer_asset.info.to_string()))?;
+    let ask_asset_info = config.pair_info.asset_infos[1 ^ offer_ind].clone();
+    let receiver = addr_opt_validate(deps.api, &to)?.unwrap_or_else(|| info.sender.clone());
+
+    let offer_balance_before = deps
+        .querier
+        .query_balance(&env.contract.address, offer_asset.info.to_string())?
+        .amount;
+
+    let mut response = internal_swap(
+        deps.branch(),
+        env,
+        info.sender.clone(),
+        offer_asset.clone(),
+        belief_price,
+        max_spread,
+        Some(receiver.clone()),
+        None,
+        None,
+        None,
+        None,
+        false,
+    )?;
+    let SwapExactAmountInResponseData { token_out_amount } =
+        from_json(response.data.clone().unwrap_or_default())?;
+
+    // Swap hooks must not observe the pool before it is repaid
+    let (swap_hooks, messages) = response
+        .messages
+        .into_iter()
+        .partition(|submsg| submsg.id == SWAP_HOOK_REPLY_ID);
+    response.messages = messages;
+
+    FLASH_SWAP.save(
+        deps.storage,
+        &FlashSwapState {
+            offer_asset: offer_asset.clone(),
+            offer_balance_before,
+            swap_hooks,
+        },
+    )?;
+
+    let callback_msg = wasm_execute(
+        receiver,
+        &FlashSwapCallbackMsg::FlashSwapCallback {
+            sender: info.sender.to_string(),
+            offer_asset,
+            return_asset: ask_asset_info.with_balance(token_out_amount),
+            msg,
+        },
+        vec![],
+    )?;
+
+    Ok(response
+        .add_submessage(SubMsg::reply_on_success(callback_msg, FLASH_SWAP_REPLY_ID))
+        .add_attribute("method", "flash_swap"))
+}
+
+/// Returns pool balances excluding **offer_asset** which was sent to the pair along with the message.
+fn pools_before_offer(
+    deps: Deps,
+    env: &Env,
+    config: &Config,
+    offer_asset: &Asset,
+) -> StdResult<Vec<Asset>> {
+    let mut pools = config
+        .pair_info
+        .query_pools(&deps.querier, &env.contract.address)?;
+    for pool in pools
+        .iter_mut()
+        .filter(|pool| pool.info == offer_asset.info)
+    {
+        pool.amount = pool.amount.checked_sub(offer_asset.amount)?;
+    }
+
+    Ok(pools)
+}
+
 /// Performs an swap operation with the specified parameters.
 ///
 /// * **sender** is the sender of the swap operation.
@@ -667,6 +1505,16 @@ pub fn execute_swap(
 /// * **max_spread** sets the maximum spread of the swap operation.
 ///
 /// * **to** sets the recipient of the swap operation.
+///
+/// * **deadline** is an optional block time (in seconds) after which the swap is rejected.
+///
+/// * **swap_fee** is an optional swap fee passed by the Osmosis pool manager.
+///
+/// * **referral_address** receives **referral_commission** share of the offer amount.
+///
+/// * **offer_in_pool** whether the offer amount is already in the pair balance.
+/// It is false for flash swaps which are repaid after the swap.
+#[allow(clippy::too_many_arguments)]
 pub fn internal_swap(
     deps: DepsMut,
     env: Env,
@@ -675,7 +1523,22 @@ pub fn internal_swap(
     belief_price: Option<Decimal>,
     max_spread: Option<Decimal>,
     to: Option<Addr>,
+    deadline: Option<u64>,
+    swap_fee: Option<Decimal>,
+    referral_address: Option<Addr>,
+    referral_commission: Option<Decimal>,
+    offer_in_pool: bool,
 ) -> Result<Response, ContractError> {
+    // Swaps dispatched before the pool was deactivated or paused must be rejected as well
+    if !is_pool_active(deps.storage)? {
+        return Err(ContractError::PoolIsNotActive {});
+    }
+    if load_pause_flags(deps.storage)?.swap {
+        return Err(ContractError::SwapPaused {});
+    }
+
+    ensure_deadline(&env, deadline)?;
+
     let precisions = Precisions::new(deps.storage)?;
     let offer_asset_prec = precisions.get_precision(&offer_asset.info)?;
     let offer_asset_dec = offer_asset.to_decimal_asset(offer_asset_prec)?;
@@ -691,34 +1554,44 @@ pub fn internal_swap(
     let ask_asset_prec = precisions.get_precision(&pools[ask_ind].info)?;
 
     // Offer pool must have offer amount in it. We need to subtract it from the pool balance
-    pools[offer_ind].amount -= offer_asset_dec.amount;
+    if offer_in_pool {
+        pools[offer_ind].amount -= offer_asset_dec.amount;
+    }
+
+    // Referral commission is deducted from the offer amount and never enters the pool
+    let mut referral_amount = Uint128::zero();
+    if let (Some(_), Some(commission)) = (&referral_address, referral_commission) {
+        referral_amount = offer_asset.amount * commission;
+    }
+    let referral_asset = offer_asset.info.with_balance(referral_amount);
+    let offer_asset = offer_asset
+        .info
+        .with_balance(offer_asset.amount - referral_amount);
+    let offer_asset_dec = offer_asset.to_decimal_asset(offer_asset_prec)?;
 
     before_swap_check(&pools, offer_asset_dec.amount)?;
 
     let mut xs = pools.iter().map(|asset| asset.amount).collect_vec();
 
-    // Get fee info from the factory
-    let fee_info = query_fee_info(
-        &deps.querier,
-        &config.factory_addr,
-        config.pair_info.pair_type.clone(),
-    )?;
-    let mut maker_fee_share = Decimal256::zero();
-    if fee_info.fee_address.is_some() {
-        maker_fee_share = fee_info.maker_fee_rate.into();
-    }
+    // Get fee info from the factory. Maker fee might be overridden for this pair
+    let fee_info = query_pair_fee_info(deps.querier, deps.storage, &config)?;
+    let (maker_fee_share, share_fee_share) = fee_shares(&config, &fee_info);
 
-    let swap_result = compute_swap(
+    let mut swap_result = compute_swap(
         &xs,
         offer_asset_dec.amount,
         ask_ind,
         &config,
         &env,
         maker_fee_share,
-        Decimal256::zero(),
+        share_fee_share,
     )?;
+    apply_fee_ratio(
+        &mut swap_result,
+        external_fee_ratio(&config, &xs, swap_fee, maker_fee_share, share_fee_share)?,
+    );
     xs[offer_ind] += offer_asset_dec.amount;
-    xs[ask_ind] -= swap_result.dy + swap_result.maker_fee;
+    xs[ask_ind] -= swap_result.dy + swap_result.maker_fee + swap_result.share_fee;
 
     let return_amount = swap_result.dy.to_uint(ask_asset_prec)?;
     let spread_amount = swap_result.spread_fee.to_uint(ask_asset_prec)?;
@@ -739,6 +1612,11 @@ pub fn internal_swap(
         && offer_asset_dec.amount >= MIN_TRADE_SIZE
     {
         let last_price = swap_result.calc_last_price(offer_asset_dec.amount, offer_ind);
+        ensure_price_deviation(
+            deps.storage,
+            config.pool_state.price_state.oracle_price,
+            last_price,
+        )?;
 
         // update_price() works only with internal representation
         xs[1] *= config.pool_state.price_state.price_scale;
@@ -763,6 +1641,26 @@ pub fn internal_swap(
         }
     }
 
+    let mut share_amount = Uint128::zero();
+    if let Some(fee_share) = &config.fee_share {
+        share_amount = swap_result.share_fee.to_uint(ask_asset_prec)?;
+        if !share_amount.is_zero() {
+            let fee = pools[ask_ind].info.with_balance(share_amount);
+            messages.push(fee.into_msg(&fee_share.recipient)?);
+        }
+    }
+
+    let mut referral_attrs = vec![];
+    if let Some(referral_address) = referral_address {
+        if !referral_amount.is_zero() {
+            messages.push(referral_asset.into_msg(&referral_address)?);
+        }
+        referral_attrs = vec![
+            attr("referral_address", referral_address),
+            attr("referral_amount", referral_amount),
+        ];
+    }
+
     // Store observation from precommit data
     accumulate_swap_sizes(deps.storage, &env)?;
 
@@ -778,6 +1676,7 @@ pub fn internal_swap(
         PrecommitObservation::save(deps.storage, &env, base_amount, quote_amount)?;
     }
 
+    accumulate_prices(deps.storage, &env, config.pool_state.price_state.last_price)?;
     CONFIG.save(deps.storage, &config)?;
 
     if config.track_asset_balances {
@@ -790,17 +1689,36 @@ pub fn internal_swap(
         BALANCES.save(
             deps.storage,
             &pools[ask_ind].info,
-            &(pools[ask_ind].amount.to_uint(ask_asset_prec)? - return_amount - maker_fee),
+            &(pools[ask_ind].amount.to_uint(ask_asset_prec)?
+                - return_amount
+                - maker_fee
+                - share_amount),
             env.block.height,
         )?;
     }
 
+    let commission_amount = swap_result.total_fee.to_uint(ask_asset_prec)?;
+    let hook_messages = swap_hook_messages(
+        deps.storage,
+        &SwapHookMsg::OnSwap {
+            sender: sender.to_string(),
+            receiver: receiver.to_string(),
+            offer_asset: offer_asset.clone(),
+            return_asset: pools[ask_ind].info.with_balance(return_amount),
+            spread_amount,
+            commission_amount,
+            maker_fee_amount: maker_fee,
+            fee_share_amount: share_amount,
+        },
+    )?;
+
     let response_data = to_json_binary(&SwapExactAmountInResponseData {
         token_out_amount: return_amount,
     })?;
 
     Ok(Response::new()
         .add_messages(messages)
+        .add_submessages(hook_messages)
         .add_attributes(vec![
             attr("action", "swap"),
             attr("sender", sender),
@@ -810,12 +1728,11 @@ pub fn internal_swap(
             attr("offer_amount", offer_asset.amount),
             attr("return_amount", return_amount),
             attr("spread_amount", spread_amount),
-            attr(
-                "commission_amount",
-                swap_result.total_fee.to_uint(ask_asset_prec)?,
-            ),
+            attr("commission_amount", commission_amount),
             attr("maker_fee_amount", maker_fee),
+            attr("fee_share_amount", share_amount),
         ])
+        .add_attributes(referral_attrs)
         .set_data(response_data))
 }
 
@@ -865,33 +1782,31 @@ pub fn update_config(
 
             "enable_asset_balances_tracking"
         }
-        ConcentratedPoolUpdateParams::EnableFeeShare { .. }
-        | ConcentratedPoolUpdateParams::DisableFeeShare => Err(StdError::generic_err(
-            "Fee sharing is not supported in this version",
-        ))?,
-    };
-    CONFIG.save(deps.storage, &config)?;
-
-    Ok(Response::new().add_attribute("action", action))
-}
+        ConcentratedPoolUpdateParams::EnableFeeShare {
+            fee_share_bps,
+            fee_share_address,
+        } => {
+            // Enable fee sharing for this contract.
+            // If fee sharing is already enabled, we overwrite the values currently set.
+
+            // Ensure the fee share isn't 0 and doesn't exceed the maximum allowed value
+            if fee_share_bps == 0 || fee_share_bps > MAX_FEE_SHARE_BPS {
+                return Err(ContractError::FeeShareOutOfBounds {});
+            }
 
-#[cfg_attr(not(feature = "library"), entry_point)]
-pub fn migrate(deps: DepsMut, _env: Env, _msg: Empty) -> Result<Response, ContractError> {
-    let contract_version = cw2::get_contract_version(deps.storage)?;
+            config.fee_share = Some(FeeShareConfig {
+                bps: fee_share_bps,
+                recipient: deps.api.addr_validate(&fee_share_address)?,
+            });
 
-    match contract_version.contract.as_ref() {
-        "astroport-pcl-osmo" => match contract_version.version.as_ref() {
-            "1.0.0" | "1.0.1" | "1.0.2" => {}
-            _ => return Err(ContractError::MigrationError {}),
-        },
-        _ => return Err(ContractError::MigrationError {}),
+            "enable_fee_share"
+        }
+        ConcentratedPoolUpdateParams::DisableFeeShare => {
+            config.fee_share = None;
+            "disable_fee_share"
+        }
     };
+    CONFIG.save(deps.storage, &config)?;
 
-    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
-
-    Ok(Response::new()
-        .add_attribute("previous_contract_name", &contract_version.contract)
-        .add_attribute("previous_contract_version", &contract_version.version)
-        .add_attribute("new_contract_name", CONTRACT_NAME)
-        .add_attribute("new_contract_version", CONTRACT_VERSION))
+    Ok(Response::new().add_attribute("action", action))
 }
@@ -1,10 +1,17 @@
 use astroport::asset::MINIMUM_LIQUIDITY_AMOUNT;
+use astroport::pair::MAX_FEE_SHARE_BPS;
 use astroport_circular_buffer::error::BufferError;
 use astroport_pcl_common::error::PclError;
-use cosmwasm_std::{ConversionOverflowError, OverflowError, StdError};
+use cosmwasm_std::{ConversionOverflowError, Decimal256, OverflowError, StdError, Uint128};
 use cw_utils::PaymentError;
 use thiserror::Error;
 
+use astroport_on_osmosis::pair_pcl::{MAX_DROPPED_OBSERVATIONS, MAX_LP_TOKEN_METADATA_LENGTH};
+
+use crate::contract::{
+    MAX_MAKER_FEE_BPS, MAX_REFERRAL_COMMISSION, MAX_SWAP_HOOKS, OBSERVATIONS_SIZE_LIMITS,
+};
+
 /// This enum describes pair contract errors
 #[derive(Error, Debug, PartialEq)]
 pub enum ContractError {
@@ -29,6 +36,9 @@ pub enum ContractError {
     #[error("Unauthorized")]
     Unauthorized {},
 
+    #[error("Factory {0} is not trusted on this chain")]
+    UntrustedFactory(String),
+
     #[error("You need to provide init params")]
     InitParamsNotFound {},
 
@@ -41,6 +51,79 @@ pub enum ContractError {
     #[error("Pair is not registered in the factory. Only swap and withdraw are allowed")]
     PairIsNotRegistered {},
 
+    #[error("Pool is not active. Only withdraw is allowed")]
+    PoolIsNotActive {},
+
+    #[error("Provide is paused")]
+    ProvidePaused {},
+
+    #[error("Swap is paused")]
+    SwapPaused {},
+
+    #[error("Withdraw is paused")]
+    WithdrawPaused {},
+
+    #[error("Post-trade price deviates from the oracle price by {deviation} which exceeds {max_deviation}")]
+    PriceDeviationTooHigh {
+        deviation: Decimal256,
+        max_deviation: Decimal256,
+    },
+
+    #[error(
+        "Maker fee override exceeds maximum allowed value of {} bps",
+        MAX_MAKER_FEE_BPS
+    )]
+    MakerFeeOverrideOutOfBounds {},
+
+    #[error(
+        "Referral commission exceeds maximum allowed value of {}",
+        MAX_REFERRAL_COMMISSION
+    )]
+    ReferralCommissionTooHigh {},
+
+    #[error("Referral address and referral commission must be specified together")]
+    IncompleteReferral {},
+
+    #[error("Number of swap hooks exceeds the maximum of {}", MAX_SWAP_HOOKS)]
+    TooManySwapHooks {},
+
+    #[error("Swap hook {0} is already registered")]
+    SwapHookAlreadyExists(String),
+
+    #[error("Swap hook {0} is not registered")]
+    SwapHookNotFound(String),
+
+    #[error(
+        "LP token name and symbol must be non-empty and at most {} characters long",
+        MAX_LP_TOKEN_METADATA_LENGTH
+    )]
+    InvalidLpTokenMetadata {},
+
+    #[error("Pair operations are locked until the flash swap is repaid")]
+    FlashSwapInProgress {},
+
+    #[error("Flash swap was not repaid. Expected {expected} but received {received}")]
+    FlashSwapNotRepaid {
+        expected: Uint128,
+        received: Uint128,
+    },
+
+    #[error(
+        "Observations size must be within [{}, {}] range",
+        OBSERVATIONS_SIZE_LIMITS.start(),
+        OBSERVATIONS_SIZE_LIMITS.end()
+    )]
+    InvalidObservationsSize {},
+
+    #[error(
+        "Observations buffer shrink can drop at most {} observations at once",
+        MAX_DROPPED_OBSERVATIONS
+    )]
+    ObservationsShrinkTooLarge {},
+
+    #[error("Max price deviation must not be zero")]
+    InvalidMaxPriceDeviation {},
+
     #[error("Invalid number of assets. This pair supports only {0} assets")]
     InvalidNumberOfAssets(usize),
 
@@ -50,9 +133,42 @@ pub enum ContractError {
     #[error("Asset balances tracking is already enabled")]
     AssetBalancesTrackingIsAlreadyEnabled {},
 
+    #[error(
+        "Fee share is 0 or exceeds maximum allowed value of {} bps",
+        MAX_FEE_SHARE_BPS
+    )]
+    FeeShareOutOfBounds {},
+
+    #[error("Generator address is not set in factory. Cannot auto-stake")]
+    AutoStakeError {},
+
+    #[error("Zap-in min LP amount must be set and must not be zero")]
+    ZapInMinLpNotSet {},
+
+    #[error("Another zap-in is in progress")]
+    ZapInInProgress {},
+
     #[error("Pool id is already set")]
     PoolIdAlreadySet {},
 
+    #[error(
+        "Not enough LP tokens to withdraw requested assets. Need {needed} but {sent} were sent"
+    )]
+    InsufficientWithdrawShare { needed: Uint128, sent: Uint128 },
+
+    #[error("Provide slippage violation. Minted {0} LP tokens but expected at least {1}")]
+    ProvideSlippageViolation(Uint128, Uint128),
+
+    #[error("Transaction deadline {deadline} has expired. Current block time is {block_time}")]
+    DeadlineExpired { deadline: u64, block_time: u64 },
+
+    #[error("Withdraw slippage violation. Received {received} {asset_name} but expected at least {expected}")]
+    WithdrawSlippageViolation {
+        asset_name: String,
+        received: Uint128,
+        expected: Uint128,
+    },
+
     #[error("Failed to migrate contract")]
     MigrationError {},
 }
@@ -1,5 +1,6 @@
 pub mod contract;
 pub mod error;
+pub mod migrate;
 pub mod queries;
 pub mod state;
 pub mod sudo;
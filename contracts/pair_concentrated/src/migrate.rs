@@ -0,0 +1,93 @@
+#[cfg(not(feature = "library"))]
+use cosmwasm_std::entry_point;
+use cosmwasm_std::{DepsMut, Env, Order, Response};
+use cw2::set_contract_version;
+
+use astroport_circular_buffer::BufferManager;
+use astroport_on_osmosis::pair_pcl::MigrateMsg;
+
+use crate::contract::{CONTRACT_NAME, CONTRACT_VERSION, OBSERVATIONS_SIZE_LIMITS};
+use crate::error::ContractError;
+use crate::state::{CONFIG, IS_ACTIVE, OBSERVATIONS, TWAP_POINTS};
+use crate::utils::accumulate_prices;
+
+/// State transform which upgrades the state written by the previous version.
+type StateTransform = fn(&mut DepsMut, &Env) -> Result<(), ContractError>;
+
+/// All released contract versions in ascending order.
+/// Each version has an optional state transform which upgrades the state written by the previous version.
+/// None means the state layout didn't change.
+/// The last entry must always match the current contract version.
+pub(crate) const VERSIONS: &[(&str, Option<StateTransform>)] = &[
+    ("1.0.0", None),
+    ("1.0.1", None),
+    ("1.0.2", None),
+    ("1.0.3", None),
+    ("1.1.0", Some(migrate_to_v110)),
+];
+
+/// Migrates the contract from any older released version.
+/// State transforms are applied in order starting from the version following the stored one.
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn migrate(mut deps: DepsMut, env: Env, _msg: MigrateMsg) -> Result<Response, ContractError> {
+    let contract_version = cw2::get_contract_version(deps.storage)?;
+
+    if contract_version.contract != CONTRACT_NAME {
+        return Err(ContractError::MigrationError {});
+    }
+
+    let stored_ind = VERSIONS
+        .iter()
+        .position(|(version, _)| *version == contract_version.version)
+        .ok_or(ContractError::MigrationError {})?;
+    let current_ind = VERSIONS
+        .iter()
+        .position(|(version, _)| *version == CONTRACT_VERSION)
+        .ok_or(ContractError::MigrationError {})?;
+    if stored_ind >= current_ind {
+        return Err(ContractError::MigrationError {});
+    }
+
+    for transform in VERSIONS[stored_ind + 1..=current_ind]
+        .iter()
+        .filter_map(|(_, transform)| *transform)
+    {
+        transform(&mut deps, &env)?;
+    }
+
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+
+    Ok(Response::new()
+        .add_attribute("previous_contract_name", &contract_version.contract)
+        .add_attribute("previous_contract_version", &contract_version.version)
+        .add_attribute("new_contract_name", CONTRACT_NAME)
+        .add_attribute("new_contract_version", CONTRACT_VERSION))
+}
+
+/// Version 1.1.0 introduced the TWAP price history, the pool activity flag and resizable observations.
+/// * seeds the price history with the last pool price so TWAP queries work right after the migration;
+/// * persists the activity flag. 1.0.x pools were always active;
+/// * checks the observations buffer. 1.0.x used the same buffer layout with a fixed capacity,
+/// thus it is resized in place later as long as its capacity is within the allowed limits.
+fn migrate_to_v110(deps: &mut DepsMut, env: &Env) -> Result<(), ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+
+    let has_twap_points = TWAP_POINTS
+        .keys(deps.storage, None, None, Order::Ascending)
+        .next()
+        .is_some();
+    if !has_twap_points {
+        accumulate_prices(deps.storage, env, config.pool_state.price_state.last_price)?;
+    }
+
+    if !IS_ACTIVE.exists(deps.storage) {
+        IS_ACTIVE.save(deps.storage, &true)?;
+    }
+
+    let buffer = BufferManager::new(deps.storage, OBSERVATIONS)?;
+    if !OBSERVATIONS_SIZE_LIMITS.contains(&buffer.capacity()) {
+        return Err(ContractError::MigrationError {});
+    }
+
+    Ok(())
+}
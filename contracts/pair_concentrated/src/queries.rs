@@ -5,11 +5,11 @@ use astroport::pair::{
     ConfigResponse, PoolResponse, ReverseSimulationResponse, SimulationResponse,
 };
 use astroport::pair_concentrated::ConcentratedPoolConfig;
-use astroport::querier::{query_factory_config, query_fee_info};
+use astroport::querier::query_factory_config;
 use astroport_pcl_common::consts::{OFFER_PERCENT, TWO};
 use astroport_pcl_common::state::Precisions;
 use astroport_pcl_common::utils::{
-    before_swap_check, compute_offer_amount, compute_swap, get_share_in_assets,
+    before_swap_check, check_assets, compute_offer_amount, compute_swap, get_share_in_assets,
 };
 use astroport_pcl_common::{calc_d, get_xcp};
 #[cfg(not(feature = "library"))]
@@ -22,13 +22,23 @@ use itertools::Itertools;
 
 use astroport_on_osmosis::pair_pcl::{
     CalcInAmtGivenOutResponse, CalcOutAmtGivenInResponse, GetSwapFeeResponse, IsActiveResponse,
-    QueryMsg, SpotPriceResponse, TotalPoolLiquidityResponse,
+    PoolStateResponse, QueryMsg, SimulateProvideResponse, SpotPriceResponse,
+    TotalPoolLiquidityResponse,
 };
 
-use crate::contract::LP_TOKEN_PRECISION;
+use crate::contract::{LP_TOKEN_PRECISION, MAX_TWAP_WINDOW};
 use crate::error::ContractError;
-use crate::state::{BALANCES, CONFIG, OBSERVATIONS};
-use crate::utils::{pool_info, query_native_supply, query_pools};
+use crate::state::{
+    BALANCES, CONFIG, MAKER_FEE_OVERRIDE, MAX_PRICE_DEVIATION, OBSERVATIONS, SWAP_HOOKS,
+};
+use crate::utils::{
+    apply_fee_ratio, ask_amount_with_fee_ratio, calc_single_asset_withdraw, calc_withdraw_share,
+    compute_provide, cumulative_price_at, current_fee_rate, ensure_no_flash_swap,
+    ensure_price_deviation, ensure_referral, external_fee_ratio, fee_shares, get_withdraw_amounts,
+    is_pool_active, load_pause_flags, pool_info, prepare_provide_assets, query_native_supply,
+    query_pair_fee_info, query_pools, query_settled_decimal_pools, query_settled_pools,
+    ProvideResult,
+};
 
 /// Exposes all the queries available in the contract.
 ///
@@ -41,34 +51,126 @@ use crate::utils::{pool_info, query_native_supply, query_pools};
 /// * **QueryMsg::Share { amount }** Returns the amount of assets that could be withdrawn from the pool
 /// using a specific amount of LP tokens. The result is returned in a vector that contains objects of type [`Asset`].
 ///
-/// * **QueryMsg::Simulation { offer_asset }** Returns the result of a swap simulation using a [`SimulationResponse`] object.
+/// * **QueryMsg::SimulateProvide { assets, slippage_tolerance }** Returns the amount of LP tokens
+/// which will be minted for providing the specified assets using a [`SimulateProvideResponse`] object.
+///
+/// * **QueryMsg::SimulateImbalancedWithdraw { assets }** Returns the amount of LP tokens which will be burned
+/// to withdraw the specified assets from the pool.
+///
+/// * **QueryMsg::SimulateSingleAssetWithdraw { lp_amount, asset_info }** Returns the amount of the asset
+/// which will be received for burning the specified amount of LP tokens in a single asset withdraw.
 ///
-/// * **QueryMsg::ReverseSimulation { ask_asset }** Returns the result of a reverse swap simulation  using
-/// a [`ReverseSimulationResponse`] object.
+/// * **QueryMsg::Simulation { offer_asset, referral_address, referral_commission }** Returns the result
+/// of a swap simulation using a [`SimulationResponse`] object. Referral commission is deducted from the offer amount.
+///
+/// * **QueryMsg::ReverseSimulation { ask_asset, referral_address, referral_commission }** Returns the result
+/// of a reverse swap simulation using a [`ReverseSimulationResponse`] object.
+/// The offer amount includes the referral commission.
 ///
 /// * **QueryMsg::CumulativePrices {}** Returns information about cumulative prices for the assets in the
 /// pool using a [`CumulativePricesResponse`] object.
 ///
+/// * **QueryMsg::Twap { start_time, end_time }** Returns the time-weighted average price between
+/// two timestamps.
+///
 /// * **QueryMsg::Config {}** Returns the configuration for the pair contract using a [`ConfigResponse`] object.
 ///
+/// * **QueryMsg::PoolState {}** Returns detailed internal pool state using a [`PoolStateResponse`] object.
+///
+/// * **QueryMsg::PauseFlags {}** Returns which pool operations are paused using a [`PauseFlags`] object.
+///
+/// * **QueryMsg::MakerFeeOverride {}** Returns the maker fee (in bps) set for this pair if it overrides
+/// the factory one.
+///
+/// * **QueryMsg::SwapHooks {}** Returns the list of registered swap hook contracts.
+///
+/// * **QueryMsg::MaxPriceDeviation {}** Returns the maximum allowed deviation of the post-trade price
+/// from the oracle price if it is set.
+///
 /// * **QueryMsg::AssetBalanceAt { asset_info, block_height }** Returns the balance of the specified
 /// asset that was in the pool just preceding the moment of the specified block height creation.
+///
+/// All queries except static ones fail while a flash swap is waiting to be repaid as pool reserves
+/// are depleted at that moment.
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
+    // Osmosis pool manager endpoints remain available during flash swaps
+    // as they are calculated for the settled pool state. See `query_settled_pools`.
+    if !matches!(
+        msg,
+        QueryMsg::Pair {}
+            | QueryMsg::PauseFlags {}
+            | QueryMsg::MaxPriceDeviation {}
+            | QueryMsg::MakerFeeOverride {}
+            | QueryMsg::SwapHooks {}
+            | QueryMsg::IsActive {}
+            | QueryMsg::GetSwapFee {}
+            | QueryMsg::GetTotalPoolLiquidity {}
+            | QueryMsg::SpotPrice { .. }
+            | QueryMsg::CalcOutAmtGivenIn { .. }
+            | QueryMsg::CalcInAmtGivenOut { .. }
+    ) {
+        ensure_no_flash_swap(deps.storage).map_err(|err| StdError::generic_err(err.to_string()))?;
+    }
+
     match msg {
         QueryMsg::Pair {} => to_json_binary(&CONFIG.load(deps.storage)?.pair_info),
         QueryMsg::Pool {} => to_json_binary(&query_pool(deps)?),
         QueryMsg::Share { amount } => to_json_binary(
             &query_share(deps, amount).map_err(|err| StdError::generic_err(err.to_string()))?,
         ),
-        QueryMsg::Simulation { offer_asset, .. } => {
-            let (sim_result, _) = query_simulation(deps, env, offer_asset)
+        QueryMsg::SimulateProvide {
+            assets,
+            slippage_tolerance,
+        } => to_json_binary(
+            &query_simulate_provide(deps, env, assets, slippage_tolerance)
+                .map_err(|err| StdError::generic_err(err.to_string()))?,
+        ),
+        QueryMsg::SimulateImbalancedWithdraw { assets } => to_json_binary(
+            &query_imbalanced_withdraw(deps, env, assets)
+                .map_err(|err| StdError::generic_err(err.to_string()))?,
+        ),
+        QueryMsg::SimulateSingleAssetWithdraw {
+            lp_amount,
+            asset_info,
+        } => to_json_binary(
+            &query_single_asset_withdraw(deps, env, lp_amount, asset_info)
+                .map_err(|err| StdError::generic_err(err.to_string()))?,
+        ),
+        QueryMsg::Simulation {
+            mut offer_asset,
+            referral_address,
+            referral_commission,
+            ..
+        } => {
+            ensure_referral(&referral_address, referral_commission)
+                .map_err(|err| StdError::generic_err(err.to_string()))?;
+            // Referral commission is deducted from the offer amount before the swap
+            offer_asset.amount -= offer_asset.amount * referral_commission.unwrap_or_default();
+
+            let (sim_result, _) = query_simulation(deps, env, offer_asset, None)
                 .map_err(|err| StdError::generic_err(format!("{err}")))?;
             to_json_binary(&sim_result)
         }
-        QueryMsg::ReverseSimulation { ask_asset, .. } => {
-            let (sim_result, _) = query_reverse_simulation(deps, env, ask_asset)
+        QueryMsg::ReverseSimulation {
+            ask_asset,
+            referral_address,
+            referral_commission,
+            ..
+        } => {
+            ensure_referral(&referral_address, referral_commission)
+                .map_err(|err| StdError::generic_err(err.to_string()))?;
+
+            let (mut sim_result, _) = query_reverse_simulation(deps, env, ask_asset, None)
                 .map_err(|err| StdError::generic_err(format!("{err}")))?;
+            // Referral commission is deducted from the offer amount before the swap,
+            // thus the offer amount must cover it on top of the amount entering the pool
+            if let Some(referral_commission) = referral_commission {
+                sim_result.offer_amount = sim_result
+                    .offer_amount
+                    .checked_div_ceil(Decimal::one() - referral_commission)
+                    .map_err(|err| StdError::generic_err(err.to_string()))?;
+            }
             to_json_binary(&sim_result)
         }
         QueryMsg::CumulativePrices {} => Err(StdError::generic_err(
@@ -77,8 +179,23 @@ pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
         QueryMsg::Observe { seconds_ago } => {
             to_json_binary(&query_observation(deps, env, OBSERVATIONS, seconds_ago)?)
         }
+        QueryMsg::Twap {
+            start_time,
+            end_time,
+        } => to_json_binary(&query_twap(deps, env, start_time, end_time)?),
         QueryMsg::Config {} => to_json_binary(&query_config(deps, env)?),
+        QueryMsg::PauseFlags {} => to_json_binary(&load_pause_flags(deps.storage)?),
+        QueryMsg::MaxPriceDeviation {} => {
+            to_json_binary(&MAX_PRICE_DEVIATION.may_load(deps.storage)?)
+        }
+        QueryMsg::MakerFeeOverride {} => {
+            to_json_binary(&MAKER_FEE_OVERRIDE.may_load(deps.storage)?)
+        }
+        QueryMsg::SwapHooks {} => {
+            to_json_binary(&SWAP_HOOKS.may_load(deps.storage)?.unwrap_or_default())
+        }
         QueryMsg::LpPrice {} => to_json_binary(&query_lp_price(deps, env)?),
+        QueryMsg::PoolState {} => to_json_binary(&query_pool_state(deps, env)?),
         QueryMsg::ComputeD {} => to_json_binary(&query_compute_d(deps, env)?),
         QueryMsg::AssetBalanceAt {
             asset_info,
@@ -90,28 +207,33 @@ pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
         //
         QueryMsg::GetTotalPoolLiquidity {} => {
             let config = CONFIG.load(deps.storage)?;
-            let total_pool_liquidity = config
-                .pair_info
-                .query_pools(&deps.querier, &config.pair_info.contract_addr)?
-                .iter()
-                .map(Asset::as_coin)
-                .collect::<StdResult<_>>()?;
+            let total_pool_liquidity =
+                query_settled_pools(deps, &config.pair_info.contract_addr, &config)?
+                    .iter()
+                    .map(Asset::as_coin)
+                    .collect::<StdResult<_>>()?;
             to_json_binary(&TotalPoolLiquidityResponse {
                 total_pool_liquidity,
             })
         }
-        QueryMsg::CalcOutAmtGivenIn { token_in, .. } => {
+        QueryMsg::CalcOutAmtGivenIn {
+            token_in, swap_fee, ..
+        } => {
             let offer_asset = native_asset_info(token_in.denom).with_balance(token_in.amount);
-            let (_, return_asset) = query_simulation(deps, env, offer_asset)
+            let (_, return_asset) = query_simulation(deps, env, offer_asset, Some(swap_fee))
                 .map_err(|err| StdError::generic_err(format!("{err}")))?;
 
             to_json_binary(&CalcOutAmtGivenInResponse {
                 token_out: return_asset.as_coin()?,
             })
         }
-        QueryMsg::CalcInAmtGivenOut { token_out, .. } => {
+        QueryMsg::CalcInAmtGivenOut {
+            token_out,
+            swap_fee,
+            ..
+        } => {
             let ask_asset = native_asset_info(token_out.denom).with_balance(token_out.amount);
-            let (_, offer_asset) = query_reverse_simulation(deps, env, ask_asset)
+            let (_, offer_asset) = query_reverse_simulation(deps, env, ask_asset, Some(swap_fee))
                 .map_err(|err| StdError::generic_err(format!("{err}")))?;
 
             to_json_binary(&CalcInAmtGivenOutResponse {
@@ -123,9 +245,7 @@ pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
             base_asset_denom,
         } => {
             let config = CONFIG.load(deps.storage)?;
-            let pools = config
-                .pair_info
-                .query_pools(&deps.querier, &env.contract.address)?;
+            let pools = query_settled_pools(deps, &env.contract.address, &config)?;
             let pool_denoms = pools
                 .iter()
                 .map(|asset| match &asset.info {
@@ -153,18 +273,8 @@ pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
                 ))
             );
 
-            let maker_fee_share = query_fee_info(
-                &deps.querier,
-                &config.factory_addr,
-                config.pair_info.pair_type.clone(),
-            )
-            .map(|fee_info| {
-                if fee_info.fee_address.is_some() {
-                    fee_info.maker_fee_rate
-                } else {
-                    Decimal::zero()
-                }
-            })?;
+            let fee_info = query_pair_fee_info(deps.querier, deps.storage, &config)?;
+            let (maker_fee_share, share_fee_share) = fee_shares(&config, &fee_info);
 
             let get_spot_price = |offer_ind: usize| -> StdResult<Decimal256> {
                 let mut offer_amount = reserves[offer_ind] * OFFER_PERCENT;
@@ -178,8 +288,8 @@ pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
                     1 ^ offer_ind,
                     &config,
                     &env,
-                    maker_fee_share.into(),
-                    Decimal256::zero(),
+                    maker_fee_share,
+                    share_fee_share,
                 )?;
 
                 let price = if pool_denoms[offer_ind] == quote_asset_denom {
@@ -220,12 +330,13 @@ pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
                     .map_err(|err: DecimalRangeExceeded| StdError::generic_err(err.to_string()))?,
             })
         }
-        // Osmosis confirmed we can safely set 0% here.
         // Osmosis team: it was needed due to Osmosis legacy multi hop osmo swap fee reduction where
         // it needs swap fee to pass into the swap interface.
-        QueryMsg::GetSwapFee {} => to_json_binary(&GetSwapFeeResponse::default()),
-        // Astroport never blocks swaps thus we always return true.
-        QueryMsg::IsActive {} => to_json_binary(&IsActiveResponse { is_active: true }),
+        // The response shape is unchanged, thus the legacy logic keeps working with the real fee.
+        QueryMsg::GetSwapFee {} => to_json_binary(&query_swap_fee(deps, env)?),
+        QueryMsg::IsActive {} => to_json_binary(&IsActiveResponse {
+            is_active: is_pool_active(deps.storage)?,
+        }),
     }
 }
 
@@ -275,18 +386,132 @@ fn query_share(deps: Deps, amount: Uint128) -> Result<Vec<Asset>, ContractError>
     Ok(refund_assets)
 }
 
+/// Returns the amount of LP tokens which will be burned to withdraw the specified assets from the pool.
+///
+/// * **assets** defines number of coins a user wants to withdraw per each asset.
+fn query_imbalanced_withdraw(
+    deps: Deps,
+    env: Env,
+    assets: Vec<Asset>,
+) -> Result<Uint128, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    let precisions = Precisions::new(deps.storage)?;
+    let pools = query_pools(
+        deps.querier,
+        &config.pair_info.contract_addr,
+        &config,
+        &precisions,
+    )?;
+    let total_share = query_native_supply(&deps.querier, &config.pair_info.liquidity_token)?;
+
+    let withdraw_amounts = get_withdraw_amounts(&assets, &pools, &precisions)?;
+    let (burn_amount, _) =
+        calc_withdraw_share(&config, &env, &pools, &withdraw_amounts, total_share)?;
+
+    Ok(burn_amount)
+}
+
+/// Returns the amount of LP tokens which will be minted for providing the specified assets.
+/// Shares the computation with provide_liquidity.
+fn query_simulate_provide(
+    deps: Deps,
+    env: Env,
+    assets: Vec<Asset>,
+    slippage_tolerance: Option<Decimal>,
+) -> Result<SimulateProvideResponse, ContractError> {
+    check_assets(deps.api, &assets)?;
+
+    let mut config = CONFIG.load(deps.storage)?;
+    let assets = prepare_provide_assets(&config, assets)?;
+
+    let precisions = Precisions::new(deps.storage)?;
+    let xs = query_pools(deps.querier, &env.contract.address, &config, &precisions)?
+        .into_iter()
+        .map(|pool| pool.amount)
+        .collect_vec();
+
+    // precisions.get_precision() also validates that the asset belongs to the pool
+    let deposits = [
+        Decimal256::with_precision(assets[0].amount, precisions.get_precision(&assets[0].info)?)?,
+        Decimal256::with_precision(assets[1].amount, precisions.get_precision(&assets[1].info)?)?,
+    ];
+
+    let total_share = query_native_supply(&deps.querier, &config.pair_info.liquidity_token)?
+        .to_decimal256(LP_TOKEN_PRECISION)?;
+
+    let ProvideResult {
+        share,
+        fee,
+        slippage,
+        price_updated,
+    } = compute_provide(
+        &mut config,
+        &env,
+        &xs,
+        deposits,
+        total_share,
+        slippage_tolerance,
+    )?;
+    if price_updated {
+        ensure_price_deviation(
+            deps.storage,
+            config.pool_state.price_state.oracle_price,
+            config.pool_state.price_state.last_price,
+        )?;
+    }
+
+    Ok(SimulateProvideResponse {
+        share: share.to_uint(LP_TOKEN_PRECISION)?,
+        fee: fee.to_uint(LP_TOKEN_PRECISION)?,
+        slippage,
+    })
+}
+
+/// Returns the amount of the asset which will be received for burning `lp_amount` LP tokens
+/// in a single asset withdraw.
+fn query_single_asset_withdraw(
+    deps: Deps,
+    env: Env,
+    lp_amount: Uint128,
+    asset_info: AssetInfo,
+) -> Result<Uint128, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    let precisions = Precisions::new(deps.storage)?;
+    let pools = query_pools(
+        deps.querier,
+        &config.pair_info.contract_addr,
+        &config,
+        &precisions,
+    )?;
+    let total_share = query_native_supply(&deps.querier, &config.pair_info.liquidity_token)?;
+
+    let asset_ind = pools
+        .iter()
+        .position(|pool| pool.info == asset_info)
+        .ok_or_else(|| ContractError::InvalidAsset(asset_info.to_string()))?;
+    let (withdraw_amounts, _) =
+        calc_single_asset_withdraw(&config, &env, &pools, asset_ind, lp_amount, total_share)?;
+
+    withdraw_amounts[asset_ind]
+        .to_uint(precisions.get_precision(&asset_info)?)
+        .map_err(Into::into)
+}
+
 /// Returns information about a swap simulation.
+///
+/// * **swap_fee** is an optional fee passed by the Osmosis pool manager. See `external_fee_ratio`.
 pub fn query_simulation(
     deps: Deps,
     env: Env,
     offer_asset: Asset,
+    swap_fee: Option<Decimal>,
 ) -> Result<(SimulationResponse, Asset), ContractError> {
     let config = CONFIG.load(deps.storage)?;
     let precisions = Precisions::new(deps.storage)?;
     let offer_asset_prec = precisions.get_precision(&offer_asset.info)?;
     let offer_asset_dec = offer_asset.to_decimal_asset(offer_asset_prec)?;
 
-    let pools = query_pools(deps.querier, &env.contract.address, &config, &precisions)?;
+    let pools = query_settled_decimal_pools(deps, &env.contract.address, &config, &precisions)?;
 
     let (offer_ind, _) = pools
         .iter()
@@ -299,26 +524,23 @@ pub fn query_simulation(
 
     let xs = pools.iter().map(|asset| asset.amount).collect_vec();
 
-    // Get fee info from the factory
-    let fee_info = query_fee_info(
-        &deps.querier,
-        &config.factory_addr,
-        config.pair_info.pair_type.clone(),
-    )?;
-    let mut maker_fee_share = Decimal256::zero();
-    if fee_info.fee_address.is_some() {
-        maker_fee_share = fee_info.maker_fee_rate.into();
-    }
+    // Get fee info from the factory. Maker fee might be overridden for this pair
+    let fee_info = query_pair_fee_info(deps.querier, deps.storage, &config)?;
+    let (maker_fee_share, share_fee_share) = fee_shares(&config, &fee_info);
 
-    let swap_result = compute_swap(
+    let mut swap_result = compute_swap(
         &xs,
         offer_asset_dec.amount,
         ask_ind,
         &config,
         &env,
         maker_fee_share,
-        Decimal256::zero(),
+        share_fee_share,
     )?;
+    apply_fee_ratio(
+        &mut swap_result,
+        external_fee_ratio(&config, &xs, swap_fee, maker_fee_share, share_fee_share)?,
+    );
 
     let return_amount = swap_result.dy.to_uint(ask_asset_prec)?;
 
@@ -333,17 +555,20 @@ pub fn query_simulation(
 }
 
 /// Returns information about a reverse swap simulation.
+///
+/// * **swap_fee** is an optional fee passed by the Osmosis pool manager. See `external_fee_ratio`.
 pub fn query_reverse_simulation(
     deps: Deps,
     env: Env,
     ask_asset: Asset,
+    swap_fee: Option<Decimal>,
 ) -> Result<(ReverseSimulationResponse, Asset), ContractError> {
     let config = CONFIG.load(deps.storage)?;
     let precisions = Precisions::new(deps.storage)?;
     let ask_asset_prec = precisions.get_precision(&ask_asset.info)?;
     let ask_asset_dec = ask_asset.to_decimal_asset(ask_asset_prec)?;
 
-    let pools = query_pools(deps.querier, &env.contract.address, &config, &precisions)?;
+    let pools = query_settled_decimal_pools(deps, &env.contract.address, &config, &precisions)?;
 
     before_swap_check(&pools, ask_asset_dec.amount)?;
 
@@ -355,8 +580,17 @@ pub fn query_reverse_simulation(
     let offer_asset_prec = precisions.get_precision(&pools[offer_ind].info)?;
 
     let xs = pools.iter().map(|asset| asset.amount).collect_vec();
-    let (offer_amount, spread_amount, commission_amount) =
-        compute_offer_amount(&xs, ask_asset_dec.amount, ask_ind, &config, &env)?;
+    let fee_info = query_pair_fee_info(deps.querier, deps.storage, &config)?;
+    let (maker_fee_share, share_fee_share) = fee_shares(&config, &fee_info);
+    let fee_ratio = external_fee_ratio(&config, &xs, swap_fee, maker_fee_share, share_fee_share)?;
+    let (offer_amount, spread_amount, commission_amount) = compute_offer_amount(
+        &xs,
+        ask_amount_with_fee_ratio(&config, ask_asset_dec.amount, fee_ratio),
+        ask_ind,
+        &config,
+        &env,
+    )?;
+    let commission_amount = commission_amount * fee_ratio;
 
     let offer_amount = offer_amount.to_uint(offer_asset_prec)?;
     Ok((
@@ -392,6 +626,98 @@ pub fn query_lp_price(deps: Deps, env: Env) -> StdResult<Decimal256> {
     }
 }
 
+/// Returns the time-weighted average price between **start_time** and **end_time**.
+pub fn query_twap(
+    deps: Deps,
+    env: Env,
+    start_time: u64,
+    end_time: Option<u64>,
+) -> StdResult<Decimal256> {
+    let now = env.block.time.seconds();
+    let end_time = end_time.unwrap_or(now);
+    ensure!(
+        start_time < end_time && end_time <= now,
+        StdError::generic_err(format!(
+            "Invalid time range: {start_time}..{end_time}. Current time: {now}"
+        ))
+    );
+    ensure!(
+        start_time >= now.saturating_sub(MAX_TWAP_WINDOW),
+        StdError::generic_err(format!(
+            "TWAP window must not start earlier than {MAX_TWAP_WINDOW} seconds ago"
+        ))
+    );
+
+    let cumulative_at = |timestamp: u64| {
+        cumulative_price_at(deps.storage, timestamp)?.ok_or_else(|| {
+            StdError::generic_err(format!("Price history doesn't cover timestamp {timestamp}"))
+        })
+    };
+    let start_cumulative = cumulative_at(start_time)?;
+    let end_cumulative = cumulative_at(end_time)?;
+
+    Ok((end_cumulative - start_cumulative) / Decimal256::from_ratio(end_time - start_time, 1u8))
+}
+
+/// Returns detailed internal pool state.
+pub fn query_pool_state(deps: Deps, env: Env) -> StdResult<PoolStateResponse> {
+    let config = CONFIG.load(deps.storage)?;
+    let precisions = Precisions::new(deps.storage)?;
+
+    let reserves = query_pools(deps.querier, &env.contract.address, &config, &precisions)
+        .map_err(|err| StdError::generic_err(err.to_string()))?;
+
+    let pool_state = &config.pool_state;
+    let price_state = &pool_state.price_state;
+    let amp_gamma = pool_state.get_amp_gamma(&env);
+
+    let d = if reserves.iter().any(|asset| asset.amount.is_zero()) {
+        Decimal256::zero()
+    } else {
+        let mut xs = reserves.iter().map(|asset| asset.amount).collect_vec();
+        xs[1] *= price_state.price_scale;
+        calc_d(&xs, &amp_gamma)?
+    };
+
+    Ok(PoolStateResponse {
+        price_scale: price_state.price_scale,
+        oracle_price: price_state.oracle_price,
+        last_price: price_state.last_price,
+        last_price_update: price_state.last_price_update,
+        xcp_profit: price_state.xcp_profit,
+        xcp_profit_real: price_state.xcp_profit_real,
+        amp: amp_gamma.amp,
+        gamma: amp_gamma.gamma,
+        initial_amp: pool_state.initial.amp,
+        initial_gamma: pool_state.initial.gamma,
+        initial_time: pool_state.initial_time,
+        future_amp: pool_state.future.amp,
+        future_gamma: pool_state.future.gamma,
+        future_time: pool_state.future_time,
+        d,
+        reserves,
+    })
+}
+
+/// Returns the current effective PCL fee. The fee is dynamic and varies between `mid_fee`
+/// and `out_fee` depending on how balanced the pool is. Returns `mid_fee` if the pool is empty.
+/// Osmosis queries it in the middle of swaps dispatched by the pair, thus the fee is calculated
+/// for the settled pool state. See `query_settled_pools`.
+pub fn query_swap_fee(deps: Deps, env: Env) -> StdResult<GetSwapFeeResponse> {
+    let config = CONFIG.load(deps.storage)?;
+    let precisions = Precisions::new(deps.storage)?;
+
+    let xs = query_settled_decimal_pools(deps, &env.contract.address, &config, &precisions)
+        .map_err(|err| StdError::generic_err(err.to_string()))?
+        .into_iter()
+        .map(|asset| asset.amount)
+        .collect_vec();
+
+    Ok(GetSwapFeeResponse {
+        swap_fee: current_fee_rate(&config, &xs)?,
+    })
+}
+
 /// Returns the pair contract configuration.
 pub fn query_config(deps: Deps, env: Env) -> StdResult<ConfigResponse> {
     let config = CONFIG.load(deps.storage)?;
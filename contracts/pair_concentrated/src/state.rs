@@ -1,11 +1,14 @@
-use astroport::asset::AssetInfo;
+use std::ops::Range;
+
+use astroport::asset::{Asset, AssetInfo};
 use astroport::common::OwnershipProposal;
 use astroport::observation::Observation;
 use astroport_circular_buffer::CircularBuffer;
+use astroport_on_osmosis::pair_pcl::PauseFlags;
 use astroport_pcl_common::state::Config;
 use cosmwasm_schema::cw_serde;
-use cosmwasm_std::{Addr, Decimal, Uint128};
-use cw_storage_plus::{Item, SnapshotMap};
+use cosmwasm_std::{Addr, Coin, Decimal, Decimal256, Empty, Storage, SubMsg, Uint128};
+use cw_storage_plus::{Item, Map, SnapshotMap};
 
 /// Astroport swap parameters
 #[cw_serde]
@@ -14,24 +17,124 @@ pub struct SwapParams {
     pub max_spread: Option<Decimal>,
     pub sender: Addr,
     pub to: Option<Addr>,
+    /// Block time in seconds after which the swap is rejected
+    pub deadline: Option<u64>,
+    /// The address which receives the referral commission
+    pub referral_address: Option<Addr>,
+    /// The share of the offer amount sent to the referral address
+    pub referral_commission: Option<Decimal>,
+    /// Pool balances before the offer asset was sent to the pair
+    pub pools_before: Vec<Asset>,
 }
 
+/// Contracts which receive a message after each swap
+pub const SWAP_HOOKS: Item<Vec<Addr>> = Item::new("swap_hooks");
+
 /// Structure stores Astroport swap parameters in the contract state to pass these params to the
 /// sudo call where real swap happens.
 pub const SWAP_PARAMS: Item<SwapParams> = Item::new("swap_params");
 
+/// Unused offer amount of an exact out swap dispatched by the pair
+#[cw_serde]
+pub struct ExactOutRefund {
+    /// The receiver of unused offer tokens
+    pub receiver: Addr,
+    /// Offer asset balance which must stay in the pool after the swap
+    pub pool_balance: Asset,
+}
+
+/// Stores exact out refund parameters between the sudo call and the reply.
+/// Osmosis pool manager charges the taker fee from the pair after the swap,
+/// thus the refund is calculated from the pair balance in the reply.
+pub const EXACT_OUT_REFUND: Item<ExactOutRefund> = Item::new("exact_out_refund");
+
+/// Zap-in parameters which are used in the reply after all swaps are executed
+#[cw_serde]
+pub struct ZapInParams {
+    /// The sender of the zap-in message
+    pub sender: Addr,
+    /// The receiver of LP tokens
+    pub receiver: Option<String>,
+    pub slippage_tolerance: Option<Decimal>,
+    pub min_lp_to_receive: Uint128,
+    /// Pool asset balances before zap-in swaps
+    pub balances_before: Vec<Coin>,
+}
+
+/// Stores zap-in parameters between the execute call and the reply
+pub const ZAP_IN_PARAMS: Item<ZapInParams> = Item::new("zap_in_params");
+
+/// Flash swap parameters which are verified in the reply after the receiver callback
+#[cw_serde]
+pub struct FlashSwapState {
+    /// The asset which must be sent back to the pair
+    pub offer_asset: Asset,
+    /// Pair balance of the offer asset before the flash swap
+    pub offer_balance_before: Uint128,
+    /// Swap hook messages which are dispatched after the flash swap is repaid
+    pub swap_hooks: Vec<SubMsg>,
+}
+
+/// Stores flash swap parameters between the execute call and the reply.
+/// All pair operations are locked while it is set.
+pub const FLASH_SWAP: Item<FlashSwapState> = Item::new("flash_swap");
+
 /// Stores pool id which the pair contract belongs to.
 pub const POOL_ID: Item<u64> = Item::new("pool_id");
 
+/// Stores whether the pool is active. Osmosis governance is able to deactivate the pool via sudo call.
+/// Inactive pool rejects all swaps while liquidity withdrawal is still possible.
+/// If not set, the pool is considered active.
+pub const IS_ACTIVE: Item<bool> = Item::new("is_active");
+
+/// Stores which pool operations are paused by the pair owner.
+/// If not set, nothing is paused.
+pub const PAUSE_FLAGS: Item<PauseFlags> = Item::new("pause_flags");
+
+/// Stores the maker fee (in bps) set by the factory owner for this pair.
+/// It overrides the maker fee configured in the factory for the whole pair type.
+pub const MAKER_FEE_OVERRIDE: Item<u16> = Item::new("maker_fee_override");
+
+/// Stores the maximum allowed relative deviation of the post-trade price from the EMA oracle price.
+/// If not set, the check is disabled.
+pub const MAX_PRICE_DEVIATION: Item<Decimal> = Item::new("max_price_deviation");
+
 /// Stores pool parameters and state.
 pub const CONFIG: Item<Config> = Item::new("config");
 
 /// Stores the latest contract ownership transfer proposal
 pub const OWNERSHIP_PROPOSAL: Item<OwnershipProposal> = Item::new("ownership_proposal");
 
+/// Storage key of the observation buffer state
+const OBSERVATIONS_STATE_KEY: &str = "observations_state";
+/// Storage namespace of the observation buffer data
+const OBSERVATIONS_BUFFER_KEY: &str = "observations_buffer";
+
 /// Circular buffer to store trade size observations
 pub const OBSERVATIONS: CircularBuffer<Observation> =
-    CircularBuffer::new("observations_state", "observations_buffer");
+    CircularBuffer::new(OBSERVATIONS_STATE_KEY, OBSERVATIONS_BUFFER_KEY);
+
+/// Removes the observation buffer state and observations stored in **slots**.
+/// The circular buffer can't change its capacity, thus it must be initialized again to be resized.
+pub(crate) fn clear_observations(storage: &mut dyn Storage, slots: Range<u32>) {
+    let observations = Map::<u32, Observation>::new(OBSERVATIONS_BUFFER_KEY);
+    for slot in slots {
+        observations.remove(storage, slot);
+    }
+    Item::<Empty>::new(OBSERVATIONS_STATE_KEY).remove(storage);
+}
+
+/// Time-weighted cumulative price recorded at a specific timestamp
+#[cw_serde]
+pub struct TwapPoint {
+    /// Sum of prices weighted by the number of seconds they were in effect
+    pub cumulative_price: Decimal256,
+    /// Price which is in effect starting from this timestamp
+    pub price: Decimal256,
+}
+
+/// Stores cumulative price points keyed by block time (in seconds)
+pub const TWAP_POINTS: Map<u64, TwapPoint> = Map::new("twap_points");
 
 /// Stores asset balances to query them later at any block height
 pub const BALANCES: SnapshotMap<&AssetInfo, Uint128> = SnapshotMap::new(
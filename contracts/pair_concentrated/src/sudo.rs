@@ -2,22 +2,24 @@ use astroport::asset::{native_asset_info, AssetInfo, AssetInfoExt};
 use astroport::cosmwasm_ext::{DecimalToInteger, IntegerToDecimal};
 use astroport::observation::PrecommitObservation;
 use astroport::pair::MIN_TRADE_SIZE;
-use astroport::querier::query_fee_info;
 use astroport_pcl_common::state::Precisions;
 use astroport_pcl_common::utils::{compute_offer_amount, compute_swap};
 #[cfg(not(feature = "library"))]
 use cosmwasm_std::entry_point;
 use cosmwasm_std::{
-    attr, ensure, to_json_binary, Coin, Decimal, Decimal256, DepsMut, Env, Response, StdError,
-    Uint128,
+    attr, ensure, to_json_binary, Addr, Coin, Decimal, DepsMut, Env, Response, StdError, Uint128,
 };
 
-use astroport_on_osmosis::pair_pcl::{SudoMessage, SwapExactAmountOutResponseData};
+use astroport_on_osmosis::pair_pcl::{SudoMessage, SwapExactAmountOutResponseData, SwapHookMsg};
 
 use crate::contract::{internal_swap, LP_TOKEN_PRECISION};
 use crate::error::ContractError;
-use crate::state::{BALANCES, CONFIG, SWAP_PARAMS};
-use crate::utils::{accumulate_swap_sizes, query_native_supply};
+use crate::state::{ExactOutRefund, BALANCES, CONFIG, EXACT_OUT_REFUND, IS_ACTIVE, SWAP_PARAMS};
+use crate::utils::{
+    accumulate_prices, accumulate_swap_sizes, apply_fee_ratio, ask_amount_with_fee_ratio,
+    ensure_no_flash_swap, ensure_price_deviation, external_fee_ratio, fee_shares, is_pool_active,
+    load_pause_flags, query_native_supply, query_pair_fee_info, swap_hook_messages,
+};
 
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn sudo(deps: DepsMut, env: Env, msg: SudoMessage) -> Result<Response, ContractError> {
@@ -26,8 +28,11 @@ pub fn sudo(deps: DepsMut, env: Env, msg: SudoMessage) -> Result<Response, Contr
             sender,
             token_in,
             token_out_min_amount,
+            swap_fee,
             ..
         } => {
+            ensure_no_flash_swap(deps.storage)?;
+
             let mut sender = deps.api.addr_validate(&sender)?;
             let offer_asset = native_asset_info(token_in.denom).with_balance(token_in.amount);
 
@@ -36,48 +41,123 @@ pub fn sudo(deps: DepsMut, env: Env, msg: SudoMessage) -> Result<Response, Contr
             // this logic with our additional default 0.02% slippage tolerance.
             let mut max_spread = Some(Decimal::zero());
             let mut to = None;
+            let mut deadline = None;
+            let mut referral_address = None;
+            let mut referral_commission = None;
+            let mut swap_fee = Some(swap_fee);
             // If swap was dispatched from Astroport pair it must have SWAP_PARAMS in the storage
             if let Some(swap_params) = SWAP_PARAMS.may_load(deps.storage)? {
                 belief_price = swap_params.belief_price;
                 max_spread = swap_params.max_spread;
                 sender = swap_params.sender;
                 to = swap_params.to;
+                deadline = swap_params.deadline;
+                referral_address = swap_params.referral_address;
+                referral_commission = swap_params.referral_commission;
+                // Osmosis queried the swap fee after the offer had been sent to the pair,
+                // thus it doesn't match the pre-trade pool state. The pair charges its own fee.
+                swap_fee = None;
 
                 // Remove params so they won't be used if SwapExactAmountIn is called directly from the DEX module
                 SWAP_PARAMS.remove(deps.storage);
             }
 
-            internal_swap(deps, env, sender, offer_asset, belief_price, max_spread, to)
-                .map(|res| res.add_attribute("method", "swap_exact_amount_in"))
+            internal_swap(
+                deps,
+                env,
+                sender,
+                offer_asset,
+                belief_price,
+                max_spread,
+                to,
+                deadline,
+                swap_fee,
+                referral_address,
+                referral_commission,
+                true,
+            )
+            .map(|res| res.add_attribute("method", "swap_exact_amount_in"))
         }
         SudoMessage::SwapExactAmountOut {
             sender,
             token_in_denom,
             token_in_max_amount,
             token_out,
-            ..
-        } => swap_exact_amount_out(
-            deps,
-            env,
-            sender,
-            token_in_denom,
-            token_in_max_amount,
-            token_out,
-        ),
-        SudoMessage::SetActive { .. } => unimplemented!("SetActive is not implemented"),
+            swap_fee,
+        } => {
+            ensure_no_flash_swap(deps.storage)?;
+
+            let mut sender = deps.api.addr_validate(&sender)?;
+            let mut receiver = sender.clone();
+            let mut refund_excess = false;
+            let mut swap_fee = Some(swap_fee);
+            // If swap was dispatched from Astroport pair it must have SWAP_PARAMS in the storage
+            if let Some(swap_params) = SWAP_PARAMS.may_load(deps.storage)? {
+                receiver = swap_params.to.unwrap_or_else(|| swap_params.sender.clone());
+                sender = swap_params.sender;
+                // Osmosis returns excess tokens to the pair contract itself
+                // thus we need to refund them to the original sender
+                refund_excess = true;
+                // Osmosis queried the swap fee after the offer had been sent to the pair,
+                // thus it doesn't match the pre-trade pool state. The pair charges its own fee.
+                swap_fee = None;
+
+                // Remove params so they won't be used if SwapExactAmountOut is called directly from the DEX module
+                SWAP_PARAMS.remove(deps.storage);
+            }
+
+            swap_exact_amount_out(
+                deps,
+                env,
+                sender,
+                receiver,
+                refund_excess,
+                token_in_denom,
+                token_in_max_amount,
+                token_out,
+                swap_fee,
+            )
+        }
+        SudoMessage::SetActive { is_active } => {
+            IS_ACTIVE.save(deps.storage, &is_active)?;
+
+            Ok(Response::new().add_attributes([
+                attr("method", "set_active"),
+                attr("is_active", is_active.to_string()),
+            ]))
+        }
     }
 }
 
 /// Osmosis cosmwasmpool module guarantees that token_in_max_amount is always sent to the contract
 /// https://github.com/osmosis-labs/osmosis/blob/294302637a47ffec5cafc0c1953e88a54390b20e/x/cosmwasmpool/pool_module.go#L288-L293
+///
+/// * **receiver** receives the ask asset.
+///
+/// * **refund_excess** determines whether unused offer amount must be sent back to the sender.
+/// It is only needed when the swap was dispatched by the pair itself. The pair pays the Osmosis taker fee
+/// in that case, thus the refund is sent in the reply once the fee is charged.
+///
+/// * **swap_fee** is the swap fee passed by the Osmosis pool manager. See `external_fee_ratio`.
+#[allow(clippy::too_many_arguments)]
 fn swap_exact_amount_out(
     deps: DepsMut,
     env: Env,
-    sender: String,
+    sender: Addr,
+    receiver: Addr,
+    refund_excess: bool,
     token_in_denom: String,
     token_in_max_amount: Uint128,
     token_out: Coin,
+    swap_fee: Option<Decimal>,
 ) -> Result<Response, ContractError> {
+    if !is_pool_active(deps.storage)? {
+        return Err(ContractError::PoolIsNotActive {});
+    }
+    if load_pause_flags(deps.storage)?.swap {
+        return Err(ContractError::SwapPaused {});
+    }
+
     if token_in_denom == token_out.denom {
         return Err(StdError::generic_err(format!(
             "Invalid swap: {token_in_denom} to {token_in_denom}"
@@ -128,7 +208,18 @@ fn swap_exact_amount_out(
         })
         .collect::<Result<Vec<_>, ContractError>>()?;
 
-    let (offer_amount, ..) = compute_offer_amount(&xs, ask_amount_dec, ask_ind, &config, &env)?;
+    // Get fee info from the factory. Maker fee might be overridden for this pair
+    let fee_info = query_pair_fee_info(deps.querier, deps.storage, &config)?;
+    let (maker_fee_share, share_fee_share) = fee_shares(&config, &fee_info);
+
+    let fee_ratio = external_fee_ratio(&config, &xs, swap_fee, maker_fee_share, share_fee_share)?;
+    let (offer_amount, ..) = compute_offer_amount(
+        &xs,
+        ask_amount_with_fee_ratio(&config, ask_amount_dec, fee_ratio),
+        ask_ind,
+        &config,
+        &env,
+    )?;
 
     let offer_amount = offer_amount.to_uint(offer_asset_prec)?;
     ensure!(
@@ -145,30 +236,27 @@ fn swap_exact_amount_out(
     let offer_asset_prec = precisions.get_precision(&offer_asset.info)?;
     let offer_asset_dec = offer_asset.to_decimal_asset(offer_asset_prec)?;
 
-    // Get fee info from the factory
-    let fee_info = query_fee_info(
-        &deps.querier,
-        &config.factory_addr,
-        config.pair_info.pair_type.clone(),
-    )?;
-    let mut maker_fee_share = Decimal256::zero();
-    if fee_info.fee_address.is_some() {
-        maker_fee_share = fee_info.maker_fee_rate.into();
-    }
-
-    let swap_result = compute_swap(
+    let mut swap_result = compute_swap(
         &xs,
         offer_asset_dec.amount,
         ask_ind,
         &config,
         &env,
         maker_fee_share,
-        Decimal256::zero(),
+        share_fee_share,
     )?;
+    apply_fee_ratio(&mut swap_result, fee_ratio);
     xs[offer_ind] += offer_asset_dec.amount;
-    xs[ask_ind] -= swap_result.dy + swap_result.maker_fee;
+    xs[ask_ind] -= swap_result.dy + swap_result.maker_fee + swap_result.share_fee;
 
     let return_amount = swap_result.dy.to_uint(ask_asset_prec)?;
+    ensure!(
+        return_amount >= token_out.amount,
+        StdError::generic_err(format!(
+            "Swap returns {return_amount} which is less than token out amount {}",
+            token_out.amount
+        ))
+    );
     let spread_amount = swap_result.spread_fee.to_uint(ask_asset_prec)?;
 
     let total_share = query_native_supply(&deps.querier, &config.pair_info.liquidity_token)?
@@ -180,6 +268,11 @@ fn swap_exact_amount_out(
         && offer_asset_dec.amount >= MIN_TRADE_SIZE
     {
         let last_price = swap_result.calc_last_price(offer_asset_dec.amount, offer_ind);
+        ensure_price_deviation(
+            deps.storage,
+            config.pool_state.price_state.oracle_price,
+            last_price,
+        )?;
 
         // update_price() works only with internal representation
         xs[1] *= config.pool_state.price_state.price_scale;
@@ -191,7 +284,20 @@ fn swap_exact_amount_out(
     let mut messages = vec![pools[ask_ind]
         .info
         .with_balance(return_amount)
-        .into_msg(&sender)?];
+        .into_msg(&receiver)?];
+
+    // The taker fee is charged from the pair after this call, thus the refund is sent in the reply
+    if refund_excess {
+        EXACT_OUT_REFUND.save(
+            deps.storage,
+            &ExactOutRefund {
+                receiver: sender.clone(),
+                pool_balance: offer_asset
+                    .info
+                    .with_balance(pools[offer_ind].amount + offer_asset.amount),
+            },
+        )?;
+    }
 
     let mut maker_fee = Uint128::zero();
     if let Some(fee_address) = fee_info.fee_address {
@@ -202,6 +308,15 @@ fn swap_exact_amount_out(
         }
     }
 
+    let mut share_amount = Uint128::zero();
+    if let Some(fee_share) = &config.fee_share {
+        share_amount = swap_result.share_fee.to_uint(ask_asset_prec)?;
+        if !share_amount.is_zero() {
+            let fee = pools[ask_ind].info.with_balance(share_amount);
+            messages.push(fee.into_msg(&fee_share.recipient)?);
+        }
+    }
+
     // Store observation from precommit data
     accumulate_swap_sizes(deps.storage, &env)?;
 
@@ -217,6 +332,7 @@ fn swap_exact_amount_out(
         PrecommitObservation::save(deps.storage, &env, base_amount, quote_amount)?;
     }
 
+    accumulate_prices(deps.storage, &env, config.pool_state.price_state.last_price)?;
     CONFIG.save(deps.storage, &config)?;
 
     if config.track_asset_balances {
@@ -229,30 +345,45 @@ fn swap_exact_amount_out(
         BALANCES.save(
             deps.storage,
             &pools[ask_ind].info,
-            &(pools[ask_ind].amount - return_amount - maker_fee),
+            &(pools[ask_ind].amount - return_amount - maker_fee - share_amount),
             env.block.height,
         )?;
     }
 
+    let commission_amount = swap_result.total_fee.to_uint(ask_asset_prec)?;
+    let hook_messages = swap_hook_messages(
+        deps.storage,
+        &SwapHookMsg::OnSwap {
+            sender: sender.to_string(),
+            receiver: receiver.to_string(),
+            offer_asset: offer_asset.clone(),
+            return_asset: ask_asset.info.with_balance(return_amount),
+            spread_amount,
+            commission_amount,
+            maker_fee_amount: maker_fee,
+            fee_share_amount: share_amount,
+        },
+    )?;
+
     let response_data = to_json_binary(&SwapExactAmountOutResponseData {
         token_in_amount: offer_asset.amount,
     })?;
 
     Ok(Response::new()
         .add_messages(messages)
+        .add_submessages(hook_messages)
         .add_attributes([
             attr("method", "swap_exact_amount_out"),
             attr("sender", sender),
+            attr("receiver", receiver),
             attr("offer_asset", offer_asset.info.to_string()),
             attr("ask_asset", ask_asset.info.to_string()),
             attr("offer_amount", offer_asset.amount),
             attr("return_amount", return_amount),
             attr("spread_amount", spread_amount),
-            attr(
-                "commission_amount",
-                swap_result.total_fee.to_uint(ask_asset_prec)?,
-            ),
+            attr("commission_amount", commission_amount),
             attr("maker_fee_amount", maker_fee),
+            attr("fee_share_amount", share_amount),
         ])
         .set_data(response_data))
 }
@@ -1,14 +1,40 @@
-use astroport::asset::{Asset, DecimalAsset};
+use astroport::asset::{Asset, AssetInfo, Decimal256Ext, DecimalAsset, MINIMUM_LIQUIDITY_AMOUNT};
+use astroport::cosmwasm_ext::{AbsDiff, DecimalToInteger, IntegerToDecimal};
+use astroport::incentives::ExecuteMsg as IncentivesExecuteMsg;
 use astroport::observation::{
     safe_sma_buffer_not_full, safe_sma_calculation, Observation, PrecommitObservation,
 };
+use astroport::pair::MIN_TRADE_SIZE;
+use astroport::querier::{query_factory_config, query_fee_info, FeeInfo};
 use astroport_circular_buffer::error::BufferResult;
 use astroport_circular_buffer::BufferManager;
 use astroport_pcl_common::state::{Config, Precisions};
-use cosmwasm_std::{Addr, Decimal, Env, QuerierWrapper, StdResult, Storage, Uint128};
+use astroport_pcl_common::utils::{assert_slippage_tolerance, calc_provide_fee, SwapResult};
+use astroport_pcl_common::{calc_d, calc_y, get_xcp};
+use cosmwasm_std::{
+    coin, ensure, wasm_execute, Addr, CosmosMsg, Decimal, Decimal256, DecimalRangeExceeded, Deps,
+    Env, Order, QuerierWrapper, StdError, StdResult, Storage, SubMsg, Uint128,
+};
+use cw_storage_plus::Bound;
+use itertools::Itertools;
+use osmosis_std::types::cosmos::bank::v1beta1::{DenomUnit, Metadata};
+use osmosis_std::types::osmosis::tokenfactory::v1beta1::{MsgMint, MsgSetDenomMetadata};
+
+use astroport_on_osmosis::pair_pcl::{
+    LpTokenMetadata, PauseFlags, SwapHookMsg, MAX_DROPPED_OBSERVATIONS,
+    MAX_LP_TOKEN_METADATA_LENGTH,
+};
 
+use crate::contract::{
+    LP_TOKEN_PRECISION, MAX_DENOM_LENGTH, MAX_PRUNED_TWAP_POINTS, MAX_REFERRAL_COMMISSION,
+    MAX_TWAP_WINDOW, OBSERVATIONS_SIZE_LIMITS, SWAP_HOOK_GAS_LIMIT, SWAP_HOOK_REPLY_ID,
+    TRUSTED_FACTORIES,
+};
 use crate::error::ContractError;
-use crate::state::OBSERVATIONS;
+use crate::state::{
+    clear_observations, FlashSwapState, TwapPoint, FLASH_SWAP, IS_ACTIVE, MAKER_FEE_OVERRIDE,
+    MAX_PRICE_DEVIATION, OBSERVATIONS, PAUSE_FLAGS, SWAP_HOOKS, SWAP_PARAMS, TWAP_POINTS,
+};
 
 /// Returns the total amount of assets in the pool as well as the total amount of LP tokens currently minted.
 pub(crate) fn pool_info(
@@ -42,6 +68,187 @@ pub(crate) fn query_pools(
         .collect()
 }
 
+/// Returns pool balances which match the current pair state.
+/// While a swap dispatched by the pair is in progress, the offer asset is already in the pair
+/// but the swap isn't executed yet, thus balances saved before the swap are used.
+/// While a flash swap is in progress, the swap is already executed but the offer asset isn't repaid yet,
+/// thus the owed offer amount is added to the pair balances.
+pub(crate) fn query_settled_pools(
+    deps: Deps,
+    addr: &Addr,
+    config: &Config,
+) -> StdResult<Vec<Asset>> {
+    if let Some(swap_params) = SWAP_PARAMS.may_load(deps.storage)? {
+        return Ok(swap_params.pools_before);
+    }
+
+    let mut pools = config.pair_info.query_pools(&deps.querier, addr)?;
+    if let Some(FlashSwapState { offer_asset, .. }) = FLASH_SWAP.may_load(deps.storage)? {
+        for pool in pools
+            .iter_mut()
+            .filter(|pool| pool.info == offer_asset.info)
+        {
+            pool.amount += offer_asset.amount;
+        }
+    }
+
+    Ok(pools)
+}
+
+/// Same as [`query_settled_pools`] but returns balances in the internal decimal representation.
+pub(crate) fn query_settled_decimal_pools(
+    deps: Deps,
+    addr: &Addr,
+    config: &Config,
+    precisions: &Precisions,
+) -> Result<Vec<DecimalAsset>, ContractError> {
+    query_settled_pools(deps, addr, config)?
+        .into_iter()
+        .map(|asset| {
+            asset
+                .to_decimal_asset(precisions.get_precision(&asset.info)?)
+                .map_err(Into::into)
+        })
+        .collect()
+}
+
+/// Converts assets requested for imbalanced withdraw into the internal decimal representation.
+/// Resulting amounts are sorted in the same order as pool assets. Omitted assets are considered as zero.
+pub(crate) fn get_withdraw_amounts(
+    assets: &[Asset],
+    pools: &[DecimalAsset],
+    precisions: &Precisions,
+) -> Result<Vec<Decimal256>, ContractError> {
+    if assets.len() > pools.len() {
+        return Err(ContractError::InvalidNumberOfAssets(pools.len()));
+    }
+    ensure!(
+        assets.iter().map(|asset| &asset.info).all_unique(),
+        StdError::generic_err("Doubling assets in withdraw request")
+    );
+
+    let mut amounts = vec![Decimal256::zero(); pools.len()];
+    for asset in assets {
+        let (ind, _) = pools
+            .iter()
+            .find_position(|pool| pool.info.equal(&asset.info))
+            .ok_or_else(|| ContractError::InvalidAsset(asset.info.to_string()))?;
+        amounts[ind] =
+            Decimal256::with_precision(asset.amount, precisions.get_precision(&asset.info)?)?;
+    }
+
+    ensure!(
+        amounts.iter().any(|amount| !amount.is_zero()),
+        StdError::generic_err("Nothing to withdraw")
+    );
+
+    Ok(amounts)
+}
+
+/// Calculates the amount of LP tokens which must be burned to withdraw exact `withdraw_amounts` from the pool.
+/// Imbalanced withdraw is charged with the same fee as imbalanced provide (see [`calc_provide_fee`]):
+///
+/// `burn_amount = total_share * (old_d - new_d) / old_d * (1 + fee) + 1`
+///
+/// where `old_d` and `new_d` are pool invariants before and after the withdraw. The share of D removed from the pool
+/// is the share of LP tokens a balanced withdraw would burn. The fee makes imbalanced withdraw more expensive, so
+/// withdrawing assets and providing them back never mints more LP tokens than were burned.
+/// One more token is burned to round the result up in favor of the pool.
+/// Returns the LP amount to burn and new pool balances in the internal representation.
+pub(crate) fn calc_withdraw_share(
+    config: &Config,
+    env: &Env,
+    pools: &[DecimalAsset],
+    withdraw_amounts: &[Decimal256],
+    total_share: Uint128,
+) -> Result<(Uint128, Vec<Decimal256>), ContractError> {
+    let price_scale = config.pool_state.price_state.price_scale;
+
+    let mut old_xp = pools.iter().map(|pool| pool.amount).collect_vec();
+    let mut new_xp = old_xp
+        .iter()
+        .zip(withdraw_amounts)
+        .map(|(pool, amount)| pool.checked_sub(*amount))
+        .collect::<Result<Vec<_>, _>>()?;
+    ensure!(
+        new_xp.iter().all(|amount| !amount.is_zero()),
+        StdError::generic_err("Imbalanced withdraw can not drain the pool")
+    );
+
+    old_xp[1] *= price_scale;
+    new_xp[1] *= price_scale;
+
+    let amp_gamma = config.pool_state.get_amp_gamma(env);
+    let old_d = calc_d(&old_xp, &amp_gamma)?;
+    let new_d = calc_d(&new_xp, &amp_gamma)?;
+
+    let mut iwithdraw = withdraw_amounts.to_vec();
+    iwithdraw[1] *= price_scale;
+    let fee = calc_provide_fee(&iwithdraw, &new_xp, &config.pool_params);
+
+    let total_share = total_share.to_decimal256(LP_TOKEN_PRECISION)?;
+    let burn_amount = total_share * old_d.saturating_sub(new_d) / old_d * (Decimal256::one() + fee);
+
+    // Round up in favor of the pool
+    let burn_amount = burn_amount.to_uint(LP_TOKEN_PRECISION)? + Uint128::one();
+
+    Ok((burn_amount, new_xp))
+}
+
+/// Calculates the amount of a single asset received for burning `burn_amount` LP tokens.
+/// Such withdraw is charged with the same fee as imbalanced withdraw (see [`calc_withdraw_share`]).
+/// Returns withdraw amounts sorted in the same order as pool assets and new pool balances in the internal representation.
+pub(crate) fn calc_single_asset_withdraw(
+    config: &Config,
+    env: &Env,
+    pools: &[DecimalAsset],
+    asset_ind: usize,
+    burn_amount: Uint128,
+    total_share: Uint128,
+) -> Result<(Vec<Decimal256>, Vec<Decimal256>), ContractError> {
+    ensure!(
+        burn_amount < total_share,
+        StdError::generic_err("Single asset withdraw can not drain the pool")
+    );
+
+    let price_scale = config.pool_state.price_state.price_scale;
+    let mut xp = pools.iter().map(|pool| pool.amount).collect_vec();
+    xp[1] *= price_scale;
+
+    let amp_gamma = config.pool_state.get_amp_gamma(env);
+    let d = calc_d(&xp, &amp_gamma)?;
+    let total_share = total_share.to_decimal256(LP_TOKEN_PRECISION)?;
+
+    // Returns new pool balances in the internal representation after burning the given share
+    let calc_new_xp = |share: Decimal256| -> Result<Vec<Decimal256>, ContractError> {
+        let new_d = d * (Decimal256::one() - share / total_share);
+        let mut new_xp = xp.clone();
+        new_xp[asset_ind] = calc_y(&xp, new_d, &amp_gamma, asset_ind)?;
+        ensure!(
+            !new_xp[asset_ind].is_zero() && new_xp[asset_ind] < xp[asset_ind],
+            StdError::generic_err("Single asset withdraw can not drain the pool")
+        );
+
+        Ok(new_xp)
+    };
+
+    // Estimate the fee using the withdraw amount without fees
+    let burn_amount = burn_amount.to_decimal256(LP_TOKEN_PRECISION)?;
+    let new_xp = calc_new_xp(burn_amount)?;
+    let mut iwithdraw = vec![Decimal256::zero(); xp.len()];
+    iwithdraw[asset_ind] = xp[asset_ind] - new_xp[asset_ind];
+    let fee = calc_provide_fee(&iwithdraw, &new_xp, &config.pool_params);
+
+    let new_xp = calc_new_xp(burn_amount / (Decimal256::one() + fee))?;
+    let mut withdraw_amounts = vec![Decimal256::zero(); xp.len()];
+    withdraw_amounts[asset_ind] = xp[asset_ind] - new_xp[asset_ind];
+    if asset_ind == 1 {
+        withdraw_amounts[1] /= price_scale;
+    }
+
+    Ok((withdraw_amounts, new_xp))
+}
+
 /// Calculate and save price moving average
 pub fn accumulate_swap_sizes(storage: &mut dyn Storage, env: &Env) -> BufferResult<()> {
     if let Some(PrecommitObservation {
@@ -102,6 +309,668 @@ pub fn accumulate_swap_sizes(storage: &mut dyn Storage, env: &Env) -> BufferResu
     Ok(())
 }
 
+/// Accumulates the price which was in effect since the last recorded point
+/// and records **price** as the one in effect starting from the current block.
+/// Multiple updates within the same block don't affect the cumulative price.
+///
+/// Points older than [`MAX_TWAP_WINDOW`] are pruned, at most [`MAX_PRUNED_TWAP_POINTS`] per call.
+/// The latest expired point is kept as it is needed to calculate the cumulative price at the window start.
+pub(crate) fn accumulate_prices(
+    storage: &mut dyn Storage,
+    env: &Env,
+    price: Decimal256,
+) -> StdResult<()> {
+    let now = env.block.time.seconds();
+    let cumulative_price = cumulative_price_at(storage, now)?.unwrap_or_default();
+
+    TWAP_POINTS.save(
+        storage,
+        now,
+        &TwapPoint {
+            cumulative_price,
+            price,
+        },
+    )?;
+
+    let cutoff = now.saturating_sub(MAX_TWAP_WINDOW);
+    let expired = TWAP_POINTS
+        .keys(
+            storage,
+            None,
+            Some(Bound::exclusive(cutoff)),
+            Order::Ascending,
+        )
+        .take(MAX_PRUNED_TWAP_POINTS + 1)
+        .collect::<StdResult<Vec<_>>>()?;
+    let prune_count = if expired.len() > MAX_PRUNED_TWAP_POINTS {
+        MAX_PRUNED_TWAP_POINTS
+    } else {
+        expired.len().saturating_sub(1)
+    };
+    for timestamp in &expired[..prune_count] {
+        TWAP_POINTS.remove(storage, *timestamp);
+    }
+
+    Ok(())
+}
+
+/// Returns the cumulative price at the specified timestamp or None if there are no price points before it.
+pub(crate) fn cumulative_price_at(
+    storage: &dyn Storage,
+    timestamp: u64,
+) -> StdResult<Option<Decimal256>> {
+    TWAP_POINTS
+        .range(
+            storage,
+            None,
+            Some(Bound::inclusive(timestamp)),
+            Order::Descending,
+        )
+        .next()
+        .transpose()?
+        .map(|(ts, point)| {
+            let elapsed = Decimal256::from_ratio(timestamp - ts, 1u8);
+            Ok(point.cumulative_price + point.price * elapsed)
+        })
+        .transpose()
+}
+
+/// Moves observations to a new circular buffer with **new_size** capacity.
+/// Growing keeps all observations as is. Shrinking keeps the most recent observations and drops
+/// at most [`MAX_DROPPED_OBSERVATIONS`] older ones. In that case SMA values of the kept observations
+/// are recalculated over them so that further SMA updates remain consistent.
+/// Storage work is bounded by the new buffer capacity and the number of dropped observations.
+pub(crate) fn resize_observations(
+    storage: &mut dyn Storage,
+    new_size: u32,
+) -> Result<(), ContractError> {
+    if !OBSERVATIONS_SIZE_LIMITS.contains(&new_size) {
+        return Err(ContractError::InvalidObservationsSize {});
+    }
+
+    let buffer = BufferManager::new(storage, OBSERVATIONS)?;
+    let old_size = buffer.capacity();
+    let head = buffer.head();
+
+    // The buffer is full if the slot at head is taken. In this case the oldest observation is stored at head.
+    // Otherwise, observations are stored in [0, head) slots
+    let (stored, oldest_ind) = if buffer.read_single(storage, head)?.is_some() {
+        (old_size, head)
+    } else {
+        (head, 0)
+    };
+    let keep = stored.min(new_size);
+    if stored - keep > MAX_DROPPED_OBSERVATIONS {
+        return Err(ContractError::ObservationsShrinkTooLarge {});
+    }
+
+    // Read the most recent observations in chronological order
+    let mut observations = Vec::with_capacity(keep as usize);
+    for i in stored - keep..stored {
+        let observation = buffer
+            .read_single(storage, (oldest_ind + i) % old_size)?
+            .ok_or_else(|| StdError::generic_err("Observations buffer is corrupted"))?;
+        observations.push(observation);
+    }
+
+    if keep < stored {
+        let mut prices_sum = Decimal::zero();
+        for (i, observation) in observations.iter_mut().enumerate() {
+            prices_sum += observation.price;
+            observation.price_sma = prices_sum / Uint128::from(i as u128 + 1);
+        }
+    }
+
+    // The new buffer overwrites [0, keep) slots. Dropped observations in the rest of the old buffer are removed
+    clear_observations(storage, keep..stored);
+
+    BufferManager::init(storage, OBSERVATIONS, new_size)?;
+    let mut buffer = BufferManager::new(storage, OBSERVATIONS)?;
+    for observation in &observations {
+        buffer.instant_push(storage, observation)?;
+    }
+
+    Ok(())
+}
+
+/// Checks that the withdrawn assets satisfy the minimum amounts requested by the user.
+/// Assets which are not specified in **min_assets_to_receive** are not checked.
+pub(crate) fn ensure_min_assets_to_receive(
+    refund_assets: &[Asset],
+    min_assets_to_receive: Option<Vec<Asset>>,
+) -> Result<(), ContractError> {
+    for min_asset in min_assets_to_receive.unwrap_or_default() {
+        let refund_asset = refund_assets
+            .iter()
+            .find(|asset| asset.info == min_asset.info)
+            .ok_or_else(|| ContractError::InvalidAsset(min_asset.info.to_string()))?;
+
+        ensure!(
+            refund_asset.amount >= min_asset.amount,
+            ContractError::WithdrawSlippageViolation {
+                asset_name: min_asset.info.to_string(),
+                received: refund_asset.amount,
+                expected: min_asset.amount,
+            }
+        );
+    }
+
+    Ok(())
+}
+
+/// Checks that the action is executed before the deadline (block time in seconds), if specified.
+pub(crate) fn ensure_deadline(env: &Env, deadline: Option<u64>) -> Result<(), ContractError> {
+    if let Some(deadline) = deadline {
+        let block_time = env.block.time.seconds();
+        ensure!(
+            block_time <= deadline,
+            ContractError::DeadlineExpired {
+                deadline,
+                block_time
+            }
+        );
+    }
+
+    Ok(())
+}
+
+/// Validates the number of provided assets and returns them in the pool order.
+/// The omitted asset in a one-sided provide is appended with explicit zero amount.
+pub(crate) fn prepare_provide_assets(
+    config: &Config,
+    mut assets: Vec<Asset>,
+) -> Result<Vec<Asset>, ContractError> {
+    match assets.len() {
+        0 => {
+            return Err(StdError::generic_err("Nothing to provide").into());
+        }
+        1 => {
+            // Append omitted asset with explicit zero amount
+            let (given_ind, _) = config
+                .pair_info
+                .asset_infos
+                .iter()
+                .find_position(|pool| pool.equal(&assets[0].info))
+                .ok_or_else(|| ContractError::InvalidAsset(assets[0].info.to_string()))?;
+            assets.push(Asset {
+                info: config.pair_info.asset_infos[1 ^ given_ind].clone(),
+                amount: Uint128::zero(),
+            });
+        }
+        2 => {}
+        _ => {
+            return Err(ContractError::InvalidNumberOfAssets(
+                config.pair_info.asset_infos.len(),
+            ))
+        }
+    }
+
+    if config.pair_info.asset_infos[0].equal(&assets[1].info) {
+        assets.swap(0, 1);
+    }
+
+    Ok(assets)
+}
+
+/// Liquidity provision result shared by provide_liquidity and the SimulateProvide query.
+pub(crate) struct ProvideResult {
+    /// Amount of LP tokens minted to the provider
+    pub share: Decimal256,
+    /// Amount of LP tokens withheld as the provide fee
+    pub fee: Decimal256,
+    /// Provide slippage. It is zero if the deposit is close to the balanced one
+    pub slippage: Decimal256,
+    /// Whether the provide updated the pool price
+    pub price_updated: bool,
+}
+
+/// Calculates the amount of LP tokens for the given deposits. Pool state in **config** is updated
+/// (initial xcp profit, price update) exactly as it must be persisted after the provide.
+///
+/// * **xs** are pool balances before the provide in the external representation.
+///
+/// * **deposits** are provided amounts in the pool order.
+///
+/// * **total_share** is the total amount of LP tokens before the provide.
+/// On the initial provide [`MINIMUM_LIQUIDITY_AMOUNT`] is subtracted from the share
+/// as it must be locked on the pair contract.
+pub(crate) fn compute_provide(
+    config: &mut Config,
+    env: &Env,
+    xs: &[Decimal256],
+    deposits: [Decimal256; 2],
+    total_share: Decimal256,
+    slippage_tolerance: Option<Decimal>,
+) -> Result<ProvideResult, ContractError> {
+    // Initial provide can not be one-sided
+    if total_share.is_zero() && (deposits[0].is_zero() || deposits[1].is_zero()) {
+        return Err(ContractError::InvalidZeroAmount {});
+    }
+
+    let mut new_xp = xs
+        .iter()
+        .enumerate()
+        .map(|(ind, amount)| *amount + deposits[ind])
+        .collect_vec();
+    new_xp[1] *= config.pool_state.price_state.price_scale;
+
+    let amp_gamma = config.pool_state.get_amp_gamma(env);
+    let new_d = calc_d(&new_xp, &amp_gamma)?;
+
+    let (share, fee) = if total_share.is_zero() {
+        let xcp = get_xcp(new_d, config.pool_state.price_state.price_scale);
+        let mint_amount =
+            xcp.saturating_sub(MINIMUM_LIQUIDITY_AMOUNT.to_decimal256(LP_TOKEN_PRECISION)?);
+
+        // share cannot become zero after minimum liquidity subtraction
+        if mint_amount.is_zero() {
+            return Err(ContractError::MinimumLiquidityAmountError {});
+        }
+
+        config.pool_state.price_state.xcp_profit_real = Decimal256::one();
+        config.pool_state.price_state.xcp_profit = Decimal256::one();
+
+        (mint_amount, Decimal256::zero())
+    } else {
+        let mut old_xp = xs.to_vec();
+        old_xp[1] *= config.pool_state.price_state.price_scale;
+        let old_d = calc_d(&old_xp, &amp_gamma)?;
+        let share = (total_share * new_d / old_d).saturating_sub(total_share);
+
+        let mut ideposits = deposits;
+        ideposits[1] *= config.pool_state.price_state.price_scale;
+
+        let share_after_fee = share
+            * (Decimal256::one() - calc_provide_fee(&ideposits, &new_xp, &config.pool_params));
+        (share_after_fee, share - share_after_fee)
+    };
+
+    // calculate accrued share
+    let share_ratio = share / (total_share + share);
+    let balanced_share = [
+        new_xp[0] * share_ratio,
+        new_xp[1] * share_ratio / config.pool_state.price_state.price_scale,
+    ];
+    let assets_diff = [
+        deposits[0].diff(balanced_share[0]),
+        deposits[1].diff(balanced_share[1]),
+    ];
+
+    let mut slippage = Decimal256::zero();
+    let mut price_updated = false;
+
+    // If deposit doesn't diverge too much from the balanced share, we don't update the price
+    if assets_diff[0] >= MIN_TRADE_SIZE && assets_diff[1] >= MIN_TRADE_SIZE {
+        slippage = assert_slippage_tolerance(
+            &deposits,
+            share,
+            &config.pool_state.price_state,
+            slippage_tolerance,
+        )?;
+
+        let last_price = assets_diff[0] / assets_diff[1];
+        config.pool_state.update_price(
+            &config.pool_params,
+            env,
+            total_share + share,
+            &new_xp,
+            last_price,
+        )?;
+        price_updated = true;
+    }
+
+    Ok(ProvideResult {
+        share,
+        fee,
+        slippage,
+        price_updated,
+    })
+}
+
+/// Returns the current PCL fee rate for the given pool balances.
+/// Balances must be in the external representation, i.e., not multiplied by price scale.
+/// Returns `mid_fee` if one of the pools is empty.
+pub(crate) fn current_fee_rate(config: &Config, xs: &[Decimal256]) -> StdResult<Decimal> {
+    if xs.iter().any(|x| x.is_zero()) {
+        return Ok(config.pool_params.mid_fee);
+    }
+
+    // fee() works only with internal representation
+    let mut ixs = xs.to_vec();
+    ixs[1] *= config.pool_state.price_state.price_scale;
+    config
+        .pool_params
+        .fee(&ixs)
+        .try_into()
+        .map_err(|err: DecimalRangeExceeded| StdError::generic_err(err.to_string()))
+}
+
+/// Osmosis pool manager passes the pool swap fee to sudo calls and simulation queries.
+/// It equals the fee returned by GetSwapFee for the pre-trade pool state unless Osmosis applies
+/// a discount (e.g., legacy multi-hop fee reduction). In that case the LP part of the fee
+/// is scaled down proportionally. Maker fee and fee share are never discounted, thus even zero
+/// swap fee doesn't waive them. The external fee never increases PCL fees.
+///
+/// Returns the ratio by which the total PCL fee must be scaled.
+/// **xs** are pool balances before the trade in the external representation.
+pub(crate) fn external_fee_ratio(
+    config: &Config,
+    xs: &[Decimal256],
+    swap_fee: Option<Decimal>,
+    maker_fee_share: Decimal256,
+    share_fee_share: Decimal256,
+) -> StdResult<Decimal256> {
+    let Some(swap_fee) = swap_fee else {
+        return Ok(Decimal256::one());
+    };
+
+    let pool_fee = current_fee_rate(config, xs)?;
+    if pool_fee.is_zero() || swap_fee >= pool_fee {
+        return Ok(Decimal256::one());
+    }
+
+    let lp_fee_ratio = Decimal256::from_ratio(swap_fee.atomics(), pool_fee.atomics());
+    let fixed_share = (maker_fee_share + share_fee_share).min(Decimal256::one());
+
+    Ok(fixed_share + (Decimal256::one() - fixed_share) * lp_fee_ratio)
+}
+
+/// Scales the total fee of the swap by the ratio from [`external_fee_ratio`].
+/// Maker fee and fee share stay the same. Fees which are not charged go to the trader.
+pub(crate) fn apply_fee_ratio(swap_result: &mut SwapResult, ratio: Decimal256) {
+    if ratio >= Decimal256::one() {
+        return;
+    }
+
+    let total_fee = swap_result.total_fee * ratio;
+    swap_result.dy += swap_result.total_fee - total_fee;
+    swap_result.total_fee = total_fee;
+}
+
+/// Returns the ask amount which must be passed to a reverse swap calculation charging the full fee
+/// so that the trader receives at least **ask_amount** when fees are scaled by **ratio**.
+/// Reverse swap calculation charges the maximum fee rate, i.e. `out_fee`,
+/// thus the ask amount is scaled with it: `ask_amount * (1 - out_fee) / (1 - out_fee * ratio)`.
+pub(crate) fn ask_amount_with_fee_ratio(
+    config: &Config,
+    ask_amount: Decimal256,
+    ratio: Decimal256,
+) -> Decimal256 {
+    if ratio >= Decimal256::one() {
+        return ask_amount;
+    }
+
+    let max_fee = Decimal256::from(config.pool_params.out_fee);
+    ask_amount * (Decimal256::one() - max_fee) / (Decimal256::one() - max_fee * ratio)
+}
+
+/// Returns fee info from the factory.
+/// Maker fee rate is replaced with the per-pair override if the factory owner set it.
+/// Swaps and simulations must always get fee info from here, otherwise they disagree on the maker fee.
+pub(crate) fn query_pair_fee_info(
+    querier: QuerierWrapper,
+    storage: &dyn Storage,
+    config: &Config,
+) -> StdResult<FeeInfo> {
+    let mut fee_info = query_fee_info(
+        &querier,
+        &config.factory_addr,
+        config.pair_info.pair_type.clone(),
+    )?;
+
+    if let Some(maker_fee_bps) = MAKER_FEE_OVERRIDE.may_load(storage)? {
+        fee_info.maker_fee_rate = Decimal::from_ratio(maker_fee_bps, 10000u16);
+    }
+
+    Ok(fee_info)
+}
+
+/// Returns maker fee share and fee share of the total swap fee.
+/// Maker fee is charged only if the factory has a fee address set.
+pub(crate) fn fee_shares(config: &Config, fee_info: &FeeInfo) -> (Decimal256, Decimal256) {
+    let maker_fee_share = if fee_info.fee_address.is_some() {
+        fee_info.maker_fee_rate.into()
+    } else {
+        Decimal256::zero()
+    };
+    let share_fee_share = config
+        .fee_share
+        .as_ref()
+        .map(|fee_share| Decimal256::from_ratio(fee_share.bps, 10000u16))
+        .unwrap_or_default();
+
+    (maker_fee_share, share_fee_share)
+}
+
+/// Ensures the sender is the pair owner. If the pair owner is not set, the factory owner acts as the pair owner.
+pub(crate) fn ensure_owner(
+    querier: QuerierWrapper,
+    config: &Config,
+    sender: &Addr,
+) -> Result<(), ContractError> {
+    let factory_config = query_factory_config(&querier, &config.factory_addr)?;
+
+    if sender != config.owner.as_ref().unwrap_or(&factory_config.owner) {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    Ok(())
+}
+
+/// Builds messages notifying registered swap hooks.
+/// Each hook call is limited by [`SWAP_HOOK_GAS_LIMIT`].
+/// Hook failures, including running out of gas, are handled in the reply and don't revert the swap.
+pub(crate) fn swap_hook_messages(
+    storage: &dyn Storage,
+    msg: &SwapHookMsg,
+) -> StdResult<Vec<SubMsg>> {
+    SWAP_HOOKS
+        .may_load(storage)?
+        .unwrap_or_default()
+        .into_iter()
+        .map(|hook| {
+            Ok(
+                SubMsg::reply_on_error(wasm_execute(hook, msg, vec![])?, SWAP_HOOK_REPLY_ID)
+                    .with_gas_limit(SWAP_HOOK_GAS_LIMIT),
+            )
+        })
+        .collect()
+}
+
+/// Ensures the referral address and the referral commission are either both set or both omitted
+/// and the commission does not exceed [`MAX_REFERRAL_COMMISSION`].
+pub(crate) fn ensure_referral<T>(
+    referral_address: &Option<T>,
+    referral_commission: Option<Decimal>,
+) -> Result<(), ContractError> {
+    if referral_address.is_some() != referral_commission.is_some() {
+        return Err(ContractError::IncompleteReferral {});
+    }
+    if referral_commission.unwrap_or_default() > MAX_REFERRAL_COMMISSION {
+        return Err(ContractError::ReferralCommissionTooHigh {});
+    }
+
+    Ok(())
+}
+
+/// Ensures the post-trade price does not deviate from the EMA oracle price more than allowed.
+/// Both prices must be in the internal representation.
+pub(crate) fn ensure_price_deviation(
+    storage: &dyn Storage,
+    oracle_price: Decimal256,
+    last_price: Decimal256,
+) -> Result<(), ContractError> {
+    if let Some(max_deviation) = MAX_PRICE_DEVIATION.may_load(storage)? {
+        if oracle_price.is_zero() {
+            return Ok(());
+        }
+
+        let max_deviation = Decimal256::from(max_deviation);
+        let deviation = last_price.abs_diff(oracle_price) / oracle_price;
+        if deviation > max_deviation {
+            return Err(ContractError::PriceDeviationTooHigh {
+                deviation,
+                max_deviation,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns pool pause flags. Nothing is paused by default.
+pub(crate) fn load_pause_flags(storage: &dyn Storage) -> StdResult<PauseFlags> {
+    Ok(PAUSE_FLAGS.may_load(storage)?.unwrap_or_default())
+}
+
+/// Checks whether **factory_addr** is one of the factories trusted on the chain with **chain_id**.
+pub(crate) fn is_trusted_factory(chain_id: &str, factory_addr: &str) -> bool {
+    TRUSTED_FACTORIES
+        .lines()
+        .filter_map(|line| line.trim().split_once(' '))
+        .any(|(chain, factory)| chain == chain_id && factory.trim() == factory_addr)
+}
+
+/// Rejects any pair operation while a flash swap is waiting to be repaid.
+pub(crate) fn ensure_no_flash_swap(storage: &dyn Storage) -> Result<(), ContractError> {
+    if FLASH_SWAP.may_load(storage)?.is_some() {
+        return Err(ContractError::FlashSwapInProgress {});
+    }
+
+    Ok(())
+}
+
+/// Returns whether the pool is active. Pools instantiated before this flag was introduced are active.
+pub(crate) fn is_pool_active(storage: &dyn Storage) -> StdResult<bool> {
+    Ok(IS_ACTIVE.may_load(storage)?.unwrap_or(true))
+}
+
+/// Returns the default LP token metadata derived from the pool denoms.
+pub(crate) fn default_lp_token_metadata(asset_infos: &[AssetInfo]) -> LpTokenMetadata {
+    let [base, quote] = lp_display_parts(asset_infos);
+    LpTokenMetadata {
+        name: format!("Astroport {base}-{quote} PCL LP token"),
+        symbol: format!("{base}-{quote}-LP"),
+        description: format!(
+            "Astroport PCL pair {}/{} liquidity token",
+            asset_infos[0], asset_infos[1]
+        ),
+    }
+}
+
+/// Returns human readable names of pool denoms used in the LP token metadata.
+/// Tokenfactory denoms are shortened to their full subdenom.
+/// Other denoms, e.g. `ibc/<hash>`, are kept as is since their last segment is meaningless on its own.
+fn lp_display_parts(asset_infos: &[AssetInfo]) -> [String; 2] {
+    [&asset_infos[0], &asset_infos[1]].map(|asset_info| {
+        let denom = asset_info.to_string();
+        match denom.splitn(3, '/').collect_vec()[..] {
+            ["factory", _, subdenom] if !subdenom.is_empty() => subdenom.to_string(),
+            _ => denom,
+        }
+    })
+}
+
+/// Ensures the LP token name and symbol are non-empty and don't exceed [`MAX_LP_TOKEN_METADATA_LENGTH`].
+pub(crate) fn validate_lp_token_metadata(metadata: &LpTokenMetadata) -> Result<(), ContractError> {
+    for value in [&metadata.name, &metadata.symbol] {
+        let len = value.trim().chars().count();
+        if len == 0 || len > MAX_LP_TOKEN_METADATA_LENGTH {
+            return Err(ContractError::InvalidLpTokenMetadata {});
+        }
+    }
+
+    Ok(())
+}
+
+/// Builds tokenfactory message which sets LP token bank metadata.
+/// The display unit is derived from the pool denoms and has [`LP_TOKEN_PRECISION`] decimals.
+/// If it doesn't fit the bank denom length limit, the pair address is used instead.
+pub(crate) fn set_lp_metadata_msg(
+    contract_address: &Addr,
+    lp_denom: &str,
+    asset_infos: &[AssetInfo],
+    metadata: LpTokenMetadata,
+) -> CosmosMsg {
+    let [base, quote] = lp_display_parts(asset_infos);
+    let mut display = format!("lp-{base}-{quote}");
+    if display.len() > MAX_DENOM_LENGTH {
+        display = format!("lp-{contract_address}");
+    }
+
+    MsgSetDenomMetadata {
+        sender: contract_address.to_string(),
+        metadata: Some(Metadata {
+            description: metadata.description,
+            denom_units: vec![
+                DenomUnit {
+                    denom: lp_denom.to_string(),
+                    exponent: 0,
+                    aliases: vec![],
+                },
+                DenomUnit {
+                    denom: display.clone(),
+                    exponent: LP_TOKEN_PRECISION as u32,
+                    aliases: vec![],
+                },
+            ],
+            base: lp_denom.to_string(),
+            display,
+            name: metadata.name,
+            symbol: metadata.symbol,
+            uri: String::new(),
+            uri_hash: String::new(),
+        }),
+    }
+    .into()
+}
+
+/// Mints LP tokens for the recipient.
+/// If `auto_stake` is set, LP tokens are minted to the pair itself and then deposited
+/// into the incentives contract (factory's `generator_address`) on behalf of the recipient.
+pub(crate) fn mint_liquidity_token_message(
+    querier: QuerierWrapper,
+    config: &Config,
+    contract_address: &Addr,
+    recipient: &Addr,
+    amount: Uint128,
+    auto_stake: bool,
+) -> Result<Vec<CosmosMsg>, ContractError> {
+    let lp_coin = coin(amount.u128(), config.pair_info.liquidity_token.to_string());
+
+    // If no auto-stake - just mint LP tokens to the recipient
+    if !auto_stake {
+        return Ok(vec![MsgMint {
+            sender: contract_address.to_string(),
+            amount: Some(lp_coin.into()),
+            mint_to_address: recipient.to_string(),
+        }
+        .into()]);
+    }
+
+    // Otherwise, mint LP tokens to the pair and stake them in the incentives contract
+    let incentives_addr = query_factory_config(&querier, &config.factory_addr)?
+        .generator_address
+        .ok_or(ContractError::AutoStakeError {})?;
+
+    Ok(vec![
+        MsgMint {
+            sender: contract_address.to_string(),
+            amount: Some(lp_coin.clone().into()),
+            mint_to_address: contract_address.to_string(),
+        }
+        .into(),
+        wasm_execute(
+            incentives_addr,
+            &IncentivesExecuteMsg::Deposit {
+                recipient: Some(recipient.to_string()),
+            },
+            vec![lp_coin],
+        )?
+        .into(),
+    ])
+}
+
 pub fn query_native_supply(querier: &QuerierWrapper, contract_addr: &Addr) -> StdResult<Uint128> {
     querier
         .query_supply(contract_addr.as_str())
@@ -122,6 +991,60 @@ mod tests {
         f64::from_str(&val.to_string()).unwrap()
     }
 
+    #[test]
+    fn test_lp_display_parts() {
+        let ibc_denom = format!("ibc/{}", "A".repeat(64));
+        let asset_infos = [
+            AssetInfo::NativeToken {
+                denom: "factory/osmo1creator/sub/denom".to_string(),
+            },
+            AssetInfo::NativeToken {
+                denom: ibc_denom.clone(),
+            },
+        ];
+        assert_eq!(
+            lp_display_parts(&asset_infos),
+            ["sub/denom".to_string(), ibc_denom.clone()]
+        );
+
+        let asset_infos = [
+            AssetInfo::NativeToken {
+                denom: "uosmo".to_string(),
+            },
+            AssetInfo::NativeToken {
+                denom: "factory/osmo1creator/".to_string(),
+            },
+        ];
+        assert_eq!(
+            lp_display_parts(&asset_infos),
+            ["uosmo".to_string(), "factory/osmo1creator/".to_string()]
+        );
+
+        // Default metadata built from full-length denoms is still valid
+        let long_denom = format!("factory/osmo1creator/{}", "a".repeat(107));
+        let asset_infos = [
+            AssetInfo::NativeToken {
+                denom: long_denom.clone(),
+            },
+            AssetInfo::NativeToken { denom: long_denom },
+        ];
+        validate_lp_token_metadata(&default_lp_token_metadata(&asset_infos)).unwrap();
+
+        let metadata = default_lp_token_metadata(&[
+            AssetInfo::NativeToken {
+                denom: ibc_denom.clone(),
+            },
+            AssetInfo::NativeToken { denom: ibc_denom },
+        ]);
+        validate_lp_token_metadata(&metadata).unwrap();
+        let err = validate_lp_token_metadata(&LpTokenMetadata {
+            name: String::new(),
+            ..metadata
+        })
+        .unwrap_err();
+        assert_eq!(err, ContractError::InvalidLpTokenMetadata {});
+    }
+
     #[test]
     fn test_swap_observations() {
         let mut store = MockStorage::new();
@@ -149,4 +1072,215 @@ mod tests {
         assert_eq!(dec_to_f64(obs.price_sma), 2.0);
         assert_eq!(dec_to_f64(obs.price), 2.0);
     }
+
+    #[test]
+    fn test_cumulative_prices() {
+        let mut store = MockStorage::new();
+        let mut env = mock_env();
+        env.block.time = Timestamp::from_seconds(100);
+
+        assert_eq!(cumulative_price_at(&store, 100).unwrap(), None);
+
+        accumulate_prices(&mut store, &env, Decimal256::percent(200)).unwrap();
+        assert_eq!(cumulative_price_at(&store, 99).unwrap(), None);
+        assert_eq!(
+            cumulative_price_at(&store, 100).unwrap(),
+            Some(Decimal256::zero())
+        );
+
+        // Price of 2 was in effect for 10 seconds. Same block updates overwrite the price only
+        env.block.time = env.block.time.plus_seconds(10);
+        accumulate_prices(&mut store, &env, Decimal256::percent(500)).unwrap();
+        accumulate_prices(&mut store, &env, Decimal256::percent(300)).unwrap();
+        assert_eq!(
+            cumulative_price_at(&store, 110).unwrap(),
+            Some(Decimal256::from_ratio(20u8, 1u8))
+        );
+
+        // Price of 3 is extrapolated until the next point
+        assert_eq!(
+            cumulative_price_at(&store, 130).unwrap(),
+            Some(Decimal256::from_ratio(80u8, 1u8))
+        );
+        assert_eq!(
+            cumulative_price_at(&store, 105).unwrap(),
+            Some(Decimal256::from_ratio(10u8, 1u8))
+        );
+    }
+
+    #[test]
+    fn test_prune_twap_points() {
+        let mut store = MockStorage::new();
+        let mut env = mock_env();
+        env.block.time = Timestamp::from_seconds(1);
+
+        for _ in 0..30 {
+            accumulate_prices(&mut store, &env, Decimal256::one()).unwrap();
+            env.block.time = env.block.time.plus_seconds(MAX_TWAP_WINDOW / 10);
+        }
+        let points_count = |store: &MockStorage| {
+            TWAP_POINTS
+                .keys(store, None, None, Order::Ascending)
+                .count()
+        };
+        // Each update prunes a bounded number of expired points
+        assert!(points_count(&store) <= 12);
+
+        // Pruning catches up after a long inactivity period
+        env.block.time = env.block.time.plus_seconds(MAX_TWAP_WINDOW * 10);
+        accumulate_prices(&mut store, &env, Decimal256::one()).unwrap();
+        accumulate_prices(&mut store, &env, Decimal256::one()).unwrap();
+        // Only the latest expired point and the current one are kept
+        assert_eq!(points_count(&store), 2);
+
+        let now = env.block.time.seconds();
+        let window_start = now - MAX_TWAP_WINDOW;
+        assert_eq!(
+            cumulative_price_at(&store, window_start).unwrap(),
+            Some(Decimal256::from_ratio(window_start - 1, 1u8))
+        );
+    }
+
+    #[test]
+    fn test_resize_observations() {
+        let mut store = MockStorage::new();
+        let mut env = mock_env();
+        env.block.time = Timestamp::from_seconds(1);
+
+        let next_block = |block: &mut BlockInfo| {
+            block.height += 1;
+            block.time = block.time.plus_seconds(1);
+        };
+
+        BufferManager::init(&mut store, OBSERVATIONS, 20).unwrap();
+
+        // Observed prices are 1, 2, 3, ...
+        let mut price = 0u128;
+        let mut observe = |store: &mut MockStorage, env: &mut Env| {
+            price += 1;
+            accumulate_swap_sizes(store, env).unwrap();
+            PrecommitObservation::save(store, env, price.into(), 1u128.into()).unwrap();
+            next_block(&mut env.block);
+        };
+
+        for _ in 0..30 {
+            observe(&mut store, &mut env);
+        }
+        // The last precommit observation is not yet in the buffer
+        let last_obs = BufferManager::new(&store, OBSERVATIONS)
+            .unwrap()
+            .read_last(&store)
+            .unwrap()
+            .unwrap();
+        assert_eq!(last_obs.ts, 29);
+
+        let err = resize_observations(&mut store, 5).unwrap_err();
+        assert_eq!(err, ContractError::InvalidObservationsSize {});
+
+        // Shrinking keeps the most recent observations and recalculates SMA over the new window
+        resize_observations(&mut store, 10).unwrap();
+        let buffer = BufferManager::new(&store, OBSERVATIONS).unwrap();
+        assert_eq!(buffer.capacity(), 10);
+        let oldest_obs = buffer.read_single(&store, buffer.head()).unwrap().unwrap();
+        assert_eq!(oldest_obs.ts, 20);
+        let last_obs = buffer.read_last(&store).unwrap().unwrap();
+        assert_eq!(last_obs.ts, 29);
+        // Average of prices 20..=29
+        assert_eq!(dec_to_f64(last_obs.price_sma), 24.5);
+        let timestamps = (0..10)
+            .map(|i| {
+                buffer
+                    .read_single(&store, (buffer.head() + i) % 10)
+                    .unwrap()
+                    .unwrap()
+                    .ts
+            })
+            .collect_vec();
+        assert_eq!(timestamps, (20..30).collect_vec());
+
+        // New observations are pushed into the resized buffer
+        observe(&mut store, &mut env);
+        let last_obs = BufferManager::new(&store, OBSERVATIONS)
+            .unwrap()
+            .read_last(&store)
+            .unwrap()
+            .unwrap();
+        assert_eq!(last_obs.ts, 30);
+        let price_sma = last_obs.price_sma;
+
+        // Growing keeps all observations untouched
+        resize_observations(&mut store, 50).unwrap();
+        let buffer = BufferManager::new(&store, OBSERVATIONS).unwrap();
+        assert_eq!(buffer.capacity(), 50);
+        assert_eq!(buffer.head(), 10);
+        // Wrapped observations are stored in chronological order starting from the first slot
+        let timestamps = (0..10)
+            .map(|i| buffer.read_single(&store, i).unwrap().unwrap().ts)
+            .collect_vec();
+        assert_eq!(timestamps, (21..31).collect_vec());
+        assert_eq!(buffer.read_single(&store, 10).unwrap(), None);
+        let last_obs = buffer.read_last(&store).unwrap().unwrap();
+        assert_eq!(last_obs.ts, 30);
+        assert_eq!(last_obs.price_sma, price_sma);
+
+        // Buffer keeps growing after resize
+        observe(&mut store, &mut env);
+        let buffer = BufferManager::new(&store, OBSERVATIONS).unwrap();
+        assert_eq!(buffer.head(), 11);
+        assert_eq!(buffer.read_last(&store).unwrap().unwrap().ts, 31);
+    }
+
+    #[test]
+    fn test_resize_observations_limit() {
+        let mut store = MockStorage::new();
+
+        let size = MAX_DROPPED_OBSERVATIONS + 100;
+        BufferManager::init(&mut store, OBSERVATIONS, size).unwrap();
+        let mut buffer = BufferManager::new(&store, OBSERVATIONS).unwrap();
+        // Wrap the buffer
+        for i in 0..size + 10 {
+            let observation = Observation {
+                ts: i as u64,
+                price: Decimal::from_ratio(i, 1u8),
+                price_sma: Decimal::from_ratio(i, 2u8),
+            };
+            buffer.instant_push(&mut store, &observation).unwrap();
+        }
+
+        // Growing keeps the whole history untouched
+        resize_observations(&mut store, *OBSERVATIONS_SIZE_LIMITS.end()).unwrap();
+        let buffer = BufferManager::new(&store, OBSERVATIONS).unwrap();
+        assert_eq!(buffer.head(), size);
+        let timestamps = (0..size)
+            .map(|i| buffer.read_single(&store, i).unwrap().unwrap().ts)
+            .collect_vec();
+        assert_eq!(timestamps, (10..size as u64 + 10).collect_vec());
+        let last_obs = buffer.read_last(&store).unwrap().unwrap();
+        assert_eq!(last_obs.ts, size as u64 + 9);
+        assert_eq!(last_obs.price_sma, Decimal::from_ratio(size + 9, 2u8));
+
+        // Shrinking can't drop too many observations at once
+        let err = resize_observations(&mut store, 10).unwrap_err();
+        assert_eq!(err, ContractError::ObservationsShrinkTooLarge {});
+
+        resize_observations(&mut store, 100).unwrap();
+        let buffer = BufferManager::new(&store, OBSERVATIONS).unwrap();
+        assert_eq!(buffer.capacity(), 100);
+        let oldest_obs = buffer.read_single(&store, buffer.head()).unwrap().unwrap();
+        assert_eq!(oldest_obs.ts, size as u64 - 90);
+        let last_obs = buffer.read_last(&store).unwrap().unwrap();
+        assert_eq!(last_obs.ts, size as u64 + 9);
+        // SMA is recalculated over the kept observations
+        assert_eq!(
+            dec_to_f64(last_obs.price_sma),
+            (size - 90 + size + 9) as f64 / 2.0
+        );
+        // Dropped observations are removed, thus growing again doesn't pick them up
+        resize_observations(&mut store, size).unwrap();
+        let buffer = BufferManager::new(&store, OBSERVATIONS).unwrap();
+        assert_eq!(buffer.head(), 100);
+        for i in 100..size {
+            assert_eq!(buffer.read_single(&store, i).unwrap(), None);
+        }
+    }
 }
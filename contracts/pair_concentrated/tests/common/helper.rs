@@ -1,8 +1,10 @@
 #![allow(dead_code)]
 
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::error::Error;
 use std::fmt::Display;
+use std::rc::Rc;
 use std::str::FromStr;
 
 use anyhow::Result as AnyResult;
@@ -10,7 +12,7 @@ use astroport::asset::{native_asset_info, token_asset_info, Asset, AssetInfo, Pa
 use astroport::factory::{PairConfig, PairType};
 use astroport::observation::OracleObservation;
 use astroport::pair::{
-    ConfigResponse, CumulativePricesResponse, Cw20HookMsg, ReverseSimulationResponse,
+    ConfigResponse, CumulativePricesResponse, Cw20HookMsg, PoolResponse, ReverseSimulationResponse,
     SimulationResponse,
 };
 use astroport::pair_concentrated::{
@@ -20,23 +22,30 @@ use astroport::token;
 use astroport::token::Cw20Coin;
 use astroport_pcl_common::state::Config;
 use cosmwasm_schema::cw_serde;
+use cosmwasm_schema::serde::Serialize;
 use cosmwasm_std::testing::MockApi;
 use cosmwasm_std::{
-    coin, coins, from_json, to_json_binary, Addr, Api, Coin, Decimal, Decimal256, Empty, GovMsg,
-    IbcMsg, IbcQuery, MemoryStorage, StdError, StdResult, Storage, Uint128,
+    coin, coins, from_json, to_json_binary, wasm_execute, Addr, Api, BankMsg, Binary, Coin,
+    CosmosMsg, Decimal, Decimal256, Deps, DepsMut, Empty, Env, GovMsg, IbcMsg, IbcQuery,
+    MemoryStorage, MessageInfo, Response, StdError, StdResult, Storage, Uint128,
 };
 use cw_multi_test::{
     AddressGenerator, App, AppResponse, BankKeeper, BasicAppBuilder, Contract, ContractWrapper,
     DistributionKeeper, Executor, FailingModule, StakeKeeper, WasmKeeper,
 };
-use cw_storage_plus::Item;
+use cw_storage_plus::{Item, Map};
 use derivative::Derivative;
 use itertools::Itertools;
+use osmosis_std::types::cosmos::bank::v1beta1::{Metadata, QueryDenomMetadataRequest};
 use osmosis_std::types::osmosis::poolmanager::v1beta1::{
     MsgSwapExactAmountOut, SwapAmountOutRoute,
 };
 
-use astroport_on_osmosis::pair_pcl::ExecuteMsg;
+use astroport_on_osmosis::pair_pcl::{
+    CalcInAmtGivenOutResponse, CalcOutAmtGivenInResponse, ExecuteMsg, FlashSwapCallbackMsg,
+    GetSwapFeeResponse, LpTokenMetadata, PauseFlags, PoolStateResponse, QueryMsg as OsmoQueryMsg,
+    SpotPriceResponse, SwapHookMsg, TotalPoolLiquidityResponse, ZapInSwap,
+};
 use astroport_pcl_osmo::contract::{execute, instantiate, reply};
 use astroport_pcl_osmo::queries::query;
 use astroport_pcl_osmo::state::POOL_ID;
@@ -155,11 +164,230 @@ fn token_contract() -> Box<dyn Contract<Empty>> {
     ))
 }
 
+#[cw_serde]
+pub enum MockIncentivesExecuteMsg {
+    Deposit { recipient: Option<String> },
+}
+
+#[cw_serde]
+pub enum MockIncentivesQueryMsg {
+    Deposit { lp_token: String, user: String },
+}
+
+const MOCK_DEPOSITS: Map<(&str, &str), Uint128> = Map::new("deposits");
+
+/// Simplified incentives contract which only records LP token deposits.
+fn mock_incentives_contract() -> Box<dyn Contract<Empty>> {
+    Box::new(ContractWrapper::new_with_empty(
+        |deps: DepsMut,
+         _: Env,
+         info: MessageInfo,
+         msg: MockIncentivesExecuteMsg|
+         -> StdResult<Response> {
+            match msg {
+                MockIncentivesExecuteMsg::Deposit { recipient } => {
+                    let recipient = recipient.unwrap_or_else(|| info.sender.to_string());
+                    for coin in &info.funds {
+                        MOCK_DEPOSITS.update(
+                            deps.storage,
+                            (&coin.denom, &recipient),
+                            |amount| -> StdResult<_> {
+                                Ok(amount.unwrap_or_default() + coin.amount)
+                            },
+                        )?;
+                    }
+
+                    Ok(Response::new())
+                }
+            }
+        },
+        |_: DepsMut, _: Env, _: MessageInfo, _: Empty| -> StdResult<Response> {
+            Ok(Response::new())
+        },
+        |deps: Deps, _: Env, msg: MockIncentivesQueryMsg| -> StdResult<Binary> {
+            match msg {
+                MockIncentivesQueryMsg::Deposit { lp_token, user } => to_json_binary(
+                    &MOCK_DEPOSITS
+                        .may_load(deps.storage, (&lp_token, &user))?
+                        .unwrap_or_default(),
+                ),
+            }
+        },
+    ))
+}
+
+const MOCK_HOOK_FAILS: Item<bool> = Item::new("fails");
+const MOCK_HOOK_CALLS: Item<Vec<SwapHookMsg>> = Item::new("calls");
+
+/// Simplified swap hook contract which records received messages or always fails.
+fn mock_swap_hook_contract() -> Box<dyn Contract<Empty>> {
+    Box::new(ContractWrapper::new_with_empty(
+        |deps: DepsMut, _: Env, _: MessageInfo, msg: SwapHookMsg| -> StdResult<Response> {
+            if MOCK_HOOK_FAILS.load(deps.storage)? {
+                return Err(StdError::generic_err("Hook failed"));
+            }
+            let mut calls = MOCK_HOOK_CALLS.may_load(deps.storage)?.unwrap_or_default();
+            calls.push(msg);
+            MOCK_HOOK_CALLS.save(deps.storage, &calls)?;
+
+            Ok(Response::new())
+        },
+        |deps: DepsMut, _: Env, _: MessageInfo, fails: bool| -> StdResult<Response> {
+            MOCK_HOOK_FAILS.save(deps.storage, &fails)?;
+            Ok(Response::new())
+        },
+        |deps: Deps, _: Env, _: Empty| -> StdResult<Binary> {
+            to_json_binary(&MOCK_HOOK_CALLS.may_load(deps.storage)?.unwrap_or_default())
+        },
+    ))
+}
+
+/// Flash swap receiver behaviour passed in the flash swap message
+#[cw_serde]
+pub enum MockFlashSwapAction {
+    /// Sends the offer asset back to the pair
+    Repay {},
+    /// Sends back one token less than required
+    Underpay {},
+    /// Tries to swap the received asset in the same pair
+    Reenter {},
+    /// Queries the pair pool before repaying
+    QueryPool {},
+    /// Queries Osmosis pool manager endpoints and records the pool liquidity before repaying
+    QueryOsmosis {},
+}
+
+const MOCK_FLASH_POOL_LIQUIDITY: Item<Vec<Coin>> = Item::new("pool_liquidity");
+
+/// Simplified flash swap receiver which acts according to [`MockFlashSwapAction`].
+fn mock_flash_swap_receiver_contract() -> Box<dyn Contract<Empty>> {
+    Box::new(ContractWrapper::new_with_empty(
+        |deps: DepsMut,
+         _: Env,
+         info: MessageInfo,
+         msg: FlashSwapCallbackMsg|
+         -> StdResult<Response> {
+            let FlashSwapCallbackMsg::FlashSwapCallback {
+                offer_asset,
+                return_asset,
+                msg,
+                ..
+            } = msg;
+            let mut repay_coin = offer_asset.as_coin()?;
+            let msg: CosmosMsg = match from_json::<MockFlashSwapAction>(msg)? {
+                MockFlashSwapAction::Repay {} => BankMsg::Send {
+                    to_address: info.sender.to_string(),
+                    amount: vec![repay_coin],
+                }
+                .into(),
+                MockFlashSwapAction::Underpay {} => {
+                    repay_coin.amount -= Uint128::one();
+                    BankMsg::Send {
+                        to_address: info.sender.to_string(),
+                        amount: vec![repay_coin],
+                    }
+                    .into()
+                }
+                MockFlashSwapAction::QueryPool {} => {
+                    deps.querier
+                        .query_wasm_smart::<PoolResponse>(&info.sender, &OsmoQueryMsg::Pool {})?;
+                    BankMsg::Send {
+                        to_address: info.sender.to_string(),
+                        amount: vec![repay_coin],
+                    }
+                    .into()
+                }
+                MockFlashSwapAction::QueryOsmosis {} => {
+                    let offer_coin = offer_asset.as_coin()?;
+                    let return_coin = return_asset.as_coin()?;
+                    deps.querier.query_wasm_smart::<GetSwapFeeResponse>(
+                        &info.sender,
+                        &OsmoQueryMsg::GetSwapFee {},
+                    )?;
+                    deps.querier.query_wasm_smart::<SpotPriceResponse>(
+                        &info.sender,
+                        &OsmoQueryMsg::SpotPrice {
+                            quote_asset_denom: offer_coin.denom.clone(),
+                            base_asset_denom: return_coin.denom.clone(),
+                        },
+                    )?;
+                    deps.querier.query_wasm_smart::<CalcOutAmtGivenInResponse>(
+                        &info.sender,
+                        &OsmoQueryMsg::CalcOutAmtGivenIn {
+                            token_in: offer_coin,
+                            token_out_denom: return_coin.denom.clone(),
+                            swap_fee: Decimal::zero(),
+                        },
+                    )?;
+                    deps.querier.query_wasm_smart::<CalcInAmtGivenOutResponse>(
+                        &info.sender,
+                        &OsmoQueryMsg::CalcInAmtGivenOut {
+                            token_out: return_coin,
+                            token_in_denom: repay_coin.denom.clone(),
+                            swap_fee: Decimal::zero(),
+                        },
+                    )?;
+                    let TotalPoolLiquidityResponse {
+                        total_pool_liquidity,
+                    } = deps
+                        .querier
+                        .query_wasm_smart(&info.sender, &OsmoQueryMsg::GetTotalPoolLiquidity {})?;
+                    MOCK_FLASH_POOL_LIQUIDITY.save(deps.storage, &total_pool_liquidity)?;
+
+                    BankMsg::Send {
+                        to_address: info.sender.to_string(),
+                        amount: vec![repay_coin],
+                    }
+                    .into()
+                }
+                MockFlashSwapAction::Reenter {} => wasm_execute(
+                    info.sender,
+                    &ExecuteMsg::Swap {
+                        offer_asset: return_asset.clone(),
+                        ask_asset_info: None,
+                        belief_price: None,
+                        max_spread: None,
+                        to: None,
+                        deadline: None,
+                        referral_address: None,
+                        referral_commission: None,
+                    },
+                    vec![return_asset.as_coin()?],
+                )?
+                .into(),
+            };
+
+            Ok(Response::new().add_message(msg))
+        },
+        |_: DepsMut, _: Env, _: MessageInfo, _: Empty| -> StdResult<Response> {
+            Ok(Response::new())
+        },
+        |deps: Deps, _: Env, _: Empty| -> StdResult<Binary> {
+            to_json_binary(
+                &MOCK_FLASH_POOL_LIQUIDITY
+                    .may_load(deps.storage)?
+                    .unwrap_or_default(),
+            )
+        },
+    ))
+}
+
 pub fn osmo_create_pair_fee() -> Vec<Coin> {
     coins(1000_000000, "uosmo")
 }
 
-const FACTORY_ADDRESS: &str = include_str!("../../src/factory_address");
+const CHAIN_ID: &str = "osmosis-1";
+
+/// Returns the factory address pinned for [`CHAIN_ID`] in the PCL wasm binary
+fn factory_address() -> &'static str {
+    include_str!("../../src/factory_addresses")
+        .lines()
+        .find_map(|line| {
+            let (chain_id, factory) = line.split_once(' ')?;
+            (chain_id == CHAIN_ID).then_some(factory.trim())
+        })
+        .unwrap()
+}
 
 #[derive(Default)]
 struct HackyAddressGenerator<'a> {
@@ -180,7 +408,7 @@ impl<'a> AddressGenerator for HackyAddressGenerator<'a> {
     ) -> AnyResult<Addr> {
         if Self::FACTORY_MARKER.may_load(storage).unwrap().is_some() {
             Self::FACTORY_MARKER.remove(storage);
-            Ok(Addr::unchecked(FACTORY_ADDRESS))
+            Ok(Addr::unchecked(factory_address()))
         } else {
             Ok(Addr::unchecked(format!("contract{instance_id}")))
         }
@@ -198,6 +426,10 @@ pub struct Helper {
     pub pair_addr: Addr,
     pub lp_token: String,
     pub fake_maker: Addr,
+    /// Swap fees which the pool manager mock got from GetSwapFee queries
+    pub queried_swap_fees: Rc<RefCell<Vec<Decimal>>>,
+    /// Taker fee charged by the pool manager mock
+    pub taker_fee: Rc<RefCell<Decimal>>,
 }
 
 impl Helper {
@@ -208,8 +440,11 @@ impl Helper {
     ) -> AnyResult<Self> {
         let wasm_keeper =
             WasmKeeper::new().with_address_generator(HackyAddressGenerator::default());
+        let stargate = OsmosisStargate::default();
+        let queried_swap_fees = stargate.queried_swap_fees.clone();
+        let taker_fee = stargate.taker_fee.clone();
         let mut app = BasicAppBuilder::new()
-            .with_stargate(OsmosisStargate::default())
+            .with_stargate(stargate)
             .with_wasm(wasm_keeper)
             .build(|router, _, storage| {
                 router
@@ -217,6 +452,7 @@ impl Helper {
                     .init_balance(storage, owner, init_native_coins(&test_coins))
                     .unwrap()
             });
+        app.update_block(|block| block.chain_id = CHAIN_ID.to_string());
 
         let token_code_id = app.store_code(token_contract());
 
@@ -295,7 +531,7 @@ impl Helper {
             coin_registry_address: coin_registry_address.to_string(),
         };
 
-        // Set marker in storage that the next contract is factory. We need this to have exact FACTORY_ADDRESS constant
+        // Set marker in storage that the next contract is factory. We need this to have exact factory address
         // which is hardcoded in the PCL code.
         app.init_modules(|_, _, storage| HackyAddressGenerator::FACTORY_MARKER.save(storage, &()))
             .unwrap();
@@ -339,9 +575,57 @@ impl Helper {
             pair_addr: resp.contract_addr,
             lp_token: resp.liquidity_token.to_string(),
             fake_maker,
+            queried_swap_fees,
+            taker_fee,
         })
     }
 
+    /// Creates one more PCL pair through the factory and returns its address and pool id
+    pub fn create_pair(
+        &mut self,
+        asset_infos: Vec<AssetInfo>,
+        params: &impl Serialize,
+    ) -> AnyResult<(Addr, u64)> {
+        self.app.execute_contract(
+            self.owner.clone(),
+            self.factory.clone(),
+            &astroport::factory::ExecuteMsg::CreatePair {
+                pair_type: PairType::Custom("concentrated".to_string()),
+                asset_infos: asset_infos.clone(),
+                init_params: Some(to_json_binary(params).unwrap()),
+            },
+            &osmo_create_pair_fee(),
+        )?;
+
+        let pair_info: PairInfo = self.app.wrap().query_wasm_smart(
+            &self.factory,
+            &astroport::factory::QueryMsg::Pair { asset_infos },
+        )?;
+        let pool_id = POOL_ID.query(&self.app.wrap(), pair_info.contract_addr.clone())?;
+
+        Ok((pair_info.contract_addr, pool_id))
+    }
+
+    pub fn zap_in(
+        &mut self,
+        sender: &Addr,
+        coin_in: Coin,
+        swaps: Vec<ZapInSwap>,
+        min_lp_to_receive: Uint128,
+    ) -> AnyResult<AppResponse> {
+        self.app.execute_contract(
+            sender.clone(),
+            self.pair_addr.clone(),
+            &ExecuteMsg::ZapIn {
+                swaps,
+                slippage_tolerance: None,
+                min_lp_to_receive,
+                receiver: None,
+            },
+            &[coin_in],
+        )
+    }
+
     pub fn provide_liquidity(&mut self, sender: &Addr, assets: &[Asset]) -> AnyResult<AppResponse> {
         self.provide_liquidity_with_slip_tolerance(
             sender,
@@ -355,6 +639,19 @@ impl Helper {
         sender: &Addr,
         assets: &[Asset],
         slippage_tolerance: Option<Decimal>,
+    ) -> AnyResult<AppResponse> {
+        self.provide_liquidity_full(sender, assets, slippage_tolerance, None, None, None, None)
+    }
+
+    pub fn provide_liquidity_full(
+        &mut self,
+        sender: &Addr,
+        assets: &[Asset],
+        slippage_tolerance: Option<Decimal>,
+        auto_stake: Option<bool>,
+        receiver: Option<String>,
+        min_lp_to_receive: Option<Uint128>,
+        deadline: Option<u64>,
     ) -> AnyResult<AppResponse> {
         let funds =
             assets.mock_coins_sent(&mut self.app, sender, &self.pair_addr, SendType::Allowance);
@@ -362,28 +659,228 @@ impl Helper {
         let msg = ExecuteMsg::ProvideLiquidity {
             assets: assets.to_vec(),
             slippage_tolerance,
-            auto_stake: None,
-            receiver: None,
+            auto_stake,
+            receiver,
+            min_lp_to_receive,
+            deadline,
         };
 
         self.app
             .execute_contract(sender.clone(), self.pair_addr.clone(), &msg, &funds)
     }
 
+    /// Instantiates mock incentives contract and sets it as generator address in the factory.
+    pub fn setup_incentives(&mut self) -> AnyResult<Addr> {
+        let incentives_code_id = self.app.store_code(mock_incentives_contract());
+        let incentives = self.app.instantiate_contract(
+            incentives_code_id,
+            self.owner.clone(),
+            &Empty {},
+            &[],
+            "Incentives",
+            None,
+        )?;
+
+        self.app.execute_contract(
+            self.owner.clone(),
+            self.factory.clone(),
+            &astroport::factory::ExecuteMsg::UpdateConfig {
+                token_code_id: None,
+                fee_address: None,
+                generator_address: Some(incentives.to_string()),
+                whitelist_code_id: None,
+                coin_registry_address: None,
+            },
+            &[],
+        )?;
+
+        Ok(incentives)
+    }
+
+    /// Instantiates mock swap hook contract which records received messages or always fails.
+    pub fn setup_swap_hook(&mut self, fails: bool) -> AnyResult<Addr> {
+        let hook_code_id = self.app.store_code(mock_swap_hook_contract());
+        self.app.instantiate_contract(
+            hook_code_id,
+            self.owner.clone(),
+            &fails,
+            &[],
+            "Swap hook",
+            None,
+        )
+    }
+
+    pub fn setup_flash_swap_receiver(&mut self) -> AnyResult<Addr> {
+        let receiver_code_id = self.app.store_code(mock_flash_swap_receiver_contract());
+        self.app.instantiate_contract(
+            receiver_code_id,
+            self.owner.clone(),
+            &Empty {},
+            &[],
+            "Flash swap receiver",
+            None,
+        )
+    }
+
+    /// Returns the pool liquidity recorded by the flash swap receiver during the callback.
+    pub fn query_flash_pool_liquidity(&self, receiver: &Addr) -> StdResult<Vec<Coin>> {
+        self.app.wrap().query_wasm_smart(receiver, &Empty {})
+    }
+
+    pub fn flash_swap(
+        &mut self,
+        sender: &Addr,
+        offer_asset: &Asset,
+        receiver: &Addr,
+        action: MockFlashSwapAction,
+    ) -> AnyResult<AppResponse> {
+        self.app.execute_contract(
+            sender.clone(),
+            self.pair_addr.clone(),
+            &ExecuteMsg::FlashSwap {
+                offer_asset: offer_asset.clone(),
+                belief_price: None,
+                max_spread: None,
+                to: Some(receiver.to_string()),
+                msg: to_json_binary(&action).unwrap(),
+            },
+            &[],
+        )
+    }
+
+    pub fn query_swap_hook_calls(&self, hook: &Addr) -> StdResult<Vec<SwapHookMsg>> {
+        self.app.wrap().query_wasm_smart(hook, &Empty {})
+    }
+
+    pub fn update_swap_hooks(
+        &mut self,
+        sender: &Addr,
+        add: &[&Addr],
+        remove: &[&Addr],
+    ) -> AnyResult<AppResponse> {
+        self.app.execute_contract(
+            sender.clone(),
+            self.pair_addr.clone(),
+            &ExecuteMsg::UpdateSwapHooks {
+                add: add.iter().map(|addr| addr.to_string()).collect(),
+                remove: remove.iter().map(|addr| addr.to_string()).collect(),
+            },
+            &[],
+        )
+    }
+
+    pub fn resize_observations(&mut self, sender: &Addr, new_size: u32) -> AnyResult<AppResponse> {
+        self.app.execute_contract(
+            sender.clone(),
+            self.pair_addr.clone(),
+            &ExecuteMsg::ResizeObservations { new_size },
+            &[],
+        )
+    }
+
+    pub fn query_swap_hooks(&self) -> StdResult<Vec<Addr>> {
+        self.app
+            .wrap()
+            .query_wasm_smart(&self.pair_addr, &OsmoQueryMsg::SwapHooks {})
+    }
+
+    pub fn query_incentives_deposit(&self, incentives: &Addr, user: &Addr) -> StdResult<Uint128> {
+        self.app.wrap().query_wasm_smart(
+            incentives,
+            &MockIncentivesQueryMsg::Deposit {
+                lp_token: self.lp_token.clone(),
+                user: user.to_string(),
+            },
+        )
+    }
+
     pub fn withdraw_liquidity(
         &mut self,
         sender: &Addr,
         amount: u128,
         assets: Vec<Asset>,
+    ) -> AnyResult<AppResponse> {
+        self.withdraw_liquidity_full(sender, amount, assets, None, None)
+    }
+
+    pub fn withdraw_liquidity_full(
+        &mut self,
+        sender: &Addr,
+        amount: u128,
+        assets: Vec<Asset>,
+        receiver: Option<String>,
+        min_assets_to_receive: Option<Vec<Asset>>,
     ) -> AnyResult<AppResponse> {
         self.app.execute_contract(
             sender.clone(),
             self.pair_addr.clone(),
-            &ExecuteMsg::WithdrawLiquidity { assets },
+            &ExecuteMsg::WithdrawLiquidity {
+                assets,
+                receiver,
+                min_assets_to_receive,
+                single_asset: None,
+            },
+            &coins(amount, &self.lp_token),
+        )
+    }
+
+    pub fn withdraw_single_asset(
+        &mut self,
+        sender: &Addr,
+        amount: u128,
+        asset_info: &AssetInfo,
+        min_assets_to_receive: Option<Vec<Asset>>,
+    ) -> AnyResult<AppResponse> {
+        self.app.execute_contract(
+            sender.clone(),
+            self.pair_addr.clone(),
+            &ExecuteMsg::WithdrawLiquidity {
+                assets: vec![],
+                receiver: None,
+                min_assets_to_receive,
+                single_asset: Some(asset_info.clone()),
+            },
             &coins(amount, &self.lp_token),
         )
     }
 
+    pub fn query_single_asset_withdraw(
+        &self,
+        lp_amount: u128,
+        asset_info: &AssetInfo,
+    ) -> StdResult<Uint128> {
+        self.app.wrap().query_wasm_smart(
+            &self.pair_addr,
+            &OsmoQueryMsg::SimulateSingleAssetWithdraw {
+                lp_amount: lp_amount.into(),
+                asset_info: asset_info.clone(),
+            },
+        )
+    }
+
+    pub fn simulate_provide(
+        &self,
+        assets: &[Asset],
+        slippage_tolerance: Option<Decimal>,
+    ) -> StdResult<SimulateProvideResponse> {
+        self.app.wrap().query_wasm_smart(
+            &self.pair_addr,
+            &OsmoQueryMsg::SimulateProvide {
+                assets: assets.to_vec(),
+                slippage_tolerance,
+            },
+        )
+    }
+
+    pub fn query_imbalanced_withdraw(&self, assets: &[Asset]) -> StdResult<Uint128> {
+        self.app.wrap().query_wasm_smart(
+            &self.pair_addr,
+            &OsmoQueryMsg::SimulateImbalancedWithdraw {
+                assets: assets.to_vec(),
+            },
+        )
+    }
+
     pub fn swap(
         &mut self,
         sender: &Addr,
@@ -431,6 +928,9 @@ impl Helper {
                     belief_price,
                     max_spread,
                     to: None,
+                    deadline: None,
+                    referral_address: None,
+                    referral_commission: None,
                 };
 
                 self.app
@@ -439,6 +939,89 @@ impl Helper {
         }
     }
 
+    pub fn swap_with_deadline(
+        &mut self,
+        sender: &Addr,
+        offer_asset: &Asset,
+        deadline: Option<u64>,
+    ) -> AnyResult<AppResponse> {
+        let funds =
+            offer_asset.mock_coin_sent(&mut self.app, sender, &self.pair_addr, SendType::None);
+
+        let msg = ExecuteMsg::Swap {
+            offer_asset: offer_asset.clone(),
+            ask_asset_info: None,
+            belief_price: None,
+            max_spread: None,
+            to: None,
+            deadline,
+            referral_address: None,
+            referral_commission: None,
+        };
+
+        self.app
+            .execute_contract(sender.clone(), self.pair_addr.clone(), &msg, &funds)
+    }
+
+    pub fn swap_with_referral(
+        &mut self,
+        sender: &Addr,
+        offer_asset: &Asset,
+        referral_address: Option<&Addr>,
+        referral_commission: Option<Decimal>,
+    ) -> AnyResult<AppResponse> {
+        let funds =
+            offer_asset.mock_coin_sent(&mut self.app, sender, &self.pair_addr, SendType::None);
+
+        let msg = ExecuteMsg::Swap {
+            offer_asset: offer_asset.clone(),
+            ask_asset_info: None,
+            belief_price: None,
+            max_spread: None,
+            to: None,
+            deadline: None,
+            referral_address: referral_address.map(Addr::to_string),
+            referral_commission,
+        };
+
+        self.app
+            .execute_contract(sender.clone(), self.pair_addr.clone(), &msg, &funds)
+    }
+
+    pub fn simulate_swap_with_referral(
+        &self,
+        offer_asset: &Asset,
+        referral_address: Option<&Addr>,
+        referral_commission: Option<Decimal>,
+    ) -> StdResult<SimulationResponse> {
+        self.app.wrap().query_wasm_smart(
+            &self.pair_addr,
+            &OsmoQueryMsg::Simulation {
+                offer_asset: offer_asset.clone(),
+                ask_asset_info: None,
+                referral_address: referral_address.map(Addr::to_string),
+                referral_commission,
+            },
+        )
+    }
+
+    pub fn simulate_reverse_swap_with_referral(
+        &self,
+        ask_asset: &Asset,
+        referral_address: Option<&Addr>,
+        referral_commission: Option<Decimal>,
+    ) -> StdResult<ReverseSimulationResponse> {
+        self.app.wrap().query_wasm_smart(
+            &self.pair_addr,
+            &OsmoQueryMsg::ReverseSimulation {
+                offer_asset_info: None,
+                ask_asset: ask_asset.clone(),
+                referral_address: referral_address.map(Addr::to_string),
+                referral_commission,
+            },
+        )
+    }
+
     pub fn simulate_swap(
         &self,
         offer_asset: &Asset,
@@ -476,6 +1059,26 @@ impl Helper {
         self.app.execute(sender.clone(), msg.into())
     }
 
+    pub fn swap_exact_out(
+        &mut self,
+        sender: &Addr,
+        ask_asset: &Asset,
+        max_offer: &Asset,
+        to: Option<String>,
+    ) -> AnyResult<AppResponse> {
+        let funds =
+            max_offer.mock_coin_sent(&mut self.app, sender, &self.pair_addr, SendType::None);
+
+        let msg = ExecuteMsg::SwapExactOut {
+            ask_asset: ask_asset.clone(),
+            max_offer: max_offer.clone(),
+            to,
+        };
+
+        self.app
+            .execute_contract(sender.clone(), self.pair_addr.clone(), &msg, &funds)
+    }
+
     pub fn simulate_reverse_swap(
         &self,
         ask_asset: &Asset,
@@ -576,12 +1179,49 @@ impl Helper {
         from_json(binary)
     }
 
+    pub fn query_lp_metadata(&self) -> StdResult<Option<Metadata>> {
+        QueryDenomMetadataRequest {
+            denom: self.lp_token.clone(),
+        }
+        .query(&self.app.wrap())
+        .map(|resp| resp.metadata)
+    }
+
+    pub fn update_lp_token_metadata(
+        &mut self,
+        sender: &Addr,
+        metadata: LpTokenMetadata,
+    ) -> AnyResult<AppResponse> {
+        self.app.execute_contract(
+            sender.clone(),
+            self.pair_addr.clone(),
+            &ExecuteMsg::UpdateLpTokenMetadata { metadata },
+            &[],
+        )
+    }
+
     pub fn query_lp_price(&self) -> StdResult<Decimal256> {
         self.app
             .wrap()
             .query_wasm_smart(&self.pair_addr, &QueryMsg::LpPrice {})
     }
 
+    pub fn query_twap(&self, start_time: u64, end_time: Option<u64>) -> StdResult<Decimal256> {
+        self.app.wrap().query_wasm_smart(
+            &self.pair_addr,
+            &OsmoQueryMsg::Twap {
+                start_time,
+                end_time,
+            },
+        )
+    }
+
+    pub fn query_pool_state(&self) -> StdResult<PoolStateResponse> {
+        self.app
+            .wrap()
+            .query_wasm_smart(&self.pair_addr, &OsmoQueryMsg::PoolState {})
+    }
+
     pub fn query_asset_balance_at(
         &self,
         asset_info: &AssetInfo,
@@ -611,6 +1251,97 @@ impl Helper {
         )
     }
 
+    pub fn set_pause_flags(
+        &mut self,
+        sender: &Addr,
+        provide: Option<bool>,
+        swap: Option<bool>,
+        withdraw: Option<bool>,
+    ) -> AnyResult<AppResponse> {
+        self.app.execute_contract(
+            sender.clone(),
+            self.pair_addr.clone(),
+            &ExecuteMsg::SetPauseFlags {
+                provide,
+                swap,
+                withdraw,
+            },
+            &[],
+        )
+    }
+
+    pub fn query_pause_flags(&self) -> StdResult<PauseFlags> {
+        self.app
+            .wrap()
+            .query_wasm_smart(&self.pair_addr, &OsmoQueryMsg::PauseFlags {})
+    }
+
+    pub fn set_max_price_deviation(
+        &mut self,
+        sender: &Addr,
+        max_deviation: Option<Decimal>,
+    ) -> AnyResult<AppResponse> {
+        self.app.execute_contract(
+            sender.clone(),
+            self.pair_addr.clone(),
+            &ExecuteMsg::SetMaxPriceDeviation { max_deviation },
+            &[],
+        )
+    }
+
+    pub fn set_maker_fee_override(
+        &mut self,
+        sender: &Addr,
+        maker_fee_bps: Option<u16>,
+    ) -> AnyResult<AppResponse> {
+        self.app.execute_contract(
+            sender.clone(),
+            self.pair_addr.clone(),
+            &ExecuteMsg::SetMakerFeeOverride { maker_fee_bps },
+            &[],
+        )
+    }
+
+    pub fn query_swap_fee(&self) -> StdResult<Decimal> {
+        self.app
+            .wrap()
+            .query_wasm_smart::<GetSwapFeeResponse>(&self.pair_addr, &OsmoQueryMsg::GetSwapFee {})
+            .map(|resp| resp.swap_fee)
+    }
+
+    pub fn query_calc_out_amount(&self, token_in: &Coin, swap_fee: Decimal) -> StdResult<Uint128> {
+        self.app
+            .wrap()
+            .query_wasm_smart::<CalcOutAmtGivenInResponse>(
+                &self.pair_addr,
+                &OsmoQueryMsg::CalcOutAmtGivenIn {
+                    token_in: token_in.clone(),
+                    token_out_denom: "doesnt_matter".to_string(),
+                    swap_fee,
+                },
+            )
+            .map(|resp| resp.token_out.amount)
+    }
+
+    pub fn query_calc_in_amount(
+        &self,
+        token_out: &Coin,
+        token_in_denom: &str,
+        swap_fee: Decimal,
+    ) -> StdResult<Uint128> {
+        self.app
+            .wrap()
+            .query_wasm_smart::<CalcInAmtGivenOutResponse>(
+                &self.pair_addr,
+                &OsmoQueryMsg::CalcInAmtGivenOut {
+                    token_out: token_out.clone(),
+                    token_in_denom: token_in_denom.to_string(),
+                    swap_fee,
+                },
+            )
+            .map(|resp| resp.token_in.amount)
+    }
+
     pub fn query_amp_gamma(&self) -> StdResult<AmpGammaResponse> {
         let config_resp: ConfigResponse = self
             .app
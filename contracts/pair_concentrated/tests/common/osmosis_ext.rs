@@ -1,15 +1,20 @@
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::fmt::Debug;
+use std::rc::Rc;
 
 use anyhow::Result as AnyResult;
 use cosmwasm_schema::schemars::JsonSchema;
 use cosmwasm_schema::serde::de::DeserializeOwned;
 use cosmwasm_std::{
-    coin, coins, from_json, to_json_binary, Addr, Api, BankMsg, Binary, BlockInfo, CustomQuery,
-    Querier, QueryRequest, Storage, SubMsgResponse, Uint128, WasmMsg, WasmQuery,
+    coin, coins, from_json, to_json_binary, Addr, Api, BankMsg, Binary, BlockInfo, Coin,
+    CustomQuery, Decimal, Querier, QueryRequest, Storage, SubMsgResponse, Uint128, WasmMsg,
+    WasmQuery,
 };
 use cw_multi_test::{AppResponse, BankSudo, CosmosRouter, Stargate, WasmSudo};
+use osmosis_std::types::cosmos::bank::v1beta1::{
+    Metadata, QueryDenomMetadataRequest, QueryDenomMetadataResponse,
+};
 use osmosis_std::types::osmosis::cosmwasmpool::v1beta1::{
     ContractInfoByPoolIdRequest, ContractInfoByPoolIdResponse, MsgCreateCosmWasmPool,
     MsgCreateCosmWasmPoolResponse,
@@ -19,17 +24,84 @@ use osmosis_std::types::osmosis::poolmanager::v1beta1::{
     MsgSwapExactAmountIn, MsgSwapExactAmountOut,
 };
 use osmosis_std::types::osmosis::tokenfactory::v1beta1::{
-    MsgBurn, MsgCreateDenom, MsgCreateDenomResponse, MsgMint,
+    MsgBurn, MsgCreateDenom, MsgCreateDenomResponse, MsgMint, MsgSetDenomMetadata,
 };
 
 use astroport_on_osmosis::pair_pcl;
 use astroport_on_osmosis::pair_pcl::{
-    GetSwapFeeResponse, QueryMsg, SwapExactAmountOutResponseData,
+    GetSwapFeeResponse, QueryMsg, SwapExactAmountInResponseData, SwapExactAmountOutResponseData,
 };
 
+/// Receives Osmosis taker fees
+pub const COMMUNITY_POOL: &str = "community_pool";
+
 #[derive(Default)]
 pub struct OsmosisStargate {
     pub cw_pools: RefCell<HashMap<u64, String>>,
+    pub denom_metadata: RefCell<HashMap<String, Metadata>>,
+    /// Swap fees returned by GetSwapFee queries performed by the pool manager
+    pub queried_swap_fees: Rc<RefCell<Vec<Decimal>>>,
+    /// Taker fee which the pool manager charges from the swap sender
+    pub taker_fee: Rc<RefCell<Decimal>>,
+}
+
+impl OsmosisStargate {
+    fn query_swap_fee<ExecC, QueryC>(
+        &self,
+        api: &dyn Api,
+        storage: &dyn Storage,
+        router: &dyn CosmosRouter<ExecC = ExecC, QueryC = QueryC>,
+        block: &BlockInfo,
+        contract_addr: &Addr,
+    ) -> AnyResult<Decimal>
+    where
+        ExecC: Debug + Clone + PartialEq + JsonSchema + DeserializeOwned + 'static,
+        QueryC: CustomQuery + DeserializeOwned + 'static,
+    {
+        let res = router.query(
+            api,
+            storage,
+            block,
+            QueryRequest::Wasm(WasmQuery::Smart {
+                contract_addr: contract_addr.to_string(),
+                msg: to_json_binary(&QueryMsg::GetSwapFee {})?,
+            }),
+        )?;
+        let swap_fee = from_json::<GetSwapFeeResponse>(&res)?.swap_fee;
+        self.queried_swap_fees.borrow_mut().push(swap_fee);
+
+        Ok(swap_fee)
+    }
+
+    fn charge_taker_fee<ExecC, QueryC>(
+        &self,
+        api: &dyn Api,
+        storage: &mut dyn Storage,
+        router: &dyn CosmosRouter<ExecC = ExecC, QueryC = QueryC>,
+        block: &BlockInfo,
+        sender: &str,
+        fee: Coin,
+    ) -> AnyResult<()>
+    where
+        ExecC: Debug + Clone + PartialEq + JsonSchema + DeserializeOwned + 'static,
+        QueryC: CustomQuery + DeserializeOwned + 'static,
+    {
+        if !fee.amount.is_zero() {
+            router.execute(
+                api,
+                storage,
+                block,
+                Addr::unchecked(sender),
+                BankMsg::Send {
+                    to_address: COMMUNITY_POOL.to_string(),
+                    amount: vec![fee],
+                }
+                .into(),
+            )?;
+        }
+
+        Ok(())
+    }
 }
 
 impl Stargate for OsmosisStargate {
@@ -108,6 +180,26 @@ impl Stargate for OsmosisStargate {
                 };
                 Ok(submsg_response.into())
             }
+            MsgSetDenomMetadata::TYPE_URL => {
+                let tf_msg: MsgSetDenomMetadata = value.try_into()?;
+                let metadata = tf_msg
+                    .metadata
+                    .expect("Empty metadata in MsgSetDenomMetadata!");
+                if !metadata
+                    .base
+                    .starts_with(&format!("factory/{}/", tf_msg.sender))
+                {
+                    return Err(anyhow::anyhow!(
+                        "{} is not the admin of {}",
+                        tf_msg.sender,
+                        metadata.base
+                    ));
+                }
+                self.denom_metadata
+                    .borrow_mut()
+                    .insert(metadata.base.clone(), metadata);
+                Ok(AppResponse::default())
+            }
             MsgMint::TYPE_URL => {
                 let tf_msg: MsgMint = value.try_into()?;
                 let mint_coins = tf_msg
@@ -138,33 +230,68 @@ impl Stargate for OsmosisStargate {
             MsgSwapExactAmountIn::TYPE_URL => {
                 let pm_msg: MsgSwapExactAmountIn = value.try_into()?;
                 let token_in = pm_msg.token_in.expect("token_in must be set!");
+                let mut token_in = coin(token_in.amount.parse()?, token_in.denom);
 
-                let contract_addr =
-                    Addr::unchecked(&self.cw_pools.borrow()[&pm_msg.routes[0].pool_id]);
+                let token_out_min_amount: Uint128 = pm_msg.token_out_min_amount.parse()?;
+                let last_hop = pm_msg.routes.len() - 1;
 
-                // Osmosis always performs this query before calling a contract.
-                let res = router
-                    .query(
+                // Each hop output is the input of the next hop
+                let mut resp = AppResponse::default();
+                for (hop, route) in pm_msg.routes.iter().enumerate() {
+                    let contract_addr = Addr::unchecked(&self.cw_pools.borrow()[&route.pool_id]);
+
+                    // Osmosis always performs this query before calling a contract.
+                    let swap_fee =
+                        self.query_swap_fee(api, storage, router, block, &contract_addr)?;
+
+                    // Taker fee is deducted from the input of each hop
+                    let taker_fee = token_in.amount * *self.taker_fee.borrow();
+                    self.charge_taker_fee(
                         api,
                         storage,
+                        router,
                         block,
-                        QueryRequest::Wasm(WasmQuery::Smart {
-                            contract_addr: contract_addr.to_string(),
-                            msg: to_json_binary(&QueryMsg::GetSwapFee {}).unwrap(),
-                        }),
-                    )
-                    .unwrap();
+                        &pm_msg.sender,
+                        coin(taker_fee.u128(), &token_in.denom),
+                    )?;
+                    token_in.amount -= taker_fee;
 
-                let inner_contract_msg = pair_pcl::SudoMessage::SwapExactAmountIn {
-                    sender: pm_msg.sender.to_string(),
-                    token_in: coin(token_in.amount.parse()?, token_in.denom),
-                    token_out_denom: pm_msg.routes[0].token_out_denom.clone(),
-                    token_out_min_amount: pm_msg.token_out_min_amount.parse()?,
-                    swap_fee: from_json::<GetSwapFeeResponse>(&res)?.swap_fee,
-                };
+                    // Send funds from sender to contract
+                    router.execute(
+                        api,
+                        storage,
+                        block,
+                        Addr::unchecked(&pm_msg.sender),
+                        BankMsg::Send {
+                            to_address: contract_addr.to_string(),
+                            amount: vec![token_in.clone()],
+                        }
+                        .into(),
+                    )?;
 
-                let wasm_sudo_msg = WasmSudo::new(&contract_addr, &inner_contract_msg)?;
-                router.sudo(api, storage, block, wasm_sudo_msg.into())
+                    let inner_contract_msg = pair_pcl::SudoMessage::SwapExactAmountIn {
+                        sender: pm_msg.sender.to_string(),
+                        token_in: token_in.clone(),
+                        token_out_denom: route.token_out_denom.clone(),
+                        // Like Osmosis, apply min amount only to the last hop
+                        token_out_min_amount: if hop == last_hop {
+                            token_out_min_amount
+                        } else {
+                            Uint128::one()
+                        },
+                        swap_fee,
+                    };
+
+                    let wasm_sudo_msg = WasmSudo::new(&contract_addr, &inner_contract_msg)?;
+                    resp = router.sudo(api, storage, block, wasm_sudo_msg.into())?;
+
+                    let raw = resp.data.clone().expect("Data must be set in response");
+                    let token_out_amount =
+                        from_json::<SwapExactAmountInResponseData>(&raw)?.token_out_amount;
+                    token_in = coin(token_out_amount.u128(), &route.token_out_denom);
+                }
+
+                Ok(resp)
             }
             MsgSwapExactAmountOut::TYPE_URL => {
                 let pm_msg: MsgSwapExactAmountOut = value.try_into()?;
@@ -174,17 +301,7 @@ impl Stargate for OsmosisStargate {
                     Addr::unchecked(&self.cw_pools.borrow()[&pm_msg.routes[0].pool_id]);
 
                 // Osmosis always performs this query before calling a contract.
-                let res = router
-                    .query(
-                        api,
-                        storage,
-                        block,
-                        QueryRequest::Wasm(WasmQuery::Smart {
-                            contract_addr: contract_addr.to_string(),
-                            msg: to_json_binary(&QueryMsg::GetSwapFee {}).unwrap(),
-                        }),
-                    )
-                    .unwrap();
+                let swap_fee = self.query_swap_fee(api, storage, router, block, &contract_addr)?;
 
                 let token_in_denom = pm_msg.routes[0].token_in_denom.clone();
                 let token_in_max_amount: Uint128 = pm_msg.token_in_max_amount.parse()?;
@@ -194,7 +311,7 @@ impl Stargate for OsmosisStargate {
                     token_in_denom: token_in_denom.clone(),
                     token_in_max_amount,
                     token_out: coin(token_out.amount.parse()?, token_out.denom),
-                    swap_fee: from_json::<GetSwapFeeResponse>(&res)?.swap_fee,
+                    swap_fee,
                 };
 
                 router.execute(
@@ -233,11 +350,24 @@ impl Stargate for OsmosisStargate {
                             Addr::unchecked(contract_addr),
                             BankMsg::Send {
                                 to_address: pm_msg.sender.to_string(),
-                                amount: coins(excess_tokens.u128(), token_in_denom),
+                                amount: coins(excess_tokens.u128(), &token_in_denom),
                             }
                             .into(),
                         )?;
                     }
+
+                    // Taker fee is charged from the sender on top of the swap input
+                    let taker_fee = token_in_amount
+                        .checked_div_ceil(Decimal::one() - *self.taker_fee.borrow())?
+                        - token_in_amount;
+                    self.charge_taker_fee(
+                        api,
+                        storage,
+                        router,
+                        block,
+                        &pm_msg.sender,
+                        coin(taker_fee.u128(), &token_in_denom),
+                    )?;
                 }
 
                 resp
@@ -266,6 +396,12 @@ impl Stargate for OsmosisStargate {
                     code_id: 0,
                 })?)
             }
+            "/cosmos.bank.v1beta1.Query/DenomMetadata" => {
+                let inner: QueryDenomMetadataRequest = data.try_into()?;
+                Ok(to_json_binary(&QueryDenomMetadataResponse {
+                    metadata: self.denom_metadata.borrow().get(&inner.denom).cloned(),
+                })?)
+            }
             "/osmosis.poolmanager.v1beta1.Query/Params" => {
                 Ok(to_json_binary(&poolmanager::v1beta1::ParamsResponse {
                     params: Some(poolmanager::v1beta1::Params {
@@ -7,22 +7,27 @@ use astroport::asset::{
 };
 use astroport::cosmwasm_ext::AbsDiff;
 use astroport::observation::OracleObservation;
-use astroport::pair::{ExecuteMsg, PoolResponse};
+use astroport::pair::{ExecuteMsg, PoolResponse, MAX_FEE_SHARE_BPS};
 use astroport::pair_concentrated::{
     ConcentratedPoolParams, ConcentratedPoolUpdateParams, PromoteParams, UpdatePoolParams,
 };
 use astroport_pcl_common::consts::{AMP_MAX, AMP_MIN, MA_HALF_TIME_LIMITS};
 use astroport_pcl_common::error::PclError;
-use cosmwasm_std::{Addr, Decimal, StdError, Uint128};
+use cosmwasm_std::{coin, Addr, Decimal, Decimal256, Event, StdError, Uint128};
 use cw_multi_test::{next_block, Executor};
 use itertools::Itertools;
 
 use astroport_on_osmosis::pair_pcl::{
-    CalcInAmtGivenOutResponse, CalcOutAmtGivenInResponse, QueryMsg, SpotPriceResponse,
-    TotalPoolLiquidityResponse,
+    CalcInAmtGivenOutResponse, CalcOutAmtGivenInResponse, ConcentratedPoolInitParams,
+    ExecuteMsg as OsmoExecuteMsg, GetSwapFeeResponse, IsActiveResponse, LpTokenMetadata,
+    PauseFlags, QueryMsg, SpotPriceResponse, SudoMessage, SwapHookMsg, SwapRouteHop,
+    TotalPoolLiquidityResponse, ZapInSwap, MAX_LP_TOKEN_METADATA_LENGTH,
 };
+use astroport_pcl_osmo::contract::MAX_TWAP_WINDOW;
 use astroport_pcl_osmo::error::ContractError;
-use common::helper::{dec_to_f64, f64_to_dec, AppExtension, Helper, TestCoin};
+use astroport_pcl_osmo::state::POOL_ID;
+use common::helper::{dec_to_f64, f64_to_dec, AppExtension, Helper, MockFlashSwapAction, TestCoin};
+use common::osmosis_ext::COMMUNITY_POOL;
 
 mod common;
 
@@ -378,19 +383,6 @@ fn provide_and_withdraw() {
         helper.native_balance(&helper.lp_token, &user6)
     );
 
-    // check that imbalanced withdraw is currently disabled
-    let withdraw_assets = vec![
-        helper.assets[&test_coins[0]].with_balance(10_000_000000u128),
-        helper.assets[&test_coins[1]].with_balance(5_000_000000u128),
-    ];
-    let err = helper
-        .withdraw_liquidity(&user2, 7071_067711, withdraw_assets)
-        .unwrap_err();
-    assert_eq!(
-        err.root_cause().to_string(),
-        "Generic error: Imbalanced withdraw is currently disabled"
-    );
-
     // user2 withdraws 1/10 of his LP tokens
     helper
         .withdraw_liquidity(&user2, 7071_067711, vec![])
@@ -416,6 +408,298 @@ fn provide_and_withdraw() {
     assert_eq!(26653_440612, helper.coin_balance(&test_coins[1], &user3));
 }
 
+#[test]
+fn check_imbalanced_withdraw() {
+    let owner = Addr::unchecked("owner");
+
+    let test_coins = vec![TestCoin::native("uosmo"), TestCoin::native("uusd")];
+
+    let params = ConcentratedPoolParams {
+        track_asset_balances: Some(true),
+        ..common_pcl_params()
+    };
+    let mut helper = Helper::new(&owner, test_coins.clone(), params).unwrap();
+
+    let assets = vec![
+        helper.assets[&test_coins[0]].with_balance(100_000_000000u128),
+        helper.assets[&test_coins[1]].with_balance(100_000_000000u128),
+    ];
+    helper.provide_liquidity(&owner, &assets).unwrap();
+
+    let user = Addr::unchecked("user");
+    helper.give_me_money(&assets, &user);
+    helper.provide_liquidity(&user, &assets).unwrap();
+    let lp_amount = helper.native_balance(&helper.lp_token, &user);
+
+    // Asset which does not belong to the pair
+    let random_coin = native_asset_info("random-coin".to_string()).with_balance(100u8);
+    let err = helper
+        .withdraw_liquidity(&user, lp_amount, vec![random_coin.clone()])
+        .unwrap_err();
+    assert_eq!(
+        ContractError::InvalidAsset(random_coin.info.to_string()),
+        err.downcast().unwrap()
+    );
+
+    // Imbalanced withdraw can't empty one side of the pool
+    let err = helper
+        .withdraw_liquidity(
+            &user,
+            lp_amount,
+            vec![helper.assets[&test_coins[1]].with_balance(200_000_000000u128)],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.root_cause().to_string(),
+        "Generic error: Imbalanced withdraw can not drain the pool"
+    );
+
+    // Withdraw only uusd
+    let withdraw_assets = vec![helper.assets[&test_coins[1]].with_balance(10_000_000000u128)];
+    let needed_lp = helper
+        .query_imbalanced_withdraw(&withdraw_assets)
+        .unwrap()
+        .u128();
+    // Balanced withdraw of the same value costs 5000 LP tokens. One-sided withdraw is more expensive.
+    assert!(
+        needed_lp > 5000_000000 && needed_lp < 5100_000000,
+        "unexpected LP amount {needed_lp}"
+    );
+
+    let err = helper
+        .withdraw_liquidity(&user, needed_lp - 1, withdraw_assets.clone())
+        .unwrap_err();
+    assert_eq!(
+        ContractError::InsufficientWithdrawShare {
+            needed: needed_lp.into(),
+            sent: (needed_lp - 1).into(),
+        },
+        err.downcast().unwrap()
+    );
+
+    let lp_price_before = helper.query_lp_price().unwrap();
+
+    // Send all LP tokens. Unused LP tokens must be returned
+    helper
+        .withdraw_liquidity(&user, lp_amount, withdraw_assets)
+        .unwrap();
+    assert_eq!(
+        lp_amount - needed_lp,
+        helper.native_balance(&helper.lp_token, &user)
+    );
+    assert_eq!(0, helper.coin_balance(&test_coins[0], &user));
+    assert_eq!(10_000_000000, helper.coin_balance(&test_coins[1], &user));
+
+    // Withdraw fee stays in the pool thus LP price can only grow
+    let lp_price_after = helper.query_lp_price().unwrap();
+    assert!(lp_price_after >= lp_price_before);
+
+    helper.app.next_block(10);
+
+    let res = helper
+        .query_asset_balance_at(
+            &helper.assets[&test_coins[1]],
+            helper.app.block_info().height,
+        )
+        .unwrap();
+    assert_eq!(res.unwrap().u128(), 190_000_000000);
+
+    // Withdraw both assets in an arbitrary proportion
+    let withdraw_assets = vec![
+        helper.assets[&test_coins[1]].with_balance(1_000_000000u128),
+        helper.assets[&test_coins[0]].with_balance(3_000_000000u128),
+    ];
+    let lp_amount = helper.native_balance(&helper.lp_token, &user);
+    helper
+        .withdraw_liquidity(&user, lp_amount, withdraw_assets)
+        .unwrap();
+    assert_eq!(3_000_000000, helper.coin_balance(&test_coins[0], &user));
+    assert_eq!(11_000_000000, helper.coin_balance(&test_coins[1], &user));
+}
+
+#[test]
+fn check_imbalanced_withdraw_provide_round_trip() {
+    let owner = Addr::unchecked("owner");
+
+    let test_coins = vec![TestCoin::native("uosmo"), TestCoin::native("uusd")];
+
+    let mut helper = Helper::new(&owner, test_coins.clone(), common_pcl_params()).unwrap();
+
+    let assets = vec![
+        helper.assets[&test_coins[0]].with_balance(100_000_000000u128),
+        helper.assets[&test_coins[1]].with_balance(100_000_000000u128),
+    ];
+    helper.provide_liquidity(&owner, &assets).unwrap();
+
+    let user = Addr::unchecked("user");
+    helper.give_me_money(&assets, &user);
+    helper.provide_liquidity(&user, &assets).unwrap();
+
+    // Providing back the withdrawn assets can't mint more LP tokens than were burned
+    for withdraw_assets in [
+        vec![helper.assets[&test_coins[1]].with_balance(10_000_000000u128)],
+        vec![helper.assets[&test_coins[0]].with_balance(1_000000u128)],
+        vec![
+            helper.assets[&test_coins[0]].with_balance(5_000_000000u128),
+            helper.assets[&test_coins[1]].with_balance(1_000_000000u128),
+        ],
+        vec![
+            helper.assets[&test_coins[0]].with_balance(1_000_000000u128),
+            helper.assets[&test_coins[1]].with_balance(1_000_000000u128),
+        ],
+    ] {
+        let lp_before = helper.native_balance(&helper.lp_token, &user);
+        let needed_lp = helper
+            .query_imbalanced_withdraw(&withdraw_assets)
+            .unwrap()
+            .u128();
+        helper
+            .withdraw_liquidity(&user, needed_lp, withdraw_assets.clone())
+            .unwrap();
+        let lp_after_withdraw = helper.native_balance(&helper.lp_token, &user);
+        let burned = lp_before - lp_after_withdraw;
+
+        helper
+            .provide_liquidity_with_slip_tolerance(
+                &user,
+                &withdraw_assets,
+                Some(Decimal::percent(50)),
+            )
+            .unwrap();
+        let minted = helper.native_balance(&helper.lp_token, &user) - lp_after_withdraw;
+        assert!(minted <= burned, "minted {minted} > burned {burned}");
+    }
+}
+
+#[test]
+fn check_single_asset_withdraw() {
+    let owner = Addr::unchecked("owner");
+
+    let test_coins = vec![TestCoin::native("uosmo"), TestCoin::native("uusd")];
+
+    let params = ConcentratedPoolParams {
+        track_asset_balances: Some(true),
+        ..common_pcl_params()
+    };
+    let mut helper = Helper::new(&owner, test_coins.clone(), params).unwrap();
+
+    let assets = vec![
+        helper.assets[&test_coins[0]].with_balance(100_000_000000u128),
+        helper.assets[&test_coins[1]].with_balance(100_000_000000u128),
+    ];
+    helper.provide_liquidity(&owner, &assets).unwrap();
+
+    let user = Addr::unchecked("user");
+    helper.give_me_money(&assets, &user);
+    helper.provide_liquidity(&user, &assets).unwrap();
+    let lp_amount = helper.native_balance(&helper.lp_token, &user);
+
+    let uusd = helper.assets[&test_coins[1]].clone();
+
+    let err = helper
+        .withdraw_single_asset(
+            &user,
+            lp_amount,
+            &native_asset_info("random-coin".to_string()),
+            None,
+        )
+        .unwrap_err();
+    assert_eq!(
+        ContractError::InvalidAsset("random-coin".to_string()),
+        err.downcast().unwrap()
+    );
+
+    let err = helper
+        .app
+        .execute_contract(
+            user.clone(),
+            helper.pair_addr.clone(),
+            &OsmoExecuteMsg::WithdrawLiquidity {
+                assets: vec![uusd.with_balance(1_000000u128)],
+                receiver: None,
+                min_assets_to_receive: None,
+                single_asset: Some(uusd.clone()),
+            },
+            &[coin(lp_amount, &helper.lp_token)],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.root_cause().to_string(),
+        "Generic error: Single asset withdraw can not be combined with imbalanced withdraw"
+    );
+
+    let burn_amount = 5000_000000u128;
+    let expected = helper
+        .query_single_asset_withdraw(burn_amount, &uusd)
+        .unwrap();
+    // Balanced withdraw of the same LP amount gives 5000 uosmo and 5000 uusd. One-sided withdraw is charged with fees.
+    assert!(
+        expected.u128() > 9_800_000000 && expected.u128() < 10_000_000000,
+        "unexpected withdraw amount {expected}"
+    );
+    // Single asset withdraw is consistent with imbalanced withdraw
+    let imbalanced_lp = helper
+        .query_imbalanced_withdraw(&[uusd.with_balance(expected)])
+        .unwrap()
+        .u128();
+    assert!(
+        imbalanced_lp.abs_diff(burn_amount) <= burn_amount / 1000,
+        "imbalanced withdraw requires {imbalanced_lp} LP tokens"
+    );
+
+    let err = helper
+        .withdraw_single_asset(
+            &user,
+            burn_amount,
+            &uusd,
+            Some(vec![uusd.with_balance(expected + Uint128::one())]),
+        )
+        .unwrap_err();
+    assert_eq!(
+        ContractError::WithdrawSlippageViolation {
+            asset_name: uusd.to_string(),
+            received: expected,
+            expected: expected + Uint128::one(),
+        },
+        err.downcast().unwrap()
+    );
+
+    let lp_price_before = helper.query_lp_price().unwrap();
+
+    helper
+        .withdraw_single_asset(
+            &user,
+            burn_amount,
+            &uusd,
+            Some(vec![uusd.with_balance(expected)]),
+        )
+        .unwrap();
+    assert_eq!(
+        lp_amount - burn_amount,
+        helper.native_balance(&helper.lp_token, &user)
+    );
+    assert_eq!(0, helper.coin_balance(&test_coins[0], &user));
+    assert_eq!(expected.u128(), helper.coin_balance(&test_coins[1], &user));
+
+    // Withdraw fee stays in the pool thus LP price can only grow
+    let lp_price_after = helper.query_lp_price().unwrap();
+    assert!(lp_price_after >= lp_price_before);
+
+    helper.app.next_block(10);
+
+    let res = helper
+        .query_asset_balance_at(&uusd, helper.app.block_info().height)
+        .unwrap();
+    assert_eq!(res.unwrap().u128(), 200_000_000000 - expected.u128());
+    let res = helper
+        .query_asset_balance_at(
+            &helper.assets[&test_coins[0]],
+            helper.app.block_info().height,
+        )
+        .unwrap();
+    assert_eq!(res.unwrap().u128(), 200_000_000000);
+}
+
 #[test]
 fn check_imbalanced_provide() {
     let owner = Addr::unchecked("owner");
@@ -587,33 +871,96 @@ fn check_reverse_swap() {
 }
 
 #[test]
-fn check_swaps_simple() {
+fn check_swap_exact_out() {
     let owner = Addr::unchecked("owner");
 
     let test_coins = vec![TestCoin::native("uosmo"), TestCoin::native("uusd")];
 
     let mut helper = Helper::new(&owner, test_coins.clone(), common_pcl_params()).unwrap();
 
+    let assets = vec![
+        helper.assets[&test_coins[0]].with_balance(100_000_000000u128),
+        helper.assets[&test_coins[1]].with_balance(100_000_000000u128),
+    ];
+    helper.provide_liquidity(&owner, &assets).unwrap();
+
     let user = Addr::unchecked("user");
-    let offer_asset = helper.assets[&test_coins[0]].with_balance(100_000000u128);
-    helper.give_me_money(&[offer_asset.clone()], &user);
+    let ask_asset = helper.assets[&test_coins[1]].with_balance(100_000000u128);
+    let max_offer = helper.assets[&test_coins[0]].with_balance(110_000000u128);
+    helper.give_me_money(&[max_offer.clone()], &user);
 
-    // Check swap does not work if pool is empty
-    let err = helper.swap(&user, &offer_asset, None).unwrap_err();
+    // Max offer is not enough
+    let err = helper
+        .swap_exact_out(
+            &user,
+            &ask_asset,
+            &helper.assets[&test_coins[0]].with_balance(90_000000u128),
+            None,
+        )
+        .unwrap_err();
+    assert!(err
+        .root_cause()
+        .to_string()
+        .starts_with("Generic error: Not enough tokens to perform swap."));
+
+    // Swap to the same asset
+    let err = helper
+        .swap_exact_out(
+            &user,
+            &helper.assets[&test_coins[0]].with_balance(100_000000u128),
+            &max_offer,
+            None,
+        )
+        .unwrap_err();
     assert_eq!(
         err.root_cause().to_string(),
-        "Generic error: One of the pools is empty"
+        "Generic error: Invalid swap: uosmo to uosmo"
     );
 
-    // Try to swap a wrong asset
-    let wrong_coin = native_asset_info("random-coin".to_string());
-    let wrong_asset = wrong_coin.with_balance(100_000000u128);
-    helper.give_me_money(&[wrong_asset.clone()], &user);
-    let err = helper.swap(&user, &wrong_asset, None).unwrap_err();
+    let pools_before = helper
+        .app
+        .wrap()
+        .query_wasm_smart::<PoolResponse>(&helper.pair_addr, &QueryMsg::Pool {})
+        .unwrap()
+        .assets;
+
+    let receiver = Addr::unchecked("receiver");
+    helper
+        .swap_exact_out(&user, &ask_asset, &max_offer, Some(receiver.to_string()))
+        .unwrap();
+
     assert_eq!(
-        ContractError::InvalidAsset(wrong_coin.to_string()),
-        err.downcast().unwrap()
+        helper.coin_balance(&test_coins[1], &receiver),
+        ask_asset.amount.u128()
+    );
+    assert_eq!(helper.coin_balance(&test_coins[1], &user), 0);
+
+    // Unused offer amount was refunded to the sender
+    let refunded = helper.coin_balance(&test_coins[0], &user);
+    assert!(refunded > 0);
+    let spent = max_offer.amount.u128() - refunded;
+
+    // Excess tokens don't stay in the pool
+    let pools_after = helper
+        .app
+        .wrap()
+        .query_wasm_smart::<PoolResponse>(&helper.pair_addr, &QueryMsg::Pool {})
+        .unwrap()
+        .assets;
+    assert_eq!(
+        pools_after[0].amount.u128(),
+        pools_before[0].amount.u128() + spent
     );
+    assert!(pools_after[1].amount < pools_before[1].amount - ask_asset.amount);
+}
+
+#[test]
+fn check_swap_exact_out_with_taker_fee() {
+    let owner = Addr::unchecked("owner");
+
+    let test_coins = vec![TestCoin::native("uosmo"), TestCoin::native("uusd")];
+
+    let mut helper = Helper::new(&owner, test_coins.clone(), common_pcl_params()).unwrap();
 
     let assets = vec![
         helper.assets[&test_coins[0]].with_balance(100_000_000000u128),
@@ -621,26 +968,100 @@ fn check_swaps_simple() {
     ];
     helper.provide_liquidity(&owner, &assets).unwrap();
 
-    let d = helper.query_d().unwrap();
-    assert_eq!(dec_to_f64(d), 200000f64);
+    *helper.taker_fee.borrow_mut() = Decimal::permille(1);
 
-    assert_eq!(0, helper.coin_balance(&test_coins[1], &user));
-    helper.swap(&user, &offer_asset, None).unwrap();
-    assert_eq!(0, helper.coin_balance(&test_coins[0], &user));
-    assert_eq!(99_737929, helper.coin_balance(&test_coins[1], &user));
+    let user = Addr::unchecked("user");
+    let ask_asset = helper.assets[&test_coins[1]].with_balance(100_000000u128);
+    let max_offer = helper.assets[&test_coins[0]].with_balance(110_000000u128);
+    helper.give_me_money(&[max_offer.clone()], &user);
+
+    let pair_balance_before = helper.coin_balance(&test_coins[0], &helper.pair_addr);
+    let resp = helper
+        .swap_exact_out(&user, &ask_asset, &max_offer, None)
+        .unwrap();
+    let offer_amount: u128 = resp
+        .events
+        .iter()
+        .flat_map(|event| &event.attributes)
+        .find(|attr| attr.key == "offer_amount")
+        .unwrap()
+        .value
+        .parse()
+        .unwrap();
 
-    let offer_asset = helper.assets[&test_coins[0]].with_balance(90_000_000000u128);
-    helper.give_me_money(&[offer_asset.clone()], &user);
-    let err = helper.swap(&user, &offer_asset, None).unwrap_err();
     assert_eq!(
-        ContractError::PclError(PclError::MaxSpreadAssertion {}),
-        err.downcast().unwrap()
+        helper.coin_balance(&test_coins[1], &user),
+        ask_asset.amount.u128()
     );
 
-    let user2 = Addr::unchecked("user2");
-    let offer_asset = helper.assets[&test_coins[1]].with_balance(100_000000u128);
-    helper.give_me_money(&[offer_asset.clone()], &user2);
-    helper.swap(&user2, &offer_asset, None).unwrap();
+    // The taker fee is paid from the unused offer amount, not from the pool
+    let taker_fee = helper.native_balance("uosmo", &Addr::unchecked(COMMUNITY_POOL));
+    assert!(taker_fee > 0);
+    assert_eq!(
+        helper.coin_balance(&test_coins[0], &helper.pair_addr),
+        pair_balance_before + offer_amount
+    );
+    assert_eq!(
+        helper.coin_balance(&test_coins[0], &user),
+        max_offer.amount.u128() - offer_amount - taker_fee
+    );
+}
+
+#[test]
+fn check_swaps_simple() {
+    let owner = Addr::unchecked("owner");
+
+    let test_coins = vec![TestCoin::native("uosmo"), TestCoin::native("uusd")];
+
+    let mut helper = Helper::new(&owner, test_coins.clone(), common_pcl_params()).unwrap();
+
+    let user = Addr::unchecked("user");
+    let offer_asset = helper.assets[&test_coins[0]].with_balance(100_000000u128);
+    helper.give_me_money(&[offer_asset.clone()], &user);
+
+    // Check swap does not work if pool is empty
+    let err = helper.swap(&user, &offer_asset, None).unwrap_err();
+    assert_eq!(
+        err.root_cause().to_string(),
+        "Generic error: One of the pools is empty"
+    );
+
+    // Try to swap a wrong asset
+    let wrong_coin = native_asset_info("random-coin".to_string());
+    let wrong_asset = wrong_coin.with_balance(100_000000u128);
+    helper.give_me_money(&[wrong_asset.clone()], &user);
+    let err = helper.swap(&user, &wrong_asset, None).unwrap_err();
+    assert_eq!(
+        ContractError::InvalidAsset(wrong_coin.to_string()),
+        err.downcast().unwrap()
+    );
+
+    let assets = vec![
+        helper.assets[&test_coins[0]].with_balance(100_000_000000u128),
+        helper.assets[&test_coins[1]].with_balance(100_000_000000u128),
+    ];
+    helper.provide_liquidity(&owner, &assets).unwrap();
+
+    let d = helper.query_d().unwrap();
+    assert_eq!(dec_to_f64(d), 200000f64);
+
+    assert_eq!(0, helper.coin_balance(&test_coins[1], &user));
+    helper.swap(&user, &offer_asset, None).unwrap();
+    assert_eq!(0, helper.coin_balance(&test_coins[0], &user));
+    assert_eq!(99_737929, helper.coin_balance(&test_coins[1], &user));
+
+    let offer_asset = helper.assets[&test_coins[0]].with_balance(90_000_000000u128);
+    helper.give_me_money(&[offer_asset.clone()], &user);
+    let err = helper.swap(&user, &offer_asset, None).unwrap_err();
+    assert_eq!(
+        ContractError::PclError(PclError::MaxSpreadAssertion {}),
+        err.downcast().unwrap()
+    );
+
+    let user2 = Addr::unchecked("user2");
+    let offer_asset = helper.assets[&test_coins[1]].with_balance(100_000000u128);
+    helper.give_me_money(&[offer_asset.clone()], &user2);
+    helper.swap(&user2, &offer_asset, None).unwrap();
     assert_eq!(0, helper.coin_balance(&test_coins[1], &user2));
     assert_eq!(99_741246, helper.coin_balance(&test_coins[0], &user2));
 
@@ -679,6 +1100,47 @@ fn check_swaps_simple() {
     );
 }
 
+#[test]
+fn check_swap_deadline() {
+    let owner = Addr::unchecked("owner");
+
+    let test_coins = vec![TestCoin::native("uosmo"), TestCoin::native("uusd")];
+
+    let mut helper = Helper::new(&owner, test_coins.clone(), common_pcl_params()).unwrap();
+
+    let assets = vec![
+        helper.assets[&test_coins[0]].with_balance(100_000_000000u128),
+        helper.assets[&test_coins[1]].with_balance(100_000_000000u128),
+    ];
+    helper.provide_liquidity(&owner, &assets).unwrap();
+
+    let user = Addr::unchecked("user");
+    let offer_asset = helper.assets[&test_coins[0]].with_balance(100_000000u128);
+    helper.give_me_money(&[offer_asset.clone()], &user);
+
+    let block_time = helper.app.block_info().time.seconds();
+    let err = helper
+        .swap_with_deadline(&user, &offer_asset, Some(block_time - 1))
+        .unwrap_err();
+    assert_eq!(
+        ContractError::DeadlineExpired {
+            deadline: block_time - 1,
+            block_time
+        },
+        err.downcast().unwrap()
+    );
+    // Swap params must not leak into the next swap
+    helper
+        .swap_with_deadline(&user, &offer_asset, None)
+        .unwrap();
+
+    helper.give_me_money(&[offer_asset.clone()], &user);
+    helper
+        .swap_with_deadline(&user, &offer_asset, Some(block_time))
+        .unwrap();
+    assert_eq!(0, helper.coin_balance(&test_coins[0], &user));
+}
+
 #[test]
 fn check_swaps_with_price_update() {
     let owner = Addr::unchecked("owner");
@@ -1355,6 +1817,160 @@ fn provide_withdraw_slippage() {
         .unwrap();
 }
 
+#[test]
+fn provide_with_min_lp_and_deadline() {
+    let owner = Addr::unchecked("owner");
+
+    let test_coins = vec![TestCoin::native("uosmo"), TestCoin::native("uusd")];
+
+    let mut helper = Helper::new(&owner, test_coins.clone(), common_pcl_params()).unwrap();
+
+    let provide_assets = [
+        helper.assets[&test_coins[0]].with_balance(100_000_000000u128),
+        helper.assets[&test_coins[1]].with_balance(100_000_000000u128),
+    ];
+    helper.provide_liquidity(&owner, &provide_assets).unwrap();
+
+    let user1 = Addr::unchecked("user1");
+    let assets = [
+        helper.assets[&test_coins[0]].with_balance(10_000000u128),
+        helper.assets[&test_coins[1]].with_balance(10_000000u128),
+    ];
+    helper.give_me_money(&assets, &user1);
+
+    let block_time = helper.app.block_info().time.seconds();
+    let err = helper
+        .provide_liquidity_full(
+            &user1,
+            &assets,
+            None,
+            None,
+            None,
+            None,
+            Some(block_time - 1),
+        )
+        .unwrap_err();
+    assert_eq!(
+        ContractError::DeadlineExpired {
+            deadline: block_time - 1,
+            block_time
+        },
+        err.downcast().unwrap()
+    );
+
+    let err = helper
+        .provide_liquidity_full(
+            &user1,
+            &assets,
+            None,
+            None,
+            None,
+            Some(Uint128::new(100_000000)),
+            Some(block_time),
+        )
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::ProvideSlippageViolation(_, expected) if expected == Uint128::new(100_000000)
+    ));
+
+    helper
+        .provide_liquidity_full(
+            &user1,
+            &assets,
+            None,
+            None,
+            None,
+            Some(Uint128::new(9_000000)),
+            Some(block_time),
+        )
+        .unwrap();
+    assert!(helper.native_balance(&helper.lp_token, &user1) >= 9_000000);
+}
+
+#[test]
+fn withdraw_with_receiver_and_min_assets() {
+    let owner = Addr::unchecked("owner");
+
+    let test_coins = vec![TestCoin::native("uosmo"), TestCoin::native("uusd")];
+
+    let mut helper = Helper::new(&owner, test_coins.clone(), common_pcl_params()).unwrap();
+
+    let provide_assets = [
+        helper.assets[&test_coins[0]].with_balance(100_000_000000u128),
+        helper.assets[&test_coins[1]].with_balance(100_000_000000u128),
+    ];
+    helper.provide_liquidity(&owner, &provide_assets).unwrap();
+
+    let user1 = Addr::unchecked("user1");
+    let assets = [
+        helper.assets[&test_coins[0]].with_balance(10_000000u128),
+        helper.assets[&test_coins[1]].with_balance(10_000000u128),
+    ];
+    helper.give_me_money(&assets, &user1);
+    helper.provide_liquidity(&user1, &assets).unwrap();
+
+    let lp_amount = helper.native_balance(&helper.lp_token, &user1);
+    let share = helper.query_share(lp_amount).unwrap();
+
+    // Asset which doesn't belong to the pool
+    let err = helper
+        .withdraw_liquidity_full(
+            &user1,
+            lp_amount,
+            vec![],
+            None,
+            Some(vec![
+                native_asset_info("random-coin".to_string()).with_balance(1u8)
+            ]),
+        )
+        .unwrap_err();
+    assert_eq!(
+        ContractError::InvalidAsset("random-coin".to_string()),
+        err.downcast().unwrap()
+    );
+
+    // Too high minimum amount
+    let err = helper
+        .withdraw_liquidity_full(
+            &user1,
+            lp_amount,
+            vec![],
+            None,
+            Some(vec![share[1]
+                .info
+                .with_balance(share[1].amount + Uint128::one())]),
+        )
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::WithdrawSlippageViolation { asset_name, expected, .. }
+            if asset_name == share[1].info.to_string() && expected == share[1].amount + Uint128::one()
+    ));
+
+    let user2 = Addr::unchecked("user2");
+    let min_assets = share
+        .iter()
+        .map(|asset| asset.info.with_balance(asset.amount * Decimal::percent(99)))
+        .collect_vec();
+    helper
+        .withdraw_liquidity_full(
+            &user1,
+            lp_amount,
+            vec![],
+            Some(user2.to_string()),
+            Some(min_assets.clone()),
+        )
+        .unwrap();
+
+    // All assets were sent to the receiver
+    assert_eq!(helper.native_balance(&helper.lp_token, &user1), 0);
+    assert_eq!(helper.coin_balance(&test_coins[0], &user1), 0);
+    assert_eq!(helper.coin_balance(&test_coins[1], &user1), 0);
+    assert!(helper.coin_balance(&test_coins[0], &user2) >= min_assets[0].amount.u128());
+    assert!(helper.coin_balance(&test_coins[1], &user2) >= min_assets[1].amount.u128());
+}
+
 #[test]
 fn test_frontrun_before_initial_provide() {
     let owner = Addr::unchecked("owner");
@@ -1475,6 +2091,9 @@ fn test_osmosis_specific_queries() {
         .collect_vec();
     assert_eq!(osm_liq, astro_liq);
 
+    // Osmosis passes the pool swap fee into simulation queries
+    let swap_fee = helper.query_swap_fee().unwrap();
+
     let osm_resp = helper
         .app
         .wrap()
@@ -1483,7 +2102,7 @@ fn test_osmosis_specific_queries() {
             &QueryMsg::CalcOutAmtGivenIn {
                 token_in: offer_asset.as_coin().unwrap(),
                 token_out_denom: "doesnt_matter".to_string(),
-                swap_fee: Default::default(),
+                swap_fee,
             },
         )
         .unwrap();
@@ -1498,7 +2117,7 @@ fn test_osmosis_specific_queries() {
             &QueryMsg::CalcInAmtGivenOut {
                 token_out: offer_asset.as_coin().unwrap(),
                 token_in_denom: offer_asset.as_coin().unwrap().denom,
-                swap_fee: Default::default(),
+                swap_fee,
             },
         )
         .unwrap();
@@ -1554,65 +2173,1332 @@ fn test_osmosis_specific_queries() {
 }
 
 #[test]
-fn test_spot_price_diff_decimals() {
+fn check_zap_in() {
     let owner = Addr::unchecked("owner");
 
-    let test_coins = vec![TestCoin::native("uusd"), TestCoin::native("eth")];
+    let test_coins = vec![TestCoin::native("uusd"), TestCoin::native("rc")];
 
-    let mut helper = Helper::new(
-        &owner,
-        test_coins.clone(),
-        ConcentratedPoolParams {
-            price_scale: Decimal::from_ratio(3000u16, 1u8),
-            ..common_pcl_params()
-        },
-    )
-    .unwrap();
+    let mut helper = Helper::new(&owner, test_coins.clone(), common_pcl_params()).unwrap();
 
     let provide_assets = [
-        helper.assets[&test_coins[0]].with_balance(3_000_000 * 1e6 as u128),
-        helper.assets[&test_coins[1]].with_balance(1_000 * 1e18 as u128),
+        helper.assets[&test_coins[0]].with_balance(100_000_000000u128),
+        helper.assets[&test_coins[1]].with_balance(100_000_000000u128),
     ];
-    helper.give_me_money(&provide_assets, &owner);
     helper.provide_liquidity(&owner, &provide_assets).unwrap();
 
-    let resp = helper
-        .app
-        .wrap()
-        .query_wasm_smart::<SpotPriceResponse>(
-            &helper.pair_addr,
-            &QueryMsg::SpotPrice {
-                quote_asset_denom: helper.assets[&test_coins[0]].to_string(),
-                base_asset_denom: helper.assets[&test_coins[1]].to_string(),
-            },
-        )
-        .unwrap();
-    assert_eq!(resp.spot_price.to_string(), "0.000000003000010176");
+    // Create auxiliary uosmo pools to route zap-in swaps
+    let mut aux_pool_ids = vec![];
+    for denom in ["uusd", "rc"] {
+        let asset_infos = vec![
+            native_asset_info("uosmo".to_string()),
+            native_asset_info(denom.to_string()),
+        ];
+        let (aux_pair, aux_pool_id) = helper
+            .create_pair(asset_infos.clone(), &common_pcl_params())
+            .unwrap();
+        let assets = asset_infos
+            .iter()
+            .map(|info| info.with_balance(10_000_000000u128))
+            .collect_vec();
+        helper
+            .app
+            .execute_contract(
+                owner.clone(),
+                aux_pair,
+                &OsmoExecuteMsg::ProvideLiquidity {
+                    assets: assets.clone(),
+                    slippage_tolerance: None,
+                    auto_stake: None,
+                    receiver: None,
+                    min_lp_to_receive: None,
+                    deadline: None,
+                },
+                &assets.iter().map(|a| a.as_coin().unwrap()).collect_vec(),
+            )
+            .unwrap();
+        aux_pool_ids.push(aux_pool_id);
+    }
 
-    // query inverted price
-    let price = helper
-        .app
-        .wrap()
-        .query_wasm_smart::<SpotPriceResponse>(
-            &helper.pair_addr,
-            &QueryMsg::SpotPrice {
-                quote_asset_denom: helper.assets[&test_coins[1]].to_string(),
-                base_asset_denom: helper.assets[&test_coins[0]].to_string(),
-            },
-        )
+    let user = Addr::unchecked("user");
+    let coin_in = coin(20_000000, "uosmo");
+    helper.give_me_money(
+        &[native_asset_info("uosmo".to_string()).with_balance(coin_in.amount)],
+        &user,
+    );
+    let pool_id = POOL_ID
+        .query(&helper.app.wrap(), helper.pair_addr.clone())
         .unwrap();
-    assert_eq!(price.spot_price.to_string(), "333334464.080626");
-}
+    let zap_swap = |amount: u128, pool_id: u64, denom: &str| ZapInSwap {
+        amount: amount.into(),
+        route: vec![SwapRouteHop {
+            pool_id,
+            token_out_denom: denom.to_string(),
+        }],
+    };
 
-#[test]
-fn test_truncated_spot_price() {
-    let owner = Addr::unchecked("owner");
+    let err = helper
+        .zap_in(
+            &user,
+            coin_in.clone(),
+            vec![zap_swap(20_000000, aux_pool_ids[0], "uusd")],
+            Uint128::zero(),
+        )
+        .unwrap_err();
+    assert_eq!(ContractError::ZapInMinLpNotSet {}, err.downcast().unwrap());
 
-    let test_coins = vec![TestCoin::native("uusd"), TestCoin::native("eth")];
+    // Pool asset can't be used for zap-in
+    let err = helper
+        .zap_in(
+            &owner,
+            coin(1_000000, "uusd"),
+            vec![zap_swap(1_000000, aux_pool_ids[1], "rc")],
+            Uint128::one(),
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.root_cause().to_string(),
+        "Generic error: Zap-in coin uusd must not belong to the pool. Use provide instead"
+    );
 
-    let mut helper = Helper::new(
-        &owner,
-        test_coins.clone(),
+    let err = helper
+        .zap_in(
+            &user,
+            coin_in.clone(),
+            vec![zap_swap(10_000000, aux_pool_ids[0], "uusd")],
+            Uint128::one(),
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.root_cause().to_string(),
+        "Generic error: Sum of zap-in swap amounts must be equal to the sent amount"
+    );
+
+    let err = helper
+        .zap_in(
+            &user,
+            coin_in.clone(),
+            vec![
+                zap_swap(10_000000, aux_pool_ids[0], "uusd"),
+                zap_swap(10_000000, aux_pool_ids[0], "uusd"),
+            ],
+            Uint128::one(),
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.root_cause().to_string(),
+        "Generic error: Each zap-in route must end with a distinct pool asset. Got uusd"
+    );
+
+    let err = helper
+        .zap_in(
+            &user,
+            coin_in.clone(),
+            vec![zap_swap(20_000000, pool_id, "uusd")],
+            Uint128::one(),
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.root_cause().to_string(),
+        "Generic error: Zap-in route must not include this pool"
+    );
+
+    let swaps = vec![
+        zap_swap(10_000000, aux_pool_ids[0], "uusd"),
+        zap_swap(10_000000, aux_pool_ids[1], "rc"),
+    ];
+
+    // Min LP amount is not satisfied
+    let err = helper
+        .zap_in(
+            &user,
+            coin_in.clone(),
+            swaps.clone(),
+            Uint128::new(100_000000),
+        )
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::ProvideSlippageViolation(..)
+    ));
+
+    helper
+        .zap_in(&user, coin_in.clone(), swaps, Uint128::new(19_000000))
+        .unwrap();
+
+    let lp_amount = helper.native_balance(&helper.lp_token, &user);
+    assert!(lp_amount >= 19_000000);
+    assert_eq!(helper.native_balance("uosmo", &user), 0);
+    // The pair doesn't keep any leftovers
+    assert_eq!(helper.native_balance("uosmo", &helper.pair_addr), 0);
+
+    // One-sided zap-in
+    helper.give_me_money(
+        &[native_asset_info("uosmo".to_string()).with_balance(coin_in.amount)],
+        &user,
+    );
+    helper
+        .zap_in(
+            &user,
+            coin_in,
+            vec![zap_swap(20_000000, aux_pool_ids[1], "rc")],
+            Uint128::one(),
+        )
+        .unwrap();
+    let lp_amount_after = helper.native_balance(&helper.lp_token, &user);
+    assert!(lp_amount_after > lp_amount);
+
+    // Multi-hop zap-in uosmo -> random-coin -> rc
+    let mut multi_hop_pool_ids = vec![];
+    for (denom_a, denom_b) in [("uosmo", "random-coin"), ("random-coin", "rc")] {
+        let asset_infos = vec![
+            native_asset_info(denom_a.to_string()),
+            native_asset_info(denom_b.to_string()),
+        ];
+        let (aux_pair, aux_pool_id) = helper
+            .create_pair(asset_infos.clone(), &common_pcl_params())
+            .unwrap();
+        let assets = asset_infos
+            .iter()
+            .map(|info| info.with_balance(10_000_000000u128))
+            .collect_vec();
+        helper
+            .app
+            .execute_contract(
+                owner.clone(),
+                aux_pair,
+                &OsmoExecuteMsg::ProvideLiquidity {
+                    assets: assets.clone(),
+                    slippage_tolerance: None,
+                    auto_stake: None,
+                    receiver: None,
+                    min_lp_to_receive: None,
+                    deadline: None,
+                },
+                &assets.iter().map(|a| a.as_coin().unwrap()).collect_vec(),
+            )
+            .unwrap();
+        multi_hop_pool_ids.push(aux_pool_id);
+    }
+    let multi_hop_swap = ZapInSwap {
+        amount: coin_in.amount,
+        route: vec![
+            SwapRouteHop {
+                pool_id: multi_hop_pool_ids[0],
+                token_out_denom: "random-coin".to_string(),
+            },
+            SwapRouteHop {
+                pool_id: multi_hop_pool_ids[1],
+                token_out_denom: "rc".to_string(),
+            },
+        ],
+    };
+
+    helper.give_me_money(
+        &[native_asset_info("uosmo".to_string()).with_balance(coin_in.amount)],
+        &user,
+    );
+    // Min LP amount protects against slippage in all hops
+    let err = helper
+        .zap_in(
+            &user,
+            coin_in.clone(),
+            vec![multi_hop_swap.clone()],
+            Uint128::new(100_000000),
+        )
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::ProvideSlippageViolation(..)
+    ));
+
+    helper
+        .zap_in(&user, coin_in, vec![multi_hop_swap], Uint128::new(9_000000))
+        .unwrap();
+    assert!(helper.native_balance(&helper.lp_token, &user) >= lp_amount_after + 9_000000);
+    // Intermediate coins are not left on the pair
+    assert_eq!(helper.native_balance("random-coin", &helper.pair_addr), 0);
+}
+
+#[test]
+fn provide_with_auto_stake() {
+    let owner = Addr::unchecked("owner");
+
+    let test_coins = vec![TestCoin::native("uosmo"), TestCoin::native("uusd")];
+
+    let mut helper = Helper::new(&owner, test_coins.clone(), common_pcl_params()).unwrap();
+
+    let provide_assets = [
+        helper.assets[&test_coins[0]].with_balance(100_000_000000u128),
+        helper.assets[&test_coins[1]].with_balance(100_000_000000u128),
+    ];
+    helper.provide_liquidity(&owner, &provide_assets).unwrap();
+
+    let user1 = Addr::unchecked("user1");
+    let assets = [
+        helper.assets[&test_coins[0]].with_balance(100_000000u128),
+        helper.assets[&test_coins[1]].with_balance(100_000000u128),
+    ];
+    helper.give_me_money(&assets, &user1);
+
+    // Generator address is not set in the factory
+    let err = helper
+        .provide_liquidity_full(&user1, &assets, None, Some(true), None, None, None)
+        .unwrap_err();
+    assert_eq!(ContractError::AutoStakeError {}, err.downcast().unwrap());
+
+    let incentives = helper.setup_incentives().unwrap();
+
+    helper.give_me_money(&assets, &user1);
+    helper
+        .provide_liquidity_full(&user1, &assets, None, Some(true), None, None, None)
+        .unwrap();
+
+    // LP tokens are staked on behalf of the user
+    assert_eq!(helper.native_balance(&helper.lp_token, &user1), 0);
+    let user1_deposit = helper
+        .query_incentives_deposit(&incentives, &user1)
+        .unwrap();
+    assert!(!user1_deposit.is_zero());
+    assert_eq!(
+        helper.native_balance(&helper.lp_token, &incentives),
+        user1_deposit.u128()
+    );
+    // Pair contract holds only locked minimum liquidity
+    assert_eq!(
+        helper.native_balance(&helper.lp_token, &helper.pair_addr),
+        MINIMUM_LIQUIDITY_AMOUNT.u128()
+    );
+
+    // Stake on behalf of another receiver
+    let user2 = Addr::unchecked("user2");
+    helper.give_me_money(&assets, &user1);
+    helper
+        .provide_liquidity_full(
+            &user1,
+            &assets,
+            None,
+            Some(true),
+            Some(user2.to_string()),
+            None,
+            None,
+        )
+        .unwrap();
+
+    assert_eq!(helper.native_balance(&helper.lp_token, &user2), 0);
+    let user2_deposit = helper
+        .query_incentives_deposit(&incentives, &user2)
+        .unwrap();
+    assert!(!user2_deposit.is_zero());
+    assert_eq!(
+        helper
+            .query_incentives_deposit(&incentives, &user1)
+            .unwrap(),
+        user1_deposit
+    );
+    assert_eq!(
+        helper.native_balance(&helper.lp_token, &incentives),
+        (user1_deposit + user2_deposit).u128()
+    );
+}
+
+#[test]
+fn check_fee_share() {
+    let owner = Addr::unchecked("owner");
+
+    let test_coins = vec![TestCoin::native("uosmo"), TestCoin::native("uusd")];
+
+    let mut helper = Helper::new(&owner, test_coins.clone(), common_pcl_params()).unwrap();
+
+    let provide_assets = [
+        helper.assets[&test_coins[0]].with_balance(100_000_000000u128),
+        helper.assets[&test_coins[1]].with_balance(100_000_000000u128),
+    ];
+    helper.provide_liquidity(&owner, &provide_assets).unwrap();
+
+    let share_recipient = Addr::unchecked("share_recipient");
+    let enable_msg = ConcentratedPoolUpdateParams::EnableFeeShare {
+        fee_share_bps: 1000,
+        fee_share_address: share_recipient.to_string(),
+    };
+
+    let err = helper
+        .update_config(&Addr::unchecked("random"), &enable_msg)
+        .unwrap_err();
+    assert_eq!(ContractError::Unauthorized {}, err.downcast().unwrap());
+
+    for fee_share_bps in [0, MAX_FEE_SHARE_BPS + 1] {
+        let err = helper
+            .update_config(
+                &owner,
+                &ConcentratedPoolUpdateParams::EnableFeeShare {
+                    fee_share_bps,
+                    fee_share_address: share_recipient.to_string(),
+                },
+            )
+            .unwrap_err();
+        assert_eq!(
+            ContractError::FeeShareOutOfBounds {},
+            err.downcast().unwrap()
+        );
+    }
+
+    helper.update_config(&owner, &enable_msg).unwrap();
+    let fee_share = helper.query_config().unwrap().fee_share.unwrap();
+    assert_eq!(fee_share.bps, 1000);
+    assert_eq!(fee_share.recipient, share_recipient);
+
+    let user = Addr::unchecked("user");
+    let offer_asset = helper.assets[&test_coins[0]].with_balance(100_000000u128);
+    helper.give_me_money(&[offer_asset.clone()], &user);
+
+    let sim_resp = helper.simulate_swap(&offer_asset, None).unwrap();
+    let resp = helper.swap(&user, &offer_asset, None).unwrap();
+    assert_eq!(
+        sim_resp.return_amount.u128(),
+        helper.coin_balance(&test_coins[1], &user)
+    );
+
+    let share_amount = helper.coin_balance(&test_coins[1], &share_recipient);
+    assert!(share_amount > 0);
+    // 10% of the total fee goes to the share recipient
+    assert!(share_amount.abs_diff(sim_resp.commission_amount.u128() / 10) <= 1);
+
+    let attr_share_amount = resp
+        .events
+        .iter()
+        .flat_map(|event| &event.attributes)
+        .find(|attr| attr.key == "fee_share_amount")
+        .map(|attr| attr.value.clone())
+        .unwrap();
+    assert_eq!(attr_share_amount, share_amount.to_string());
+
+    // Reverse swap also shares fees
+    let ask_asset = helper.assets[&test_coins[0]].with_balance(50_000000u128);
+    let offer_asset = helper.assets[&test_coins[1]].with_balance(100_000000u128);
+    helper.give_me_money(&[offer_asset.clone()], &user);
+    helper
+        .reverse_swap(&user, &ask_asset, &offer_asset)
+        .unwrap();
+    assert!(helper.coin_balance(&test_coins[0], &share_recipient) > 0);
+
+    helper
+        .update_config(&owner, &ConcentratedPoolUpdateParams::DisableFeeShare)
+        .unwrap();
+    assert_eq!(helper.query_config().unwrap().fee_share, None);
+
+    let offer_asset = helper.assets[&test_coins[0]].with_balance(100_000000u128);
+    helper.give_me_money(&[offer_asset.clone()], &user);
+    helper.swap(&user, &offer_asset, None).unwrap();
+    assert_eq!(
+        share_amount,
+        helper.coin_balance(&test_coins[1], &share_recipient)
+    );
+}
+
+#[test]
+fn check_pool_deactivation() {
+    let owner = Addr::unchecked("owner");
+
+    let test_coins = vec![TestCoin::native("uosmo"), TestCoin::native("uusd")];
+
+    let mut helper = Helper::new(&owner, test_coins.clone(), common_pcl_params()).unwrap();
+
+    let provide_assets = [
+        helper.assets[&test_coins[0]].with_balance(100_000_000000u128),
+        helper.assets[&test_coins[1]].with_balance(100_000_000000u128),
+    ];
+    helper.provide_liquidity(&owner, &provide_assets).unwrap();
+
+    let query_is_active = |helper: &Helper| {
+        helper
+            .app
+            .wrap()
+            .query_wasm_smart::<IsActiveResponse>(&helper.pair_addr, &QueryMsg::IsActive {})
+            .unwrap()
+            .is_active
+    };
+    assert!(query_is_active(&helper));
+
+    helper
+        .app
+        .wasm_sudo(
+            helper.pair_addr.clone(),
+            &SudoMessage::SetActive { is_active: false },
+        )
+        .unwrap();
+    assert!(!query_is_active(&helper));
+
+    let user = Addr::unchecked("user");
+    let offer_asset = helper.assets[&test_coins[0]].with_balance(100_000000u128);
+    helper.give_me_money(&[offer_asset.clone()], &user);
+
+    let err = helper.swap(&user, &offer_asset, None).unwrap_err();
+    assert_eq!(ContractError::PoolIsNotActive {}, err.downcast().unwrap());
+
+    let ask_asset = helper.assets[&test_coins[1]].with_balance(50_000000u128);
+    let err = helper
+        .reverse_swap(&user, &ask_asset, &offer_asset)
+        .unwrap_err();
+    assert_eq!(ContractError::PoolIsNotActive {}, err.downcast().unwrap());
+
+    // Only balanced withdraw is possible
+    let lp_amount = helper.native_balance(&helper.lp_token, &owner);
+    let err = helper
+        .withdraw_liquidity(&owner, lp_amount / 2, vec![offer_asset.clone()])
+        .unwrap_err();
+    assert_eq!(ContractError::PoolIsNotActive {}, err.downcast().unwrap());
+    let err = helper
+        .withdraw_single_asset(&owner, lp_amount / 2, &offer_asset.info, None)
+        .unwrap_err();
+    assert_eq!(ContractError::PoolIsNotActive {}, err.downcast().unwrap());
+    helper
+        .withdraw_liquidity(&owner, lp_amount / 2, vec![])
+        .unwrap();
+
+    helper
+        .app
+        .wasm_sudo(
+            helper.pair_addr.clone(),
+            &SudoMessage::SetActive { is_active: true },
+        )
+        .unwrap();
+    assert!(query_is_active(&helper));
+
+    helper.swap(&user, &offer_asset, None).unwrap();
+    assert_eq!(0, helper.coin_balance(&test_coins[0], &user));
+}
+
+#[test]
+fn check_pause_flags() {
+    let owner = Addr::unchecked("owner");
+
+    let test_coins = vec![TestCoin::native("uosmo"), TestCoin::native("uusd")];
+
+    let mut helper = Helper::new(&owner, test_coins.clone(), common_pcl_params()).unwrap();
+
+    let provide_assets = [
+        helper.assets[&test_coins[0]].with_balance(100_000_000000u128),
+        helper.assets[&test_coins[1]].with_balance(100_000_000000u128),
+    ];
+    helper.provide_liquidity(&owner, &provide_assets).unwrap();
+
+    assert_eq!(helper.query_pause_flags().unwrap(), PauseFlags::default());
+
+    let user = Addr::unchecked("user");
+    let err = helper
+        .set_pause_flags(&user, Some(true), None, None)
+        .unwrap_err();
+    assert_eq!(ContractError::Unauthorized {}, err.downcast().unwrap());
+
+    // Factory owner acts as the pair owner while the latter is not set
+    helper
+        .set_pause_flags(&owner, Some(true), Some(true), None)
+        .unwrap();
+    assert_eq!(
+        helper.query_pause_flags().unwrap(),
+        PauseFlags {
+            provide: true,
+            swap: true,
+            withdraw: false,
+        }
+    );
+
+    helper.give_me_money(&provide_assets, &user);
+    let err = helper
+        .provide_liquidity(&user, &provide_assets)
+        .unwrap_err();
+    assert_eq!(ContractError::ProvidePaused {}, err.downcast().unwrap());
+
+    let offer_asset = helper.assets[&test_coins[0]].with_balance(100_000000u128);
+    let err = helper.swap(&user, &offer_asset, None).unwrap_err();
+    assert_eq!(ContractError::SwapPaused {}, err.downcast().unwrap());
+
+    let ask_asset = helper.assets[&test_coins[1]].with_balance(50_000000u128);
+    let err = helper
+        .reverse_swap(&user, &ask_asset, &offer_asset)
+        .unwrap_err();
+    assert_eq!(ContractError::SwapPaused {}, err.downcast().unwrap());
+
+    // Withdraw is still possible
+    let lp_amount = helper.native_balance(&helper.lp_token, &owner);
+    helper
+        .withdraw_liquidity(&owner, lp_amount / 4, vec![])
+        .unwrap();
+
+    // Imbalanced and single asset withdrawals trade against the pool, thus they are paused along with swaps
+    let err = helper
+        .withdraw_liquidity(&owner, lp_amount / 4, vec![offer_asset.clone()])
+        .unwrap_err();
+    assert_eq!(ContractError::SwapPaused {}, err.downcast().unwrap());
+    let err = helper
+        .withdraw_single_asset(&owner, lp_amount / 4, &offer_asset.info, None)
+        .unwrap_err();
+    assert_eq!(ContractError::SwapPaused {}, err.downcast().unwrap());
+
+    helper
+        .set_pause_flags(&owner, None, None, Some(true))
+        .unwrap();
+    let err = helper
+        .withdraw_liquidity(&owner, lp_amount / 4, vec![])
+        .unwrap_err();
+    assert_eq!(ContractError::WithdrawPaused {}, err.downcast().unwrap());
+
+    // Once the pair owner is set, only they can change pause flags
+    let pair_owner = Addr::unchecked("pair_owner");
+    helper
+        .app
+        .execute_contract(
+            owner.clone(),
+            helper.pair_addr.clone(),
+            &OsmoExecuteMsg::ProposeNewOwner {
+                owner: pair_owner.to_string(),
+                expires_in: 100,
+            },
+            &[],
+        )
+        .unwrap();
+    helper
+        .app
+        .execute_contract(
+            pair_owner.clone(),
+            helper.pair_addr.clone(),
+            &OsmoExecuteMsg::ClaimOwnership {},
+            &[],
+        )
+        .unwrap();
+    let err = helper
+        .set_pause_flags(&owner, Some(false), Some(false), Some(false))
+        .unwrap_err();
+    assert_eq!(ContractError::Unauthorized {}, err.downcast().unwrap());
+    helper
+        .set_pause_flags(&pair_owner, Some(false), Some(false), Some(false))
+        .unwrap();
+    assert_eq!(helper.query_pause_flags().unwrap(), PauseFlags::default());
+
+    helper.provide_liquidity(&user, &provide_assets).unwrap();
+    helper.swap(&user, &offer_asset, None).unwrap();
+    helper
+        .withdraw_liquidity(&owner, lp_amount / 4, vec![])
+        .unwrap();
+}
+
+#[test]
+fn check_max_price_deviation() {
+    let owner = Addr::unchecked("owner");
+
+    let test_coins = vec![TestCoin::native("uosmo"), TestCoin::native("uusd")];
+
+    let mut helper = Helper::new(&owner, test_coins.clone(), common_pcl_params()).unwrap();
+
+    let provide_assets = [
+        helper.assets[&test_coins[0]].with_balance(100_000_000000u128),
+        helper.assets[&test_coins[1]].with_balance(100_000_000000u128),
+    ];
+    helper.provide_liquidity(&owner, &provide_assets).unwrap();
+
+    let user = Addr::unchecked("user");
+    let err = helper
+        .set_max_price_deviation(&user, Some(Decimal::percent(1)))
+        .unwrap_err();
+    assert_eq!(ContractError::Unauthorized {}, err.downcast().unwrap());
+
+    let err = helper
+        .set_max_price_deviation(&owner, Some(Decimal::zero()))
+        .unwrap_err();
+    assert_eq!(
+        ContractError::InvalidMaxPriceDeviation {},
+        err.downcast().unwrap()
+    );
+
+    helper
+        .set_max_price_deviation(&owner, Some(Decimal::percent(1)))
+        .unwrap();
+    let max_deviation: Option<Decimal> = helper
+        .app
+        .wrap()
+        .query_wasm_smart(&helper.pair_addr, &QueryMsg::MaxPriceDeviation {})
+        .unwrap();
+    assert_eq!(max_deviation, Some(Decimal::percent(1)));
+
+    // Small trade doesn't move the price much
+    let offer_asset = helper.assets[&test_coins[0]].with_balance(100_000000u128);
+    helper.give_me_money(&[offer_asset.clone()], &user);
+    helper.swap(&user, &offer_asset, None).unwrap();
+
+    // Large trade pushes the price far away from the oracle price
+    let offer_asset = helper.assets[&test_coins[0]].with_balance(30_000_000000u128);
+    helper.give_me_money(&[offer_asset.clone()], &user);
+    let err = helper.swap(&user, &offer_asset, None).unwrap_err();
+    assert!(matches!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::PriceDeviationTooHigh { .. }
+    ));
+
+    let ask_asset = helper.assets[&test_coins[1]].with_balance(25_000_000000u128);
+    let err = helper
+        .reverse_swap(&user, &ask_asset, &offer_asset)
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::PriceDeviationTooHigh { .. }
+    ));
+
+    // Imbalanced provide moves the price as well
+    let err = helper
+        .provide_liquidity_with_slip_tolerance(
+            &user,
+            &[offer_asset.clone()],
+            Some(Decimal::percent(50)),
+        )
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::PriceDeviationTooHigh { .. }
+    ));
+    let err = helper
+        .simulate_provide(&[offer_asset.clone()], Some(Decimal::percent(50)))
+        .unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("Post-trade price deviates from the oracle price"),
+        "{err}"
+    );
+
+    // Imbalanced and single asset withdrawals are checked too
+    let uusd = helper.assets[&test_coins[1]].clone();
+    let lp_amount = helper.native_balance(&helper.lp_token, &owner);
+    let err = helper
+        .withdraw_liquidity(
+            &owner,
+            lp_amount / 2,
+            vec![uusd.with_balance(30_000_000000u128)],
+        )
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::PriceDeviationTooHigh { .. }
+    ));
+    let err = helper
+        .withdraw_single_asset(&owner, lp_amount / 3, &uusd, None)
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::PriceDeviationTooHigh { .. }
+    ));
+
+    // Balanced withdraw doesn't move the price
+    helper
+        .withdraw_liquidity(&owner, lp_amount / 10, vec![])
+        .unwrap();
+
+    // Disable the check
+    helper.set_max_price_deviation(&owner, None).unwrap();
+    helper.swap(&user, &offer_asset, None).unwrap();
+
+    // Balanced provide doesn't update the price, thus it is allowed
+    // even though the last price deviates from the oracle price
+    helper
+        .set_max_price_deviation(&owner, Some(Decimal::percent(1)))
+        .unwrap();
+    let balanced_assets = helper
+        .app
+        .wrap()
+        .query_wasm_smart::<PoolResponse>(&helper.pair_addr, &QueryMsg::Pool {})
+        .unwrap()
+        .assets
+        .into_iter()
+        .map(|asset| Asset {
+            amount: asset.amount / Uint128::new(100),
+            ..asset
+        })
+        .collect_vec();
+    helper.simulate_provide(&balanced_assets, None).unwrap();
+    helper.give_me_money(&balanced_assets, &user);
+    helper.provide_liquidity(&user, &balanced_assets).unwrap();
+}
+
+#[test]
+fn check_dynamic_swap_fee() {
+    let owner = Addr::unchecked("owner");
+
+    let test_coins = vec![TestCoin::native("uosmo"), TestCoin::native("uusd")];
+
+    let params = common_pcl_params();
+    let mut helper = Helper::new(&owner, test_coins.clone(), params.clone()).unwrap();
+
+    let query_swap_fee = |helper: &Helper| {
+        helper
+            .app
+            .wrap()
+            .query_wasm_smart::<GetSwapFeeResponse>(&helper.pair_addr, &QueryMsg::GetSwapFee {})
+            .unwrap()
+            .swap_fee
+    };
+
+    // Empty pool reports mid fee
+    assert_eq!(query_swap_fee(&helper), params.mid_fee);
+
+    let provide_assets = [
+        helper.assets[&test_coins[0]].with_balance(100_000_000000u128),
+        helper.assets[&test_coins[1]].with_balance(100_000_000000u128),
+    ];
+    helper.provide_liquidity(&owner, &provide_assets).unwrap();
+
+    // Balanced pool charges mid fee
+    assert_eq!(query_swap_fee(&helper), params.mid_fee);
+
+    let user = Addr::unchecked("user");
+    let offer_asset = helper.assets[&test_coins[0]].with_balance(50_000_000000u128);
+    helper.give_me_money(&[offer_asset.clone()], &user);
+    helper.swap(&user, &offer_asset, None).unwrap();
+
+    // Imbalanced pool charges more, but never more than out fee
+    let swap_fee = query_swap_fee(&helper);
+    assert!(swap_fee > params.mid_fee, "{swap_fee}");
+    assert!(swap_fee <= params.out_fee, "{swap_fee}");
+}
+
+#[test]
+fn check_external_swap_fee() {
+    let owner = Addr::unchecked("owner");
+
+    let test_coins = vec![TestCoin::native("uosmo"), TestCoin::native("uusd")];
+
+    let mut helper = Helper::new(&owner, test_coins.clone(), common_pcl_params()).unwrap();
+
+    let provide_assets = [
+        helper.assets[&test_coins[0]].with_balance(100_000_000000u128),
+        helper.assets[&test_coins[1]].with_balance(100_000_000000u128),
+    ];
+    helper.provide_liquidity(&owner, &provide_assets).unwrap();
+
+    let pool_fee = helper.query_swap_fee().unwrap();
+    let offer_coin = coin(1_000_000000, "uosmo");
+
+    // Pool fee or higher doesn't change the result
+    let astro_resp = helper
+        .simulate_swap(
+            &helper.assets[&test_coins[0]].with_balance(1_000_000000u128),
+            None,
+        )
+        .unwrap();
+    let full_fee_out = helper.query_calc_out_amount(&offer_coin, pool_fee).unwrap();
+    assert_eq!(full_fee_out, astro_resp.return_amount);
+    let higher_fee_out = helper
+        .query_calc_out_amount(&offer_coin, Decimal::percent(10))
+        .unwrap();
+    assert_eq!(higher_fee_out, full_fee_out);
+
+    // Discounted fee scales LP fees down proportionally
+    let half_fee = pool_fee / Uint128::new(2);
+    let half_fee_out = helper.query_calc_out_amount(&offer_coin, half_fee).unwrap();
+    let zero_fee_out = helper
+        .query_calc_out_amount(&offer_coin, Decimal::zero())
+        .unwrap();
+    assert!(full_fee_out < half_fee_out && half_fee_out < zero_fee_out);
+    let full_fee = zero_fee_out - full_fee_out;
+    let discounted_fee = zero_fee_out - half_fee_out;
+    assert!(
+        (full_fee / Uint128::new(2)).abs_diff(discounted_fee) <= Uint128::one(),
+        "{full_fee} {discounted_fee}"
+    );
+
+    // Maker fee is never discounted, thus zero swap fee waives only the LP part of the fee.
+    // Maker fee share is 50% in this setup.
+    let maker_fee = astro_resp.commission_amount / Uint128::new(2);
+    assert!(
+        full_fee.abs_diff(astro_resp.commission_amount - maker_fee) <= Uint128::one(),
+        "{full_fee} {maker_fee}"
+    );
+
+    // Execution through sudo matches the simulation with the same swap fee
+    let user = Addr::unchecked("user");
+    helper.give_me_money(
+        &[helper.assets[&test_coins[0]].with_balance(1_000_000000u128)],
+        &user,
+    );
+    helper
+        .app
+        .send_tokens(
+            user.clone(),
+            helper.pair_addr.clone(),
+            &[offer_coin.clone()],
+        )
+        .unwrap();
+    helper
+        .app
+        .wasm_sudo(
+            helper.pair_addr.clone(),
+            &SudoMessage::SwapExactAmountIn {
+                sender: user.to_string(),
+                token_in: offer_coin.clone(),
+                token_out_denom: "uusd".to_string(),
+                token_out_min_amount: Uint128::one(),
+                swap_fee: half_fee,
+            },
+        )
+        .unwrap();
+    assert_eq!(
+        helper.coin_balance(&test_coins[1], &user),
+        half_fee_out.u128()
+    );
+    let maker_balance = helper.coin_balance(&test_coins[1], &helper.fake_maker);
+    assert!(
+        maker_balance.abs_diff(maker_fee.u128()) <= 1,
+        "{maker_balance} {maker_fee}"
+    );
+
+    // Same for exact out swaps
+    let pool_fee = helper.query_swap_fee().unwrap();
+    let half_fee = pool_fee / Uint128::new(2);
+    let token_out = coin(500_000000, "uosmo");
+    let full_fee_in = helper
+        .query_calc_in_amount(&token_out, "uusd", pool_fee)
+        .unwrap();
+    let half_fee_in = helper
+        .query_calc_in_amount(&token_out, "uusd", half_fee)
+        .unwrap();
+    assert!(half_fee_in < full_fee_in);
+
+    let user2 = Addr::unchecked("user2");
+    let max_in = helper.assets[&test_coins[1]].with_balance(half_fee_in);
+    helper.give_me_money(&[max_in.clone()], &user2);
+    helper
+        .app
+        .send_tokens(
+            user2.clone(),
+            helper.pair_addr.clone(),
+            &[max_in.as_coin().unwrap()],
+        )
+        .unwrap();
+    helper
+        .app
+        .wasm_sudo(
+            helper.pair_addr.clone(),
+            &SudoMessage::SwapExactAmountOut {
+                sender: user2.to_string(),
+                token_in_denom: "uusd".to_string(),
+                token_in_max_amount: half_fee_in,
+                token_out: token_out.clone(),
+                swap_fee: half_fee,
+            },
+        )
+        .unwrap();
+    assert!(helper.coin_balance(&test_coins[0], &user2) >= token_out.amount.u128());
+}
+
+#[test]
+fn check_dispatched_swap_charges_pool_fee() {
+    let owner = Addr::unchecked("owner");
+
+    let test_coins = vec![TestCoin::native("uosmo"), TestCoin::native("uusd")];
+
+    let mut helper = Helper::new(&owner, test_coins.clone(), common_pcl_params()).unwrap();
+
+    let provide_assets = [
+        helper.assets[&test_coins[0]].with_balance(100_000_000000u128),
+        helper.assets[&test_coins[1]].with_balance(100_000_000000u128),
+    ];
+    helper.provide_liquidity(&owner, &provide_assets).unwrap();
+
+    // Imbalance the pool
+    let user = Addr::unchecked("user");
+    let offer_asset = helper.assets[&test_coins[0]].with_balance(5_000_000000u128);
+    helper.give_me_money(&[offer_asset.clone()], &user);
+    helper
+        .swap(&user, &offer_asset, Some(Decimal::percent(50)))
+        .unwrap();
+
+    // Osmosis queries the swap fee after the pair sent the offer to the pool.
+    // For a rebalancing swap it is lower than the fee for the pre-trade pool state.
+    // The pair must charge the fee for the pre-trade state anyway.
+    let offer_asset = helper.assets[&test_coins[1]].with_balance(2_000_000000u128);
+    let sim_resp = helper.simulate_swap(&offer_asset, None).unwrap();
+    let osmo_balance = helper.coin_balance(&test_coins[0], &user);
+    helper.give_me_money(&[offer_asset.clone()], &user);
+    helper
+        .swap(&user, &offer_asset, Some(Decimal::percent(50)))
+        .unwrap();
+    assert_eq!(
+        helper.coin_balance(&test_coins[0], &user) - osmo_balance,
+        sim_resp.return_amount.u128()
+    );
+}
+
+#[test]
+fn check_swap_fee_query_during_dispatch() {
+    let owner = Addr::unchecked("owner");
+
+    let test_coins = vec![TestCoin::native("uosmo"), TestCoin::native("uusd")];
+
+    let mut helper = Helper::new(&owner, test_coins.clone(), common_pcl_params()).unwrap();
+
+    let provide_assets = [
+        helper.assets[&test_coins[0]].with_balance(100_000_000000u128),
+        helper.assets[&test_coins[1]].with_balance(100_000_000000u128),
+    ];
+    helper.provide_liquidity(&owner, &provide_assets).unwrap();
+
+    let user = Addr::unchecked("user");
+    let offer_asset = helper.assets[&test_coins[0]].with_balance(5_000_000000u128);
+    helper.give_me_money(&[offer_asset.clone()], &user);
+    helper
+        .swap(&user, &offer_asset, Some(Decimal::percent(50)))
+        .unwrap();
+
+    // The pool manager queries the swap fee when the offer is already in the pair balance.
+    // The pair reports the fee for the pool state before the swap.
+    let swap_fee_before = helper.query_swap_fee().unwrap();
+    let offer_asset = helper.assets[&test_coins[1]].with_balance(2_000_000000u128);
+    helper.give_me_money(&[offer_asset.clone()], &user);
+    helper
+        .swap(&user, &offer_asset, Some(Decimal::percent(50)))
+        .unwrap();
+    assert_eq!(
+        helper.queried_swap_fees.borrow().last().copied(),
+        Some(swap_fee_before)
+    );
+
+    // Same for exact out swaps
+    let swap_fee_before = helper.query_swap_fee().unwrap();
+    let ask_asset = helper.assets[&test_coins[0]].with_balance(1_000_000000u128);
+    let max_offer = helper.assets[&test_coins[1]].with_balance(2_000_000000u128);
+    helper.give_me_money(&[max_offer.clone()], &user);
+    helper
+        .swap_exact_out(&user, &ask_asset, &max_offer, None)
+        .unwrap();
+    assert_eq!(
+        helper.queried_swap_fees.borrow().last().copied(),
+        Some(swap_fee_before)
+    );
+
+    // Swap params are removed after the swap, thus the fee reflects current balances
+    assert_ne!(helper.query_swap_fee().unwrap(), swap_fee_before);
+}
+
+#[test]
+fn check_exact_out_with_discounted_fee() {
+    let owner = Addr::unchecked("owner");
+
+    let test_coins = vec![TestCoin::native("uosmo"), TestCoin::native("uusd")];
+
+    let params = ConcentratedPoolParams {
+        price_scale: Decimal::from_ratio(10u8, 1u8),
+        ..common_pcl_params()
+    };
+    let mut helper = Helper::new(&owner, test_coins.clone(), params).unwrap();
+
+    let provide_assets = [
+        helper.assets[&test_coins[0]].with_balance(100_000_000000u128),
+        helper.assets[&test_coins[1]].with_balance(10_000_000000u128),
+    ];
+    helper.provide_liquidity(&owner, &provide_assets).unwrap();
+
+    let pool_fee = helper.query_swap_fee().unwrap();
+    let user = Addr::unchecked("user");
+
+    // Offer and ask assets have different prices, thus fee discount must be calculated in the ask asset
+    for (offer_ind, ask_amount, swap_fee) in [
+        (0, 100_000000u128, Decimal::zero()),
+        (0, 100_000000u128, pool_fee / Uint128::new(4)),
+        (1, 1_000_000000u128, pool_fee / Uint128::new(2)),
+    ] {
+        let offer_coin = &test_coins[offer_ind];
+        let ask_coin = &test_coins[1 ^ offer_ind];
+        let token_out = helper.assets[ask_coin]
+            .with_balance(ask_amount)
+            .as_coin()
+            .unwrap();
+        let token_in_denom = offer_coin.denom().unwrap();
+
+        let full_fee_in = helper
+            .query_calc_in_amount(&token_out, &token_in_denom, pool_fee)
+            .unwrap();
+        let token_in_amount = helper
+            .query_calc_in_amount(&token_out, &token_in_denom, swap_fee)
+            .unwrap();
+        assert!(token_in_amount < full_fee_in);
+
+        let token_in = helper.assets[offer_coin].with_balance(token_in_amount);
+        helper.give_me_money(&[token_in.clone()], &user);
+        helper
+            .app
+            .send_tokens(
+                user.clone(),
+                helper.pair_addr.clone(),
+                &[token_in.as_coin().unwrap()],
+            )
+            .unwrap();
+
+        let balance_before = helper.coin_balance(ask_coin, &user);
+        helper
+            .app
+            .wasm_sudo(
+                helper.pair_addr.clone(),
+                &SudoMessage::SwapExactAmountOut {
+                    sender: user.to_string(),
+                    token_in_denom,
+                    token_in_max_amount: token_in_amount,
+                    token_out: token_out.clone(),
+                    swap_fee,
+                },
+            )
+            .unwrap();
+        let received = helper.coin_balance(ask_coin, &user) - balance_before;
+        assert!(
+            received >= ask_amount,
+            "received {received} but requested {ask_amount}"
+        );
+    }
+}
+
+#[test]
+fn check_maker_fee_override() {
+    let owner = Addr::unchecked("owner");
+
+    let test_coins = vec![TestCoin::native("uosmo"), TestCoin::native("uusd")];
+
+    let mut helper = Helper::new(&owner, test_coins.clone(), common_pcl_params()).unwrap();
+
+    let provide_assets = [
+        helper.assets[&test_coins[0]].with_balance(100_000_000000u128),
+        helper.assets[&test_coins[1]].with_balance(100_000_000000u128),
+    ];
+    helper.provide_liquidity(&owner, &provide_assets).unwrap();
+
+    // Pair owner is not able to change maker fee
+    let pair_owner = Addr::unchecked("pair_owner");
+    helper
+        .app
+        .execute_contract(
+            owner.clone(),
+            helper.pair_addr.clone(),
+            &OsmoExecuteMsg::ProposeNewOwner {
+                owner: pair_owner.to_string(),
+                expires_in: 100,
+            },
+            &[],
+        )
+        .unwrap();
+    helper
+        .app
+        .execute_contract(
+            pair_owner.clone(),
+            helper.pair_addr.clone(),
+            &OsmoExecuteMsg::ClaimOwnership {},
+            &[],
+        )
+        .unwrap();
+    let err = helper
+        .set_maker_fee_override(&pair_owner, Some(1000))
+        .unwrap_err();
+    assert_eq!(ContractError::Unauthorized {}, err.downcast().unwrap());
+
+    let err = helper
+        .set_maker_fee_override(&owner, Some(10001))
+        .unwrap_err();
+    assert_eq!(
+        ContractError::MakerFeeOverrideOutOfBounds {},
+        err.downcast().unwrap()
+    );
+
+    let offer_asset = helper.assets[&test_coins[0]].with_balance(1_000_000000u128);
+    let default_sim = helper.simulate_swap(&offer_asset, None).unwrap();
+
+    helper.set_maker_fee_override(&owner, Some(1000)).unwrap();
+    let maker_fee_bps: Option<u16> = helper
+        .app
+        .wrap()
+        .query_wasm_smart(&helper.pair_addr, &QueryMsg::MakerFeeOverride {})
+        .unwrap();
+    assert_eq!(maker_fee_bps, Some(1000));
+
+    // Lower maker fee leaves more fees in the pool, thus the trader gets the same amount
+    let override_sim = helper.simulate_swap(&offer_asset, None).unwrap();
+    assert_eq!(override_sim.return_amount, default_sim.return_amount);
+    assert_eq!(
+        override_sim.commission_amount,
+        default_sim.commission_amount
+    );
+
+    // Osmosis simulation endpoints use the same maker fee
+    let pool_fee = helper.query_swap_fee().unwrap();
+    let calc_out = helper
+        .query_calc_out_amount(&offer_asset.as_coin().unwrap(), pool_fee)
+        .unwrap();
+    assert_eq!(calc_out, override_sim.return_amount);
+
+    // Execution matches the simulation
+    let user = Addr::unchecked("user");
+    helper.give_me_money(&[offer_asset.clone()], &user);
+    helper.swap(&user, &offer_asset, None).unwrap();
+    assert_eq!(
+        helper.coin_balance(&test_coins[1], &user),
+        override_sim.return_amount.u128()
+    );
+    let maker_fee = helper.coin_balance(&test_coins[1], &helper.fake_maker);
+    let expected_maker_fee = override_sim.commission_amount.u128() / 10;
+    assert!(
+        maker_fee.abs_diff(expected_maker_fee) <= 1,
+        "{maker_fee} {expected_maker_fee}"
+    );
+
+    // Zero maker fee for this pair
+    helper.set_maker_fee_override(&owner, Some(0)).unwrap();
+    helper.give_me_money(&[offer_asset.clone()], &user);
+    helper.swap(&user, &offer_asset, None).unwrap();
+    assert_eq!(
+        helper.coin_balance(&test_coins[1], &helper.fake_maker),
+        maker_fee
+    );
+
+    // Removing the override restores the factory maker fee
+    helper.set_maker_fee_override(&owner, None).unwrap();
+    helper.give_me_money(&[offer_asset.clone()], &user);
+    helper.swap(&user, &offer_asset, None).unwrap();
+    assert!(helper.coin_balance(&test_coins[1], &helper.fake_maker) > maker_fee);
+}
+
+#[test]
+fn check_simulate_provide() {
+    let owner = Addr::unchecked("owner");
+
+    let test_coins = vec![TestCoin::native("uosmo"), TestCoin::native("uusd")];
+
+    let mut helper = Helper::new(&owner, test_coins.clone(), common_pcl_params()).unwrap();
+
+    // Initial provide can't be one-sided
+    let err = helper
+        .simulate_provide(
+            &[helper.assets[&test_coins[0]].with_balance(100_000000u128)],
+            None,
+        )
+        .unwrap_err();
+    assert_eq!(
+        err,
+        StdError::generic_err(format!(
+            "Querier contract error: {}",
+            StdError::generic_err(ContractError::InvalidZeroAmount {}.to_string())
+        ))
+    );
+
+    // Initial provide excludes locked minimum liquidity
+    let provide_assets = [
+        helper.assets[&test_coins[0]].with_balance(100_000_000000u128),
+        helper.assets[&test_coins[1]].with_balance(100_000_000000u128),
+    ];
+    let sim = helper
+        .simulate_provide(&provide_assets, Some(f64_to_dec(0.5)))
+        .unwrap();
+    helper.provide_liquidity(&owner, &provide_assets).unwrap();
+    assert_eq!(
+        sim.share.u128(),
+        helper.native_balance(&helper.lp_token, &owner)
+    );
+    assert_eq!(sim.fee, Uint128::zero());
+
+    // Balanced provide
+    let user = Addr::unchecked("user");
+    let provide_assets = [
+        helper.assets[&test_coins[0]].with_balance(1_000_000000u128),
+        helper.assets[&test_coins[1]].with_balance(1_000_000000u128),
+    ];
+    helper.give_me_money(&provide_assets, &user);
+    let sim = helper
+        .simulate_provide(&provide_assets, Some(f64_to_dec(0.5)))
+        .unwrap();
+    helper.provide_liquidity(&user, &provide_assets).unwrap();
+    assert_eq!(
+        sim.share.u128(),
+        helper.native_balance(&helper.lp_token, &user)
+    );
+    assert_eq!(sim.slippage, Decimal256::zero());
+
+    // One-sided provide charges provide fee and has slippage
+    let user2 = Addr::unchecked("user2");
+    let provide_assets = [helper.assets[&test_coins[1]].with_balance(10_000_000000u128)];
+    helper.give_me_money(&provide_assets, &user2);
+    let sim = helper
+        .simulate_provide(&provide_assets, Some(f64_to_dec(0.5)))
+        .unwrap();
+    assert!(!sim.fee.is_zero());
+    assert!(!sim.slippage.is_zero());
+
+    // The same slippage tolerance check applies
+    helper
+        .simulate_provide(&provide_assets, Some(Decimal::permille(1)))
+        .unwrap_err();
+    helper
+        .provide_liquidity_with_slip_tolerance(&user2, &provide_assets, Some(Decimal::permille(1)))
+        .unwrap_err();
+
+    helper.provide_liquidity(&user2, &provide_assets).unwrap();
+    assert_eq!(
+        sim.share.u128(),
+        helper.native_balance(&helper.lp_token, &user2)
+    );
+}
+
+#[test]
+fn test_spot_price_diff_decimals() {
+    let owner = Addr::unchecked("owner");
+
+    let test_coins = vec![TestCoin::native("uusd"), TestCoin::native("eth")];
+
+    let mut helper = Helper::new(
+        &owner,
+        test_coins.clone(),
+        ConcentratedPoolParams {
+            price_scale: Decimal::from_ratio(3000u16, 1u8),
+            ..common_pcl_params()
+        },
+    )
+    .unwrap();
+
+    let provide_assets = [
+        helper.assets[&test_coins[0]].with_balance(3_000_000 * 1e6 as u128),
+        helper.assets[&test_coins[1]].with_balance(1_000 * 1e18 as u128),
+    ];
+    helper.give_me_money(&provide_assets, &owner);
+    helper.provide_liquidity(&owner, &provide_assets).unwrap();
+
+    let resp = helper
+        .app
+        .wrap()
+        .query_wasm_smart::<SpotPriceResponse>(
+            &helper.pair_addr,
+            &QueryMsg::SpotPrice {
+                quote_asset_denom: helper.assets[&test_coins[0]].to_string(),
+                base_asset_denom: helper.assets[&test_coins[1]].to_string(),
+            },
+        )
+        .unwrap();
+    assert_eq!(resp.spot_price.to_string(), "0.000000003000010176");
+
+    // query inverted price
+    let price = helper
+        .app
+        .wrap()
+        .query_wasm_smart::<SpotPriceResponse>(
+            &helper.pair_addr,
+            &QueryMsg::SpotPrice {
+                quote_asset_denom: helper.assets[&test_coins[1]].to_string(),
+                base_asset_denom: helper.assets[&test_coins[0]].to_string(),
+            },
+        )
+        .unwrap();
+    assert_eq!(price.spot_price.to_string(), "333334464.080626");
+}
+
+#[test]
+fn test_truncated_spot_price() {
+    let owner = Addr::unchecked("owner");
+
+    let test_coins = vec![TestCoin::native("uusd"), TestCoin::native("eth")];
+
+    let mut helper = Helper::new(
+        &owner,
+        test_coins.clone(),
         ConcentratedPoolParams {
             price_scale: Decimal::from_ratio(1u128, 10000u128),
             ..common_pcl_params()
@@ -1621,40 +3507,510 @@ fn test_truncated_spot_price() {
     .unwrap();
 
     let provide_assets = [
-        helper.assets[&test_coins[0]].with_balance(1e2 as u128),
-        helper.assets[&test_coins[1]].with_balance(1_000 * 1e18 as u128),
+        helper.assets[&test_coins[0]].with_balance(1e2 as u128),
+        helper.assets[&test_coins[1]].with_balance(1_000 * 1e18 as u128),
+    ];
+    helper.give_me_money(&provide_assets, &owner);
+    helper.provide_liquidity(&owner, &provide_assets).unwrap();
+
+    let err = helper
+        .app
+        .wrap()
+        .query_wasm_smart::<SpotPriceResponse>(
+            &helper.pair_addr,
+            &QueryMsg::SpotPrice {
+                quote_asset_denom: helper.assets[&test_coins[0]].to_string(),
+                base_asset_denom: helper.assets[&test_coins[1]].to_string(),
+            },
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Generic error: Querier contract error: Generic error: Normalized price 0.000000100003868184 became zero after denormalization"
+    );
+}
+
+#[test]
+fn check_reverse_swaps() {
+    let owner = Addr::unchecked("owner");
+
+    let test_coins = vec![TestCoin::native("uosmo"), TestCoin::native("uusd")];
+
+    let params = ConcentratedPoolParams {
+        track_asset_balances: Some(true),
+        ..common_pcl_params()
+    };
+    let mut helper = Helper::new(&owner, test_coins.clone(), params).unwrap();
+
+    let provide_assets = [
+        helper.assets[&test_coins[0]].with_balance(100_000_000000u128),
+        helper.assets[&test_coins[1]].with_balance(100_000_000000u128),
+    ];
+    helper.provide_liquidity(&owner, &provide_assets).unwrap();
+
+    let user = Addr::unchecked("user");
+    let offer_asset = helper.assets[&test_coins[0]].with_balance(100_000000u128);
+    helper.give_me_money(&[offer_asset.clone()], &user);
+
+    // exchange rate is not 1:1 thus pool is not able to perform such reverse swap
+    let ask_asset = helper.assets[&test_coins[1]].with_balance(100_000000u128);
+    let err = helper
+        .reverse_swap(&user, &ask_asset, &offer_asset)
+        .unwrap_err();
+    assert_eq!(err.root_cause().to_string(), "Generic error: Not enough tokens to perform swap. Need 100453296 but token_in_max_amount is 100000000");
+
+    // check the user still holds their uosmo token and didn't receive uusd
+    assert_eq!(helper.coin_balance(&test_coins[0], &user), 100_000000);
+    assert_eq!(helper.coin_balance(&test_coins[1], &user), 0);
+
+    // Ask slightly less uusd
+    let ask_asset = helper.assets[&test_coins[1]].with_balance(95_000000u128);
+    helper
+        .reverse_swap(&user, &ask_asset, &offer_asset)
+        .unwrap();
+
+    // check balances. User received slightly more uusd than expected cuz PCL has dynamic fee
+    // and it can't forecast exact fee rate in reverse swaps
+    assert_eq!(helper.coin_balance(&test_coins[0], &user), 4_569430);
+    assert_eq!(helper.coin_balance(&test_coins[1], &user), 95_180600);
+
+    helper.app.update_block(next_block);
+
+    // Check that asset balance is being tracked
+    let res = helper
+        .query_asset_balance_at(
+            &helper.assets[&test_coins[0]],
+            helper.app.block_info().height,
+        )
+        .unwrap();
+    assert_eq!(res.unwrap().u128(), 100095_430570);
+
+    // make reverse swap in opposite direction
+    let user2 = Addr::unchecked("user2");
+    let offer_asset = helper.assets[&test_coins[1]].with_balance(100_000000u128);
+    helper.give_me_money(&[offer_asset.clone()], &user2);
+    let ask_asset = helper.assets[&test_coins[0]].with_balance(95_000000u128);
+    helper
+        .reverse_swap(&user2, &ask_asset, &offer_asset)
+        .unwrap();
+    assert_eq!(helper.coin_balance(&test_coins[0], &user), 4_569430);
+    assert_eq!(helper.coin_balance(&test_coins[1], &user), 95_180600);
+
+    // try to abuse reverse swap and use equal offer and ask assets
+    let user3 = Addr::unchecked("user3");
+    let offer_asset = helper.assets[&test_coins[1]].with_balance(100_000000u128);
+    helper.give_me_money(&[offer_asset.clone()], &user3);
+    let ask_asset = helper.assets[&test_coins[1]].with_balance(95_000000u128);
+    let err = helper
+        .reverse_swap(&user3, &ask_asset, &offer_asset)
+        .unwrap_err();
+    assert_eq!(
+        err.root_cause().to_string(),
+        format!(
+            "Generic error: Invalid swap: {0} to {0}",
+            offer_asset.info.to_string()
+        )
+    );
+}
+
+#[test]
+fn check_pool_state_query() {
+    let owner = Addr::unchecked("owner");
+
+    let test_coins = vec![TestCoin::native("uosmo"), TestCoin::native("uusd")];
+
+    let mut helper = Helper::new(&owner, test_coins.clone(), common_pcl_params()).unwrap();
+
+    // Empty pool reports zero D and reserves
+    let state = helper.query_pool_state().unwrap();
+    assert_eq!(state.d, Decimal256::zero());
+    assert_eq!(state.price_scale, Decimal256::one());
+    assert!(state.reserves.iter().all(|asset| asset.amount.is_zero()));
+
+    let provide_assets = [
+        helper.assets[&test_coins[0]].with_balance(100_000_000000u128),
+        helper.assets[&test_coins[1]].with_balance(100_000_000000u128),
+    ];
+    helper.provide_liquidity(&owner, &provide_assets).unwrap();
+
+    let offer_asset = helper.assets[&test_coins[0]].with_balance(100_000000u128);
+    helper.give_me_money(&[offer_asset.clone()], &owner);
+    helper.swap(&owner, &offer_asset, None).unwrap();
+
+    let state = helper.query_pool_state().unwrap();
+    assert_eq!(state.d, helper.query_d().unwrap());
+    assert_eq!(state.amp, f64_to_dec(40f64));
+    assert_eq!(state.gamma, f64_to_dec(0.000145));
+    assert_eq!(state.future_amp, state.amp);
+    assert_eq!(state.future_gamma, state.gamma);
+    assert_eq!(
+        state.last_price_update,
+        helper.app.block_info().time.seconds()
+    );
+    assert!(state.last_price > Decimal256::one());
+    assert!(state.xcp_profit_real >= Decimal256::one());
+    assert_eq!(
+        state.reserves[0].amount,
+        Decimal256::from_str("100100").unwrap()
+    );
+    assert!(state.reserves[1].amount < Decimal256::from_str("100000").unwrap());
+
+    // Promotion is reflected in the response
+    helper.app.next_block(86400);
+    let future_time = helper.app.block_info().time.seconds() + 100_000;
+    let action = ConcentratedPoolUpdateParams::Promote(PromoteParams {
+        next_amp: f64_to_dec(44f64),
+        next_gamma: f64_to_dec(0.0001),
+        future_time,
+    });
+    helper.update_config(&owner, &action).unwrap();
+    helper.app.next_block(50_000);
+
+    let state = helper.query_pool_state().unwrap();
+    assert_eq!(state.initial_amp, f64_to_dec(40f64));
+    assert_eq!(state.future_amp, f64_to_dec(44f64));
+    assert_eq!(state.future_time, future_time);
+    assert_eq!(state.amp, f64_to_dec(42f64));
+}
+
+#[test]
+fn check_referral_swap() {
+    let owner = Addr::unchecked("owner");
+
+    let test_coins = vec![TestCoin::native("uosmo"), TestCoin::native("uusd")];
+
+    let mut helper = Helper::new(&owner, test_coins.clone(), common_pcl_params()).unwrap();
+
+    let provide_assets = [
+        helper.assets[&test_coins[0]].with_balance(100_000_000000u128),
+        helper.assets[&test_coins[1]].with_balance(100_000_000000u128),
+    ];
+    helper.provide_liquidity(&owner, &provide_assets).unwrap();
+
+    let user = Addr::unchecked("user");
+    let referral = Addr::unchecked("referral");
+    let offer_asset = helper.assets[&test_coins[0]].with_balance(100_000000u128);
+
+    // Commission is capped
+    let err = helper
+        .simulate_swap_with_referral(&offer_asset, Some(&referral), Some(Decimal::percent(2)))
+        .unwrap_err();
+    assert_eq!(
+        err,
+        StdError::generic_err(format!(
+            "Querier contract error: {}",
+            StdError::generic_err(ContractError::ReferralCommissionTooHigh {}.to_string())
+        ))
+    );
+    let err = helper
+        .swap_with_referral(
+            &user,
+            &offer_asset,
+            Some(&referral),
+            Some(Decimal::percent(2)),
+        )
+        .unwrap_err();
+    assert_eq!(
+        ContractError::ReferralCommissionTooHigh {},
+        err.downcast().unwrap()
+    );
+
+    // Referral address and commission must be specified together
+    for (referral_address, referral_commission) in
+        [(None, Some(Decimal::percent(1))), (Some(&referral), None)]
+    {
+        let err = helper
+            .simulate_swap_with_referral(&offer_asset, referral_address, referral_commission)
+            .unwrap_err();
+        assert_eq!(
+            err,
+            StdError::generic_err(format!(
+                "Querier contract error: {}",
+                StdError::generic_err(ContractError::IncompleteReferral {}.to_string())
+            ))
+        );
+        let err = helper
+            .simulate_reverse_swap_with_referral(
+                &helper.assets[&test_coins[1]].with_balance(50_000000u128),
+                referral_address,
+                referral_commission,
+            )
+            .unwrap_err();
+        assert_eq!(
+            err,
+            StdError::generic_err(format!(
+                "Querier contract error: {}",
+                StdError::generic_err(ContractError::IncompleteReferral {}.to_string())
+            ))
+        );
+        let err = helper
+            .swap_with_referral(&user, &offer_asset, referral_address, referral_commission)
+            .unwrap_err();
+        assert_eq!(
+            ContractError::IncompleteReferral {},
+            err.downcast().unwrap()
+        );
+    }
+
+    // Commission is deducted from the offer amount
+    let sim = helper
+        .simulate_swap_with_referral(&offer_asset, Some(&referral), Some(Decimal::percent(1)))
+        .unwrap();
+    let expected = helper
+        .simulate_swap(
+            &helper.assets[&test_coins[0]].with_balance(99_000000u128),
+            None,
+        )
+        .unwrap();
+    assert_eq!(sim, expected);
+
+    helper
+        .swap_with_referral(
+            &user,
+            &offer_asset,
+            Some(&referral),
+            Some(Decimal::percent(1)),
+        )
+        .unwrap();
+    assert_eq!(helper.coin_balance(&test_coins[0], &referral), 1_000000);
+    assert_eq!(helper.coin_balance(&test_coins[0], &user), 0);
+    assert_eq!(
+        helper.coin_balance(&test_coins[1], &user),
+        sim.return_amount.u128()
+    );
+
+    // Referral commission never enters the pool
+    assert_eq!(
+        helper.coin_balance(&test_coins[0], &helper.pair_addr),
+        100_099_000000
+    );
+
+    // Reverse simulation adds the referral commission on top of the amount entering the pool
+    let ask_asset = helper.assets[&test_coins[1]].with_balance(50_000000u128);
+    let plain_sim = helper.simulate_reverse_swap(&ask_asset, None).unwrap();
+    let sim = helper
+        .simulate_reverse_swap_with_referral(&ask_asset, Some(&referral), Some(Decimal::percent(1)))
+        .unwrap();
+    assert_eq!(
+        sim.offer_amount,
+        plain_sim.offer_amount.div_ceil(Decimal::percent(99))
+    );
+
+    let offer_asset = helper.assets[&test_coins[0]].with_balance(sim.offer_amount);
+    let expected = helper
+        .simulate_swap(
+            &helper.assets[&test_coins[0]].with_balance(plain_sim.offer_amount),
+            None,
+        )
+        .unwrap();
+    let user_balance_before = helper.coin_balance(&test_coins[1], &user);
+    let referral_balance_before = helper.coin_balance(&test_coins[0], &referral);
+    helper.give_me_money(&[offer_asset.clone()], &user);
+    helper
+        .swap_with_referral(
+            &user,
+            &offer_asset,
+            Some(&referral),
+            Some(Decimal::percent(1)),
+        )
+        .unwrap();
+    let referral_amount = helper.coin_balance(&test_coins[0], &referral) - referral_balance_before;
+    assert!(sim.offer_amount.u128() - referral_amount >= plain_sim.offer_amount.u128());
+    assert!(
+        helper.coin_balance(&test_coins[1], &user) - user_balance_before
+            >= expected.return_amount.u128()
+    );
+}
+
+#[test]
+fn check_swap_hooks() {
+    let owner = Addr::unchecked("owner");
+
+    let test_coins = vec![TestCoin::native("uosmo"), TestCoin::native("uusd")];
+
+    let mut helper = Helper::new(&owner, test_coins.clone(), common_pcl_params()).unwrap();
+
+    let provide_assets = [
+        helper.assets[&test_coins[0]].with_balance(100_000_000000u128),
+        helper.assets[&test_coins[1]].with_balance(100_000_000000u128),
     ];
-    helper.give_me_money(&provide_assets, &owner);
     helper.provide_liquidity(&owner, &provide_assets).unwrap();
 
+    let hook = helper.setup_swap_hook(false).unwrap();
+    let failing_hook = helper.setup_swap_hook(true).unwrap();
+
+    let random_user = Addr::unchecked("random_user");
     let err = helper
-        .app
-        .wrap()
-        .query_wasm_smart::<SpotPriceResponse>(
-            &helper.pair_addr,
-            &QueryMsg::SpotPrice {
-                quote_asset_denom: helper.assets[&test_coins[0]].to_string(),
-                base_asset_denom: helper.assets[&test_coins[1]].to_string(),
-            },
+        .update_swap_hooks(&random_user, &[&hook], &[])
+        .unwrap_err();
+    assert_eq!(ContractError::Unauthorized {}, err.downcast().unwrap());
+
+    helper
+        .update_swap_hooks(&owner, &[&hook, &failing_hook], &[])
+        .unwrap();
+    assert_eq!(
+        helper.query_swap_hooks().unwrap(),
+        vec![hook.clone(), failing_hook.clone()]
+    );
+
+    let err = helper.update_swap_hooks(&owner, &[&hook], &[]).unwrap_err();
+    assert_eq!(
+        ContractError::SwapHookAlreadyExists(hook.to_string()),
+        err.downcast().unwrap()
+    );
+    let extra_hooks = (0..4)
+        .map(|i| Addr::unchecked(format!("hook{i}")))
+        .collect_vec();
+    let err = helper
+        .update_swap_hooks(&owner, &extra_hooks.iter().collect_vec(), &[])
+        .unwrap_err();
+    assert_eq!(ContractError::TooManySwapHooks {}, err.downcast().unwrap());
+
+    // Failing hook doesn't revert the swap
+    let user = Addr::unchecked("user");
+    let offer_asset = helper.assets[&test_coins[0]].with_balance(100_000000u128);
+    let resp = helper.swap(&user, &offer_asset, None).unwrap();
+    assert!(resp.has_event(&Event::new("wasm").add_attribute("action", "swap_hook_failed")));
+    let return_amount = helper.coin_balance(&test_coins[1], &user);
+
+    let calls = helper.query_swap_hook_calls(&hook).unwrap();
+    assert_eq!(calls.len(), 1);
+    let SwapHookMsg::OnSwap {
+        sender,
+        receiver,
+        offer_asset: hook_offer_asset,
+        return_asset,
+        ..
+    } = &calls[0];
+    assert_eq!(sender, user.as_str());
+    assert_eq!(receiver, user.as_str());
+    assert_eq!(hook_offer_asset, &offer_asset);
+    assert_eq!(return_asset.amount.u128(), return_amount);
+
+    // Exact out swaps notify hooks as well
+    helper
+        .swap_exact_out(
+            &user,
+            &helper.assets[&test_coins[0]].with_balance(10_000000u128),
+            &helper.assets[&test_coins[1]].with_balance(20_000000u128),
+            None,
         )
+        .unwrap();
+    let calls = helper.query_swap_hook_calls(&hook).unwrap();
+    assert_eq!(calls.len(), 2);
+    let SwapHookMsg::OnSwap { return_asset, .. } = &calls[1];
+    assert_eq!(
+        return_asset,
+        &helper.assets[&test_coins[0]].with_balance(10_000000u128)
+    );
+
+    helper
+        .update_swap_hooks(&owner, &[], &[&failing_hook])
+        .unwrap();
+    assert_eq!(helper.query_swap_hooks().unwrap(), vec![hook.clone()]);
+    let err = helper
+        .update_swap_hooks(&owner, &[], &[&failing_hook])
         .unwrap_err();
     assert_eq!(
-        err.to_string(),
-        "Generic error: Querier contract error: Generic error: Normalized price 0.000000100003868184 became zero after denormalization"
+        ContractError::SwapHookNotFound(failing_hook.to_string()),
+        err.downcast().unwrap()
     );
 }
 
 #[test]
-fn check_reverse_swaps() {
+fn check_twap() {
     let owner = Addr::unchecked("owner");
 
     let test_coins = vec![TestCoin::native("uosmo"), TestCoin::native("uusd")];
 
-    let params = ConcentratedPoolParams {
-        track_asset_balances: Some(true),
-        ..common_pcl_params()
+    let mut helper = Helper::new(&owner, test_coins.clone(), common_pcl_params()).unwrap();
+    let start_time = helper.app.block_info().time.seconds();
+
+    let err = helper.query_twap(start_time, None).unwrap_err();
+    assert!(err.to_string().contains("Invalid time range"));
+    let err = helper.query_twap(start_time - 1, None).unwrap_err();
+    assert!(err.to_string().contains(&format!(
+        "Price history doesn't cover timestamp {}",
+        start_time - 1
+    )));
+
+    let provide_assets = [
+        helper.assets[&test_coins[0]].with_balance(100_000_000000u128),
+        helper.assets[&test_coins[1]].with_balance(100_000_000000u128),
+    ];
+    helper.provide_liquidity(&owner, &provide_assets).unwrap();
+
+    helper.app.next_block(1000);
+    assert_eq!(
+        helper.query_twap(start_time, None).unwrap(),
+        Decimal256::one()
+    );
+
+    // Burst of swaps within one block doesn't dominate the average
+    let user = Addr::unchecked("user");
+    for _ in 0..10 {
+        let offer_asset = helper.assets[&test_coins[0]].with_balance(1_000_000000u128);
+        helper.give_me_money(&[offer_asset.clone()], &user);
+        helper
+            .swap(&user, &offer_asset, Some(f64_to_dec(0.5)))
+            .unwrap();
+    }
+    let swap_time = helper.app.block_info().time.seconds();
+    let last_price = helper.query_pool_state().unwrap().last_price;
+
+    helper.app.next_block(1000);
+    let twap = helper.query_twap(start_time, None).unwrap();
+    let expected = (Decimal256::one() * Decimal256::from_ratio(1000u16, 1u8)
+        + last_price * Decimal256::from_ratio(1000u16, 1u8))
+        / Decimal256::from_ratio(2000u16, 1u8);
+    assert_eq!(twap, expected);
+
+    // Only the price after the burst is in effect afterwards
+    assert_eq!(helper.query_twap(swap_time, None).unwrap(), last_price);
+    assert_eq!(
+        helper.query_twap(start_time, Some(swap_time)).unwrap(),
+        Decimal256::one()
+    );
+    // Only bounded price history is kept
+    helper.app.next_block(MAX_TWAP_WINDOW);
+    let now = helper.app.block_info().time.seconds();
+    let err = helper
+        .query_twap(now - MAX_TWAP_WINDOW - 1, None)
+        .unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("TWAP window must not start earlier than"));
+    assert_eq!(
+        helper.query_twap(now - MAX_TWAP_WINDOW, None).unwrap(),
+        last_price
+    );
+}
+
+#[test]
+fn check_observations_size() {
+    let owner = Addr::unchecked("owner");
+
+    let test_coins = vec![TestCoin::native("uusd"), TestCoin::native("rc")];
+
+    let mut helper = Helper::new(&owner, test_coins.clone(), common_pcl_params()).unwrap();
+
+    let mut init_params = ConcentratedPoolInitParams {
+        params: common_pcl_params(),
+        observations_size: Some(5),
     };
-    let mut helper = Helper::new(&owner, test_coins.clone(), params).unwrap();
+    let asset_infos = vec![
+        native_asset_info("uosmo".to_string()),
+        native_asset_info("uusd".to_string()),
+    ];
+    let err = helper
+        .create_pair(asset_infos.clone(), &init_params)
+        .unwrap_err();
+    assert_eq!(
+        ContractError::InvalidObservationsSize {},
+        err.downcast().unwrap()
+    );
+    init_params.observations_size = Some(100);
+    helper.create_pair(asset_infos, &init_params).unwrap();
 
     let provide_assets = [
         helper.assets[&test_coins[0]].with_balance(100_000_000000u128),
@@ -1663,66 +4019,292 @@ fn check_reverse_swaps() {
     helper.provide_liquidity(&owner, &provide_assets).unwrap();
 
     let user = Addr::unchecked("user");
-    let offer_asset = helper.assets[&test_coins[0]].with_balance(100_000000u128);
+    for _ in 0..20 {
+        let offer_asset = helper.assets[&test_coins[0]].with_balance(1_000000u128);
+        helper.give_me_money(&[offer_asset.clone()], &user);
+        helper.swap(&user, &offer_asset, None).unwrap();
+        helper.app.next_block(600);
+    }
+
+    let err = helper.resize_observations(&user, 10).unwrap_err();
+    assert_eq!(ContractError::Unauthorized {}, err.downcast().unwrap());
+    let err = helper.resize_observations(&owner, 5).unwrap_err();
+    assert_eq!(
+        ContractError::InvalidObservationsSize {},
+        err.downcast().unwrap()
+    );
+
+    helper.observe_price(600 * 15).unwrap();
+    helper.resize_observations(&owner, 10).unwrap();
+    helper.observe_price(600).unwrap();
+
+    // Observations older than the new window are dropped
+    helper.observe_price(600 * 15).unwrap_err();
+
+    // Pool keeps accumulating observations after resize
+    let offer_asset = helper.assets[&test_coins[0]].with_balance(1_000000u128);
     helper.give_me_money(&[offer_asset.clone()], &user);
+    helper.swap(&user, &offer_asset, None).unwrap();
+    helper.app.next_block(600);
+    helper.observe_price(0).unwrap();
+}
 
-    // exchange rate is not 1:1 thus pool is not able to perform such reverse swap
-    let ask_asset = helper.assets[&test_coins[1]].with_balance(100_000000u128);
+#[test]
+fn check_lp_token_metadata() {
+    let owner = Addr::unchecked("owner");
+
+    let test_coins = vec![TestCoin::native("uosmo"), TestCoin::native("uusd")];
+
+    let mut helper = Helper::new(&owner, test_coins.clone(), common_pcl_params()).unwrap();
+
+    let metadata = helper.query_lp_metadata().unwrap().unwrap();
+    assert_eq!(metadata.base, helper.lp_token);
+    assert_eq!(metadata.name, "Astroport uosmo-uusd PCL LP token");
+    assert_eq!(metadata.symbol, "uosmo-uusd-LP");
+    assert_eq!(metadata.display, "lp-uosmo-uusd");
+    assert_eq!(
+        metadata
+            .denom_units
+            .iter()
+            .map(|unit| (unit.denom.as_str(), unit.exponent))
+            .collect_vec(),
+        vec![(helper.lp_token.as_str(), 0), ("lp-uosmo-uusd", 6)]
+    );
+
+    let new_metadata = LpTokenMetadata {
+        name: "OSMO-USD LP".to_string(),
+        symbol: "OSMOUSD".to_string(),
+        description: "OSMO-USD liquidity".to_string(),
+    };
     let err = helper
-        .reverse_swap(&user, &ask_asset, &offer_asset)
+        .update_lp_token_metadata(&Addr::unchecked("random"), new_metadata.clone())
         .unwrap_err();
-    assert_eq!(err.root_cause().to_string(), "Generic error: Not enough tokens to perform swap. Need 100453296 but token_in_max_amount is 100000000");
+    assert_eq!(ContractError::Unauthorized {}, err.downcast().unwrap());
 
-    // check the user still holds their uosmo token and didn't receive uusd
-    assert_eq!(helper.coin_balance(&test_coins[0], &user), 100_000000);
-    assert_eq!(helper.coin_balance(&test_coins[1], &user), 0);
+    for invalid_metadata in [
+        LpTokenMetadata {
+            name: "".to_string(),
+            ..new_metadata.clone()
+        },
+        LpTokenMetadata {
+            symbol: " ".to_string(),
+            ..new_metadata.clone()
+        },
+        LpTokenMetadata {
+            name: "a".repeat(MAX_LP_TOKEN_METADATA_LENGTH + 1),
+            ..new_metadata.clone()
+        },
+    ] {
+        let err = helper
+            .update_lp_token_metadata(&owner, invalid_metadata)
+            .unwrap_err();
+        assert_eq!(
+            ContractError::InvalidLpTokenMetadata {},
+            err.downcast().unwrap()
+        );
+    }
 
-    // Ask slightly less uusd
-    let ask_asset = helper.assets[&test_coins[1]].with_balance(95_000000u128);
     helper
-        .reverse_swap(&user, &ask_asset, &offer_asset)
+        .update_lp_token_metadata(&owner, new_metadata.clone())
         .unwrap();
+    let metadata = helper.query_lp_metadata().unwrap().unwrap();
+    assert_eq!(metadata.name, new_metadata.name);
+    assert_eq!(metadata.symbol, new_metadata.symbol);
+    assert_eq!(metadata.description, new_metadata.description);
+    assert_eq!(metadata.display, "lp-uosmo-uusd");
+}
 
-    // check balances. User received slightly more uusd than expected cuz PCL has dynamic fee
-    // and it can't forecast exact fee rate in reverse swaps
-    assert_eq!(helper.coin_balance(&test_coins[0], &user), 4_569430);
-    assert_eq!(helper.coin_balance(&test_coins[1], &user), 95_180600);
+#[test]
+fn check_flash_swap() {
+    let owner = Addr::unchecked("owner");
 
-    helper.app.update_block(next_block);
+    let test_coins = vec![TestCoin::native("uosmo"), TestCoin::native("uusd")];
 
-    // Check that asset balance is being tracked
-    let res = helper
-        .query_asset_balance_at(
-            &helper.assets[&test_coins[0]],
-            helper.app.block_info().height,
-        )
-        .unwrap();
-    assert_eq!(res.unwrap().u128(), 100095_430570);
+    let mut helper = Helper::new(&owner, test_coins.clone(), common_pcl_params()).unwrap();
+    // Twin pool to compare the flash swap with the usual one
+    let mut twin = Helper::new(&owner, test_coins.clone(), common_pcl_params()).unwrap();
 
-    // make reverse swap in opposite direction
-    let user2 = Addr::unchecked("user2");
-    let offer_asset = helper.assets[&test_coins[1]].with_balance(100_000000u128);
-    helper.give_me_money(&[offer_asset.clone()], &user2);
-    let ask_asset = helper.assets[&test_coins[0]].with_balance(95_000000u128);
-    helper
-        .reverse_swap(&user2, &ask_asset, &offer_asset)
-        .unwrap();
-    assert_eq!(helper.coin_balance(&test_coins[0], &user), 4_569430);
-    assert_eq!(helper.coin_balance(&test_coins[1], &user), 95_180600);
+    let provide_assets = [
+        helper.assets[&test_coins[0]].with_balance(100_000_000000u128),
+        helper.assets[&test_coins[1]].with_balance(100_000_000000u128),
+    ];
+    helper.provide_liquidity(&owner, &provide_assets).unwrap();
+    twin.provide_liquidity(&owner, &provide_assets).unwrap();
 
-    // try to abuse reverse swap and use equal offer and ask assets
-    let user3 = Addr::unchecked("user3");
-    let offer_asset = helper.assets[&test_coins[1]].with_balance(100_000000u128);
-    helper.give_me_money(&[offer_asset.clone()], &user3);
-    let ask_asset = helper.assets[&test_coins[1]].with_balance(95_000000u128);
+    let receiver = helper.setup_flash_swap_receiver().unwrap();
+    let offer_asset = helper.assets[&test_coins[0]].with_balance(100_000000u128);
+    // Receiver repays the flash swap from its own funds
+    helper.give_me_money(&[offer_asset.clone()], &receiver);
+
+    let user = Addr::unchecked("user");
     let err = helper
-        .reverse_swap(&user3, &ask_asset, &offer_asset)
+        .flash_swap(
+            &user,
+            &offer_asset,
+            &receiver,
+            MockFlashSwapAction::Underpay {},
+        )
         .unwrap_err();
     assert_eq!(
         err.root_cause().to_string(),
-        format!(
-            "Generic error: Invalid swap: {0} to {0}",
-            offer_asset.info.to_string()
+        ContractError::FlashSwapNotRepaid {
+            expected: offer_asset.amount,
+            received: offer_asset.amount - Uint128::one(),
+        }
+        .to_string()
+    );
+
+    let err = helper
+        .flash_swap(
+            &user,
+            &offer_asset,
+            &receiver,
+            MockFlashSwapAction::Reenter {},
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.root_cause().to_string(),
+        ContractError::FlashSwapInProgress {}.to_string()
+    );
+
+    // Pool can't be used as a price source until the flash swap is repaid
+    let err = helper
+        .flash_swap(
+            &user,
+            &offer_asset,
+            &receiver,
+            MockFlashSwapAction::QueryPool {},
+        )
+        .unwrap_err();
+    assert!(err
+        .root_cause()
+        .to_string()
+        .contains(&ContractError::FlashSwapInProgress {}.to_string()));
+
+    helper
+        .flash_swap(
+            &user,
+            &offer_asset,
+            &receiver,
+            MockFlashSwapAction::Repay {},
+        )
+        .unwrap();
+    twin.swap(&user, &offer_asset, None).unwrap();
+
+    assert_eq!(helper.coin_balance(&test_coins[0], &receiver), 0);
+    assert_eq!(
+        helper.coin_balance(&test_coins[1], &receiver),
+        twin.coin_balance(&test_coins[1], &user)
+    );
+    // Pool state is updated in the same way as for the usual swap
+    for coin in &test_coins {
+        assert_eq!(
+            helper.coin_balance(coin, &helper.pair_addr),
+            twin.coin_balance(coin, &twin.pair_addr)
+        );
+    }
+    assert_eq!(
+        helper.query_pool_state().unwrap(),
+        twin.query_pool_state().unwrap()
+    );
+
+    // Pair is unlocked after the flash swap
+    helper.swap(&user, &offer_asset, None).unwrap();
+}
+
+#[test]
+fn check_flash_swap_osmosis_queries_and_hooks() {
+    let owner = Addr::unchecked("owner");
+
+    let test_coins = vec![TestCoin::native("uosmo"), TestCoin::native("uusd")];
+
+    let mut helper = Helper::new(&owner, test_coins.clone(), common_pcl_params()).unwrap();
+    let mut twin = Helper::new(&owner, test_coins.clone(), common_pcl_params()).unwrap();
+
+    let provide_assets = [
+        helper.assets[&test_coins[0]].with_balance(100_000_000000u128),
+        helper.assets[&test_coins[1]].with_balance(100_000_000000u128),
+    ];
+    helper.provide_liquidity(&owner, &provide_assets).unwrap();
+    twin.provide_liquidity(&owner, &provide_assets).unwrap();
+
+    let hook = helper.setup_swap_hook(false).unwrap();
+    helper.update_swap_hooks(&owner, &[&hook], &[]).unwrap();
+
+    // Flash swaps bypass the pool manager, thus the taker fee is not charged
+    *helper.taker_fee.borrow_mut() = Decimal::permille(1);
+
+    let receiver = helper.setup_flash_swap_receiver().unwrap();
+    let offer_asset = helper.assets[&test_coins[0]].with_balance(100_000000u128);
+    helper.give_me_money(&[offer_asset.clone()], &receiver);
+
+    let user = Addr::unchecked("user");
+    // Osmosis endpoints are available during the callback
+    let resp = helper
+        .flash_swap(
+            &user,
+            &offer_asset,
+            &receiver,
+            MockFlashSwapAction::QueryOsmosis {},
         )
+        .unwrap();
+    twin.swap(&user, &offer_asset, None).unwrap();
+
+    assert_eq!(
+        helper.coin_balance(&test_coins[1], &receiver),
+        twin.coin_balance(&test_coins[1], &user)
+    );
+    for coin in &test_coins {
+        assert_eq!(
+            helper.coin_balance(coin, &Addr::unchecked(COMMUNITY_POOL)),
+            0
+        );
+    }
+
+    // Pool liquidity reported during the callback already includes the owed offer asset
+    let pool_liquidity = test_coins
+        .iter()
+        .map(|coin| {
+            helper.assets[coin]
+                .with_balance(helper.coin_balance(coin, &helper.pair_addr))
+                .as_coin()
+                .unwrap()
+        })
+        .collect_vec();
+    assert_eq!(
+        helper.query_flash_pool_liquidity(&receiver).unwrap(),
+        pool_liquidity
+    );
+
+    // Swap hooks are notified only after the flash swap is repaid
+    let repaid_ind = resp
+        .events
+        .iter()
+        .position(|event| {
+            event
+                .attributes
+                .iter()
+                .any(|attr| attr.key == "action" && attr.value == "flash_swap_repaid")
+        })
+        .unwrap();
+    let hook_ind = resp
+        .events
+        .iter()
+        .position(|event| {
+            event.ty == "execute"
+                && event
+                    .attributes
+                    .iter()
+                    .any(|attr| attr.key == "_contract_address" && attr.value == hook.as_str())
+        })
+        .unwrap();
+    assert!(hook_ind > repaid_ind);
+
+    let calls = helper.query_swap_hook_calls(&hook).unwrap();
+    assert_eq!(calls.len(), 1);
+    let SwapHookMsg::OnSwap { return_asset, .. } = &calls[0];
+    assert_eq!(
+        return_asset.amount.u128(),
+        helper.coin_balance(&test_coins[1], &receiver)
     );
 }
@@ -1,18 +1,45 @@
+use std::str::FromStr;
+
 use astroport::asset::{native_asset_info, token_asset_info, PairInfo};
 use astroport::factory::PairType;
 use astroport::pair::InstantiateMsg;
 use astroport::pair_concentrated::ConcentratedPoolParams;
+use astroport_circular_buffer::BufferManager;
 use astroport_pcl_common::state::{Config, PoolState};
 use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
-use cosmwasm_std::{to_json_binary, Addr, Reply, StdError, SubMsgResponse, SubMsgResult};
+use cosmwasm_std::{
+    coin, to_json_binary, Addr, Decimal, Decimal256, Env, Order, Reply, StdError, StdResult,
+    Storage, SubMsgResponse, SubMsgResult, Uint128,
+};
 use osmosis_std::types::osmosis::tokenfactory::v1beta1::MsgCreateDenomResponse;
 
-use astroport_on_osmosis::pair_pcl::ExecuteMsg;
+use astroport_on_osmosis::pair_pcl::{ExecuteMsg, MigrateMsg, ZapInSwap};
 use astroport_pcl_osmo::contract::{execute, instantiate, reply};
 use astroport_pcl_osmo::error::ContractError;
-use astroport_pcl_osmo::state::CONFIG;
+use astroport_pcl_osmo::migrate::migrate;
+use astroport_pcl_osmo::state::{
+    TwapPoint, ZapInParams, CONFIG, IS_ACTIVE, OBSERVATIONS, SWAP_PARAMS, TWAP_POINTS,
+    ZAP_IN_PARAMS,
+};
+
+const CHAIN_ID: &str = "osmosis-1";
 
-const FACTORY_ADDRESS: &str = include_str!("../src/factory_address");
+/// Returns the factory address trusted on the specified chain
+fn trusted_factory(chain_id: &str) -> &'static str {
+    include_str!("../src/factory_addresses")
+        .lines()
+        .find_map(|line| {
+            let (chain, factory) = line.split_once(' ')?;
+            (chain == chain_id).then_some(factory.trim())
+        })
+        .unwrap()
+}
+
+fn osmosis_env() -> Env {
+    let mut env = mock_env();
+    env.block.chain_id = CHAIN_ID.to_string();
+    env
+}
 
 #[test]
 fn test_replies() {
@@ -85,17 +112,27 @@ fn test_set_pool_id() {
     let msg = ExecuteMsg::SetPoolId { pool_id: 1 };
     let err = execute(
         deps.as_mut(),
-        mock_env(),
+        osmosis_env(),
         mock_info("random", &[]),
         msg.clone(),
     )
     .unwrap_err();
     assert_eq!(err, ContractError::Unauthorized {});
 
+    // Factory trusted on another chain can't set pool id
+    let err = execute(
+        deps.as_mut(),
+        osmosis_env(),
+        mock_info(trusted_factory("osmo-test-5"), &[]),
+        msg.clone(),
+    )
+    .unwrap_err();
+    assert_eq!(err, ContractError::Unauthorized {});
+
     execute(
         deps.as_mut(),
-        mock_env(),
-        mock_info(FACTORY_ADDRESS, &[]),
+        osmosis_env(),
+        mock_info(trusted_factory(CHAIN_ID), &[]),
         msg.clone(),
     )
     .unwrap();
@@ -103,14 +140,49 @@ fn test_set_pool_id() {
 
     let err = execute(
         deps.as_mut(),
-        mock_env(),
-        mock_info(FACTORY_ADDRESS, &[]),
+        osmosis_env(),
+        mock_info(trusted_factory(CHAIN_ID), &[]),
         msg,
     )
     .unwrap_err();
     assert_eq!(err, ContractError::PoolIdAlreadySet {});
 }
 
+#[test]
+fn test_zap_in_in_progress() {
+    let mut deps = mock_dependencies();
+    ZAP_IN_PARAMS
+        .save(
+            deps.as_mut().storage,
+            &ZapInParams {
+                sender: Addr::unchecked("user"),
+                receiver: None,
+                slippage_tolerance: None,
+                min_lp_to_receive: Uint128::one(),
+                balances_before: vec![],
+            },
+        )
+        .unwrap();
+
+    let msg = ExecuteMsg::ZapIn {
+        swaps: vec![ZapInSwap {
+            amount: Uint128::one(),
+            route: vec![],
+        }],
+        slippage_tolerance: None,
+        min_lp_to_receive: Uint128::one(),
+        receiver: None,
+    };
+    let err = execute(
+        deps.as_mut(),
+        osmosis_env(),
+        mock_info("user", &[coin(1, "uosmo")]),
+        msg,
+    )
+    .unwrap_err();
+    assert_eq!(err, ContractError::ZapInInProgress {});
+}
+
 #[test]
 fn try_init_with_cw20() {
     let init_msg = InstantiateMsg {
@@ -119,7 +191,7 @@ fn try_init_with_cw20() {
             native_asset_info("uosmo".to_string()),
         ],
         token_code_id: 0,
-        factory_addr: FACTORY_ADDRESS.to_string(),
+        factory_addr: trusted_factory(CHAIN_ID).to_string(),
         init_params: Some(
             to_json_binary(&ConcentratedPoolParams {
                 amp: Default::default(),
@@ -152,3 +224,184 @@ fn try_init_with_cw20() {
         ContractError::Std(StdError::generic_err("CW20 tokens are not supported"))
     );
 }
+
+#[test]
+fn try_init_with_untrusted_factory() {
+    let mut init_msg = InstantiateMsg {
+        asset_infos: vec![
+            native_asset_info("uosmo".to_string()),
+            native_asset_info("uusd".to_string()),
+        ],
+        token_code_id: 0,
+        factory_addr: "untrusted_factory".to_string(),
+        init_params: Some(
+            to_json_binary(&ConcentratedPoolParams {
+                amp: Default::default(),
+                gamma: Default::default(),
+                mid_fee: Default::default(),
+                out_fee: Default::default(),
+                fee_gamma: Default::default(),
+                repeg_profit_threshold: Default::default(),
+                min_price_scale_delta: Default::default(),
+                price_scale: Decimal::one(),
+                ma_half_time: 0,
+                track_asset_balances: None,
+                fee_share: None,
+            })
+            .unwrap(),
+        ),
+    };
+
+    let mut deps = mock_dependencies();
+    let err = instantiate(
+        deps.as_mut(),
+        osmosis_env(),
+        mock_info("sender", &[]),
+        init_msg.clone(),
+    )
+    .unwrap_err();
+    assert_eq!(
+        err,
+        ContractError::UntrustedFactory("untrusted_factory".to_string())
+    );
+
+    // Factories are trusted only on their own chain
+    init_msg.factory_addr = trusted_factory("osmo-test-5").to_string();
+    let err = instantiate(
+        deps.as_mut(),
+        osmosis_env(),
+        mock_info("sender", &[]),
+        init_msg,
+    )
+    .unwrap_err();
+    assert_eq!(
+        err,
+        ContractError::UntrustedFactory(trusted_factory("osmo-test-5").to_string())
+    );
+}
+
+/// Builds a raw storage key of a [`cw_storage_plus::Map`] entry
+fn map_key(namespace: &[u8], key: &[u8]) -> Vec<u8> {
+    let mut raw_key = (namespace.len() as u16).to_be_bytes().to_vec();
+    raw_key.extend_from_slice(namespace);
+    raw_key.extend_from_slice(key);
+    raw_key
+}
+
+/// Writes the state as it was serialized by v1.0.3
+fn write_v103_state(storage: &mut dyn Storage, env: &Env) {
+    storage.set(
+        b"contract_info",
+        br#"{"contract":"astroport-pcl-osmo","version":"1.0.3"}"#,
+    );
+    let config = format!(
+        r#"{{"pair_info":{{"asset_infos":[{{"native_token":{{"denom":"uosmo"}}}},{{"native_token":{{"denom":"uusd"}}}}],"contract_addr":"{contract}","liquidity_token":"factory/{contract}/astroport/share","pair_type":{{"custom":"concentrated"}}}},"factory_addr":"{factory}","pool_params":{{"mid_fee":"0.0026","out_fee":"0.0045","fee_gamma":"0.00023","repeg_profit_threshold":"0.000002","min_price_scale_delta":"0.000146","ma_half_time":600}},"pool_state":{{"initial":{{"amp":"0","gamma":"0"}},"future":{{"amp":"40","gamma":"0.000145"}},"future_time":0,"initial_time":0,"price_state":{{"oracle_price":"2","last_price":"2.5","price_scale":"2","last_price_update":{ts},"xcp_profit":"1","xcp_profit_real":"1"}}}},"owner":null,"track_asset_balances":false,"fee_share":null}}"#,
+        contract = env.contract.address,
+        factory = trusted_factory(CHAIN_ID),
+        ts = env.block.time.seconds() - 100,
+    );
+    storage.set(b"config", config.as_bytes());
+    storage.set(b"pool_id", b"1");
+    // Swap params didn't have the deadline field
+    storage.set(
+        b"swap_params",
+        br#"{"belief_price":null,"max_spread":"0.01","sender":"user","to":null}"#,
+    );
+    // Observations buffer with a fixed capacity of 3000 and two observations
+    storage.set(b"observations_state", br#"{"capacity":3000,"head":2}"#);
+    for (i, price) in ["2", "2.5"].into_iter().enumerate() {
+        storage.set(
+            &map_key(b"observations_buffer", &(i as u32).to_be_bytes()),
+            format!(
+                r#"{{"ts":{},"price":"{price}","price_sma":"{price}"}}"#,
+                env.block.time.seconds() - 200 + i as u64 * 100
+            )
+            .as_bytes(),
+        );
+    }
+}
+
+#[test]
+fn test_migrate_from_legacy_state() {
+    let mut deps = mock_dependencies();
+    let env = osmosis_env();
+
+    write_v103_state(deps.as_mut().storage, &env);
+
+    let resp = migrate(deps.as_mut(), env.clone(), MigrateMsg {}).unwrap();
+    assert!(resp
+        .attributes
+        .iter()
+        .any(|attr| attr.key == "previous_contract_version" && attr.value == "1.0.3"));
+
+    let version = cw2::get_contract_version(deps.as_ref().storage).unwrap();
+    assert_eq!(version.contract, "astroport-pcl-osmo");
+    assert_eq!(version.version, env!("CARGO_PKG_VERSION"));
+
+    let config = CONFIG.load(deps.as_ref().storage).unwrap();
+    assert!(!config.track_asset_balances);
+    assert_eq!(config.owner, None);
+
+    // Price history is seeded with the last pool price
+    let twap_points = TWAP_POINTS
+        .range(deps.as_ref().storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()
+        .unwrap();
+    assert_eq!(
+        twap_points,
+        vec![(
+            env.block.time.seconds(),
+            TwapPoint {
+                cumulative_price: Decimal256::zero(),
+                price: Decimal256::from_str("2.5").unwrap(),
+            }
+        )]
+    );
+
+    assert!(IS_ACTIVE.load(deps.as_ref().storage).unwrap());
+
+    // Observations written by 1.0.3 are readable through the buffer API
+    let buffer = BufferManager::new(deps.as_ref().storage, OBSERVATIONS).unwrap();
+    assert_eq!(buffer.capacity(), 3000);
+    assert_eq!(buffer.head(), 2);
+    let observation = buffer
+        .read_single(deps.as_ref().storage, 1)
+        .unwrap()
+        .unwrap();
+    assert_eq!(observation.price, Decimal::from_str("2.5").unwrap());
+
+    let swap_params = SWAP_PARAMS.load(deps.as_ref().storage).unwrap();
+    assert_eq!(swap_params.deadline, None);
+
+    // Can't migrate twice
+    let err = migrate(deps.as_mut(), env, MigrateMsg {}).unwrap_err();
+    assert_eq!(err, ContractError::MigrationError {});
+}
+
+#[test]
+fn test_migrate_unknown_versions() {
+    let mut deps = mock_dependencies();
+    let env = osmosis_env();
+
+    write_v103_state(deps.as_mut().storage, &env);
+
+    cw2::set_contract_version(deps.as_mut().storage, "astroport-pcl-osmo", "0.9.0").unwrap();
+    let err = migrate(deps.as_mut(), env.clone(), MigrateMsg {}).unwrap_err();
+    assert_eq!(err, ContractError::MigrationError {});
+
+    cw2::set_contract_version(
+        deps.as_mut().storage,
+        "astroport-pair-concentrated",
+        "1.0.2",
+    )
+    .unwrap();
+    let err = migrate(deps.as_mut(), env.clone(), MigrateMsg {}).unwrap_err();
+    assert_eq!(err, ContractError::MigrationError {});
+
+    // Observations buffer capacity must be within the allowed limits
+    deps.storage
+        .set(b"observations_state", br#"{"capacity":20000,"head":2}"#);
+    cw2::set_contract_version(deps.as_mut().storage, "astroport-pcl-osmo", "1.0.3").unwrap();
+    let err = migrate(deps.as_mut(), env, MigrateMsg {}).unwrap_err();
+    assert_eq!(err, ContractError::MigrationError {});
+}
@@ -0,0 +1,18 @@
+use cosmwasm_std::{entry_point, DepsMut, Empty, Env, MessageInfo, Response, StdResult};
+
+use astroport_on_osmosis::pair_pcl::SwapHookMsg;
+
+#[entry_point]
+pub fn instantiate(_: DepsMut, _: Env, _: MessageInfo, _: Empty) -> StdResult<Response> {
+    Ok(Response::new())
+}
+
+/// Writes to storage until the hook runs out of gas
+#[entry_point]
+pub fn execute(deps: DepsMut, _: Env, _: MessageInfo, _: SwapHookMsg) -> StdResult<Response> {
+    let mut counter = 0u64;
+    loop {
+        counter += 1;
+        deps.storage.set(b"counter", &counter.to_be_bytes());
+    }
+}
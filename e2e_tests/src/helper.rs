@@ -68,10 +68,14 @@ pub fn default_pcl_params(price_scale: Decimal) -> ConcentratedPoolParams {
     }
 }
 
+/// Chain id of the osmosis-test-tube app
+const TEST_CHAIN_ID: &str = "osmosis-1";
+
 const BUILD_CONTRACTS: &[&str] = &[
     // "astroport-pcl-osmo", // we build this contract separately to hardcode factory address
     "astroport-factory-osmosis",
     "astroport-maker-osmosis",
+    "mock-gas-burning-hook",
 ];
 
 fn compile_wasm(project_dir: &str, contract: &str) {
@@ -97,6 +101,38 @@ fn compile_wasm(project_dir: &str, contract: &str) {
     );
 }
 
+/// Restores the original factory addresses list when dropped,
+/// thus the source tree is not left modified even if the contract build panics.
+struct FactoryAddressesGuard {
+    path: &'static str,
+    original: String,
+}
+
+impl FactoryAddressesGuard {
+    /// Pins **factory** for [`TEST_CHAIN_ID`] keeping addresses of other chains.
+    fn pin(path: &'static str, factory: &str) -> Self {
+        let original = fs::read_to_string(path).unwrap();
+        let mut factory_addresses = original
+            .lines()
+            .filter(|line| !line.starts_with(&format!("{TEST_CHAIN_ID} ")))
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        factory_addresses.push(format!("{TEST_CHAIN_ID} {factory}"));
+        let guard = Self { path, original };
+        fs::write(path, factory_addresses.join("\n")).unwrap();
+
+        guard
+    }
+}
+
+impl Drop for FactoryAddressesGuard {
+    fn drop(&mut self) {
+        if let Err(err) = fs::write(self.path, &self.original) {
+            eprintln!("Failed to restore {}: {err}", self.path);
+        }
+    }
+}
+
 pub struct TestAppWrapper<'a> {
     pub signer: SigningAccount,
     pub wasm: Wasm<'a, OsmosisTestApp>,
@@ -183,6 +219,12 @@ impl<'a> TestAppWrapper<'a> {
             .init_contract("satellite", &satellite_init_msg, &[])
             .unwrap();
 
+        println!("Storing gas burning hook contract...");
+        let hook_code_id = helper
+            .store_code(target_dir.join("mock_gas_burning_hook.wasm"))
+            .unwrap();
+        helper.code_ids.insert("gas-burning-hook", hook_code_id);
+
         println!("Storing maker contract...");
         let maker_code_id = helper
             .store_code(target_dir.join("astroport_maker_osmosis.wasm"))
@@ -223,13 +265,15 @@ impl<'a> TestAppWrapper<'a> {
             .init_contract("factory", &factory_init_msg, &[])
             .unwrap();
 
-        // Pin factory address in the PCL wasm binary
-        fs::write(
-            "../contracts/pair_concentrated/src/factory_address",
-            &helper.factory,
-        )
-        .unwrap();
-        compile_wasm(&project_dir, "astroport-pcl-osmo");
+        // Pin factory address for the test chain in the PCL wasm binary.
+        // The original list is restored right after the build
+        {
+            let _guard = FactoryAddressesGuard::pin(
+                "../contracts/pair_concentrated/src/factory_addresses",
+                &helper.factory,
+            );
+            compile_wasm(&project_dir, "astroport-pcl-osmo");
+        }
         println!("Storing cl pool contract...");
         let cl_pool_wasm = target_dir.join("astroport_pcl_osmo.wasm");
         let gov = GovWithAppAccess::new(app);
@@ -349,6 +393,8 @@ impl<'a> TestAppWrapper<'a> {
                 slippage_tolerance: slippage_tolerance.map(f64_to_dec),
                 auto_stake: None,
                 receiver: None,
+                min_lp_to_receive: None,
+                deadline: None,
             },
             &sorted_coins,
             sender,
@@ -363,7 +409,12 @@ impl<'a> TestAppWrapper<'a> {
     ) -> RunnerExecuteResult<MsgExecuteContractResponse> {
         self.wasm.execute(
             pair_addr,
-            &ExecuteMsg::WithdrawLiquidity { assets: vec![] },
+            &ExecuteMsg::WithdrawLiquidity {
+                assets: vec![],
+                receiver: None,
+                min_assets_to_receive: None,
+                single_asset: None,
+            },
             &[lp_tokens],
             sender,
         )
@@ -478,6 +529,9 @@ impl<'a> TestAppWrapper<'a> {
             belief_price,
             max_spread,
             to: None,
+            deadline: None,
+            referral_address: None,
+            referral_commission: None,
         };
 
         self.wasm.execute(
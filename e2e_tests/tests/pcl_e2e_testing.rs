@@ -1,12 +1,13 @@
 use astroport::asset::{native_asset_info, AssetInfo, AssetInfoExt};
 use astroport::pair;
-use cosmwasm_std::{coin, to_json_binary, Coin, Decimal};
+use cosmwasm_std::{coin, to_json_binary, Coin, Decimal, Empty};
 use osmosis_std::types::osmosis::cosmwasmpool::v1beta1::{
     ContractInfoByPoolIdRequest, ContractInfoByPoolIdResponse, MsgCreateCosmWasmPool,
     MsgCreateCosmWasmPoolResponse,
 };
 use osmosis_test_tube::{Account, OsmosisTestApp, Runner};
 
+use astroport_on_osmosis::pair_pcl::ExecuteMsg;
 use astroport_osmo_e2e_tests::helper::{default_pcl_params, TestAppWrapper};
 
 fn gas_fee() -> Coin {
@@ -203,3 +204,72 @@ fn swap_with_fake_token() {
         "execute error: failed to execute message; message index: 0: The asset random does not belong to the pair: execute wasm contract failed"
     );
 }
+
+#[test]
+fn swap_with_gas_burning_hook() {
+    let app = OsmosisTestApp::new();
+    let helper = TestAppWrapper::bootstrap(&app).unwrap();
+
+    let foo_denom = helper.register_and_mint("foo", 1_000_000_000000, 6, None);
+    let bar_denom = helper.register_and_mint("bar", 1_000_000_000000, 6, None);
+    let foo = native_asset_info(foo_denom.clone());
+    let bar = native_asset_info(bar_denom.clone());
+
+    let (pair_addr, _) = helper
+        .create_pair(
+            &[foo.clone(), bar.clone()],
+            default_pcl_params(Decimal::from_ratio(1u8, 2u8)),
+        )
+        .unwrap();
+    let pool_id = helper.get_pool_id_by_contract(&pair_addr);
+
+    helper
+        .provide(
+            &helper.signer,
+            &pair_addr,
+            &[
+                foo.with_balance(50_000_000000u128),
+                bar.with_balance(100_000_000000u128),
+            ],
+            None,
+        )
+        .unwrap();
+
+    // Gas is not metered in cw-multi-test thus this case is covered here
+    let hook = helper
+        .init_contract("gas-burning-hook", &Empty {}, &[])
+        .unwrap();
+    helper
+        .wasm
+        .execute(
+            &pair_addr,
+            &ExecuteMsg::UpdateSwapHooks {
+                add: vec![hook],
+                remove: vec![],
+            },
+            &[],
+            &helper.signer,
+        )
+        .unwrap();
+
+    // Hook runs out of gas but the swap via DEX goes through
+    let asset = foo.with_balance(1_000000u128);
+    let user = helper
+        .app
+        .init_account(&[asset.as_coin().unwrap(), gas_fee()])
+        .unwrap();
+    helper.swap_on_dex(&user, pool_id, &asset).unwrap();
+
+    assert_eq!(helper.coin_balance(&user.address(), &foo_denom), 0);
+    let bar_bal = helper.coin_balance(&user.address(), &bar_denom);
+    assert!(bar_bal > 0);
+
+    // Same for the swap dispatched through the pair contract
+    let asset = bar.with_balance(bar_bal);
+    helper
+        .swap_on_pair(&user, &pair_addr, &asset, None)
+        .unwrap();
+
+    assert_eq!(helper.coin_balance(&user.address(), &bar_denom), 0);
+    assert!(helper.coin_balance(&user.address(), &foo_denom) > 0);
+}
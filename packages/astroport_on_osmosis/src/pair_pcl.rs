@@ -1,12 +1,21 @@
 use astroport::asset::PairInfo;
-use astroport::asset::{Asset, AssetInfo};
+use astroport::asset::{Asset, AssetInfo, DecimalAsset};
 use astroport::observation::OracleObservation;
 use astroport::pair::{
     ConfigResponse, PoolResponse, ReverseSimulationResponse, SimulationResponse,
 };
+use astroport::pair_concentrated::ConcentratedPoolParams;
 use cosmwasm_schema::{cw_serde, QueryResponses};
 use cosmwasm_std::Empty;
-use cosmwasm_std::{Binary, Coin, Decimal, Decimal256, Uint128, Uint64};
+use cosmwasm_std::{Addr, Binary, Coin, Decimal, Decimal256, Uint128, Uint64};
+
+/// Maximum number of observations dropped by a single observation buffer shrink.
+/// Larger shrinks must be done in several steps to keep gas usage bounded.
+pub const MAX_DROPPED_OBSERVATIONS: u32 = 3000;
+
+/// Maximum length of the LP token name and symbol.
+/// Fits the default metadata built from two full-length pool denoms.
+pub const MAX_LP_TOKEN_METADATA_LENGTH: usize = 512;
 
 /// This structure describes the execute messages available in the contract.
 #[cw_serde]
@@ -19,15 +28,29 @@ pub enum ExecuteMsg {
         assets: Vec<Asset>,
         /// The slippage tolerance that allows liquidity provision only if the price in the pool doesn't move too much
         slippage_tolerance: Option<Decimal>,
-        /// Determines whether the LP tokens minted for the user is auto_staked in the Generator contract
+        /// Determines whether the LP tokens minted for the user is auto_staked in the incentives contract
+        /// set as generator_address in the factory config
         auto_stake: Option<bool>,
         /// The receiver of LP tokens
         receiver: Option<String>,
+        /// The minimum amount of LP tokens to receive. Provide fails if the pool mints less
+        min_lp_to_receive: Option<Uint128>,
+        /// Block time in seconds after which the provide is no longer valid
+        deadline: Option<u64>,
     },
-    /// Withdraw liquidity from the pool
+    /// Withdraw liquidity from the pool.
+    /// If assets are specified, the pool burns only the amount of LP tokens needed to withdraw them
+    /// and returns the rest back to the sender.
     WithdrawLiquidity {
         #[serde(default)]
         assets: Vec<Asset>,
+        /// The receiver of withdrawn assets. If not specified, assets are sent to the sender
+        receiver: Option<String>,
+        /// Minimum amounts of assets to receive. Withdraw fails if any of them is not satisfied
+        min_assets_to_receive: Option<Vec<Asset>>,
+        /// If set, the pool burns all sent LP tokens and returns only this asset.
+        /// Can not be combined with non-empty `assets`.
+        single_asset: Option<AssetInfo>,
     },
     /// Perform a swap in the pool
     Swap {
@@ -36,6 +59,52 @@ pub enum ExecuteMsg {
         belief_price: Option<Decimal>,
         max_spread: Option<Decimal>,
         to: Option<String>,
+        /// Block time in seconds after which the swap is no longer valid
+        deadline: Option<u64>,
+        /// The address which receives the referral commission
+        referral_address: Option<String>,
+        /// The share of the offer amount sent to the referral address.
+        /// Must be specified together with the referral address
+        referral_commission: Option<Decimal>,
+    },
+    /// Swap the offer asset to receive exactly `ask_asset`.
+    /// `max_offer` must be sent along with the message. Unused offer amount is refunded to the sender.
+    SwapExactOut {
+        ask_asset: Asset,
+        max_offer: Asset,
+        to: Option<String>,
+    },
+    /// Swap `offer_asset` without paying upfront.
+    /// The ask asset is sent to the receiver which then gets [`FlashSwapCallbackMsg`]
+    /// and must send `offer_asset` back to the pair within that call.
+    /// All other pair operations and non-static queries, except the Osmosis pool manager endpoints,
+    /// are locked until the flash swap is repaid. Swap hooks are notified after the repayment.
+    /// Flash swaps bypass the pool manager, thus the Osmosis taker fee is not charged.
+    /// The swap fee is charged on the ask asset in the same way as for the usual swap,
+    /// thus exactly `offer_asset` must be repaid without any additional fee.
+    FlashSwap {
+        offer_asset: Asset,
+        belief_price: Option<Decimal>,
+        max_spread: Option<Decimal>,
+        /// The receiver contract. Defaults to the sender
+        to: Option<String>,
+        /// Arbitrary message forwarded to the receiver in the callback
+        msg: Binary,
+    },
+    /// Provide liquidity with a single coin which doesn't belong to the pool.
+    /// The coin is swapped into one or both pool assets via the Osmosis pool manager
+    /// and the received amounts are provided to the pool.
+    ZapIn {
+        /// Swaps to perform. Their amounts must sum up to the sent coin amount
+        /// and each route must end with a distinct pool asset.
+        swaps: Vec<ZapInSwap>,
+        /// The slippage tolerance that allows liquidity provision only if the price in the pool doesn't move too much
+        slippage_tolerance: Option<Decimal>,
+        /// The minimum amount of LP tokens to receive. It protects the whole zap-in
+        /// including pool manager swaps thus it is mandatory and must not be zero
+        min_lp_to_receive: Uint128,
+        /// The receiver of LP tokens
+        receiver: Option<String>,
     },
     /// Update the pair configuration
     UpdateConfig { params: Binary },
@@ -51,6 +120,127 @@ pub enum ExecuteMsg {
     DropOwnershipProposal {},
     /// Used to claim contract ownership.
     ClaimOwnership {},
+    /// Pauses or unpauses pool operations independently.
+    /// Only the pair owner (the factory owner if the pair owner is not set) can execute this.
+    /// Omitted flags are left unchanged.
+    SetPauseFlags {
+        provide: Option<bool>,
+        swap: Option<bool>,
+        withdraw: Option<bool>,
+    },
+    /// Sets the maximum allowed relative deviation of the post-trade price from the EMA oracle price.
+    /// Swaps exceeding it are rejected. None disables the check.
+    /// Only the pair owner (the factory owner if the pair owner is not set) can execute this.
+    SetMaxPriceDeviation { max_deviation: Option<Decimal> },
+    /// Overrides the maker fee (in bps) configured in the factory for this pair only.
+    /// None removes the override. Only the factory owner can execute this.
+    SetMakerFeeOverride { maker_fee_bps: Option<u16> },
+    /// Adds or removes contracts which receive [`SwapHookMsg`] after each swap.
+    /// Only the pair owner (the factory owner if the pair owner is not set) can execute this.
+    UpdateSwapHooks {
+        /// Hook contracts to register
+        add: Vec<String>,
+        /// Hook contracts to unregister
+        remove: Vec<String>,
+    },
+    /// Changes the observation buffer capacity. Growing keeps all observations.
+    /// Shrinking keeps the most recent ones and may drop at most [`MAX_DROPPED_OBSERVATIONS`] at once.
+    /// Only the pair owner (the factory owner if the pair owner is not set) can execute this.
+    ResizeObservations { new_size: u32 },
+    /// Updates the bank metadata of the LP token.
+    /// Name and symbol must be non-empty and at most [`MAX_LP_TOKEN_METADATA_LENGTH`] characters long.
+    /// Only the pair owner (the factory owner if the pair owner is not set) can execute this.
+    UpdateLpTokenMetadata { metadata: LpTokenMetadata },
+}
+
+/// Human readable part of the LP token bank metadata
+#[cw_serde]
+pub struct LpTokenMetadata {
+    pub name: String,
+    pub symbol: String,
+    pub description: String,
+}
+
+/// This message is sent to every registered swap hook contract after each swap.
+/// Hook failures don't revert the swap.
+#[cw_serde]
+pub enum SwapHookMsg {
+    OnSwap {
+        /// The sender of the swap
+        sender: String,
+        /// The receiver of the ask asset
+        receiver: String,
+        /// The asset which was swapped
+        offer_asset: Asset,
+        /// The asset which was received
+        return_asset: Asset,
+        /// Spread amount in ask asset units
+        spread_amount: Uint128,
+        /// Total swap fee in ask asset units
+        commission_amount: Uint128,
+        /// Maker fee in ask asset units
+        maker_fee_amount: Uint128,
+        /// Fee share in ask asset units
+        fee_share_amount: Uint128,
+    },
+}
+
+/// This message is sent to the flash swap receiver right after it gets the ask asset.
+/// The receiver must send `offer_asset` back to the pair before returning, otherwise the whole
+/// flash swap is reverted.
+#[cw_serde]
+pub enum FlashSwapCallbackMsg {
+    FlashSwapCallback {
+        /// The sender of the flash swap
+        sender: String,
+        /// The asset which must be sent back to the pair
+        offer_asset: Asset,
+        /// The asset which was received
+        return_asset: Asset,
+        /// The message passed along with the flash swap
+        msg: Binary,
+    },
+}
+
+/// PCL pool init params. Extends [`ConcentratedPoolParams`] with the observation buffer capacity.
+/// Unknown fields are not denied since serde doesn't support it together with flatten.
+#[derive(
+    ::cosmwasm_schema::serde::Serialize,
+    ::cosmwasm_schema::serde::Deserialize,
+    Clone,
+    Debug,
+    PartialEq,
+    ::cosmwasm_schema::schemars::JsonSchema,
+)]
+#[serde(crate = "::cosmwasm_schema::serde")]
+#[schemars(crate = "::cosmwasm_schema::schemars")]
+pub struct ConcentratedPoolInitParams {
+    #[serde(flatten)]
+    pub params: ConcentratedPoolParams,
+    /// Capacity of the observation buffer. Astroport default is used if not set
+    pub observations_size: Option<u32>,
+}
+
+/// This structure describes a migration message.
+/// Asset balances tracking is enabled via [`ExecuteMsg::UpdateConfig`] rather than on migration.
+#[cw_serde]
+#[derive(Default)]
+pub struct MigrateMsg {}
+
+/// One hop of the Osmosis pool manager swap route. Mirrors Osmosis `SwapAmountInRoute`.
+#[cw_serde]
+pub struct SwapRouteHop {
+    pub pool_id: u64,
+    pub token_out_denom: String,
+}
+
+/// Describes a swap performed during zap-in
+#[cw_serde]
+pub struct ZapInSwap {
+    /// Amount of the sent coin to swap
+    pub amount: Uint128,
+    /// Pool manager route. The last hop must end with one of the pool assets
+    pub route: Vec<SwapRouteHop>,
 }
 
 /// This structure describes all sudo messages needed to integrate with Osmosis
@@ -98,17 +288,44 @@ pub enum QueryMsg {
     /// Returns information about the share of the pool in a vector that contains objects of type [`Asset`].
     #[returns(Vec<Asset>)]
     Share { amount: Uint128 },
+    /// Returns the amount of LP tokens which will be minted for providing the specified assets
+    /// as well as the provide fee and slippage. One-sided provide is supported.
+    /// Fails if the provide would fail with the given slippage tolerance.
+    #[returns(SimulateProvideResponse)]
+    SimulateProvide {
+        assets: Vec<Asset>,
+        slippage_tolerance: Option<Decimal>,
+    },
+    /// Returns the amount of LP tokens which will be burned to withdraw the specified assets
+    /// from the pool in an imbalanced way.
+    #[returns(Uint128)]
+    SimulateImbalancedWithdraw { assets: Vec<Asset> },
+    /// Returns the amount of the asset which will be received for burning `lp_amount` LP tokens
+    /// in a single asset withdraw.
+    #[returns(Uint128)]
+    SimulateSingleAssetWithdraw {
+        lp_amount: Uint128,
+        asset_info: AssetInfo,
+    },
     /// Returns information about a swap simulation
     #[returns(SimulationResponse)]
     Simulation {
         offer_asset: Asset,
         ask_asset_info: Option<AssetInfo>,
+        /// The referral address. Must be specified together with the referral commission
+        referral_address: Option<String>,
+        /// The referral commission deducted from the offer amount before the swap
+        referral_commission: Option<Decimal>,
     },
     /// Returns information about a reverse swap simulation
     #[returns(ReverseSimulationResponse)]
     ReverseSimulation {
         offer_asset_info: Option<AssetInfo>,
         ask_asset: Asset,
+        /// The referral address. Must be specified together with the referral commission
+        referral_address: Option<String>,
+        /// The referral commission. The returned offer amount includes it
+        referral_commission: Option<Decimal>,
     },
     /// Returns information about the cumulative prices
     #[returns(Empty)]
@@ -119,6 +336,9 @@ pub enum QueryMsg {
     /// Query LP token virtual price
     #[returns(Decimal256)]
     LpPrice {},
+    /// Returns detailed internal pool state for monitoring
+    #[returns(PoolStateResponse)]
+    PoolState {},
     /// Returns the balance of the specified asset that was in the pool just preceding the moment
     /// of the specified block height creation.
     #[returns(Option<Uint128>)]
@@ -129,6 +349,27 @@ pub enum QueryMsg {
     /// Query price from observations
     #[returns(OracleObservation)]
     Observe { seconds_ago: u64 },
+    /// Returns the time-weighted average price of the second asset in terms of the first one
+    /// (normalized to 18 decimals) between two timestamps (in seconds).
+    /// If `end_time` is not set, the current block time is used.
+    /// Price history is kept only for the last 7 days.
+    #[returns(Decimal256)]
+    Twap {
+        start_time: u64,
+        end_time: Option<u64>,
+    },
+    /// Returns which pool operations are paused
+    #[returns(PauseFlags)]
+    PauseFlags {},
+    /// Returns the maximum allowed deviation of the post-trade price from the oracle price
+    #[returns(Option<Decimal>)]
+    MaxPriceDeviation {},
+    /// Returns the maker fee override (in bps) if it is set
+    #[returns(Option<u16>)]
+    MakerFeeOverride {},
+    /// Returns registered swap hook contracts
+    #[returns(Vec<Addr>)]
+    SwapHooks {},
 
     ///
     /// # QueryMsg enum variants defined specifically for Osmosis
@@ -187,6 +428,65 @@ pub struct GetSwapFeeResponse {
     pub swap_fee: Decimal,
 }
 
+/// This structure holds the result of a liquidity provision simulation
+#[cw_serde]
+pub struct SimulateProvideResponse {
+    /// Amount of LP tokens minted to the provider. On the initial provide it excludes
+    /// the minimum liquidity amount which is locked on the pair contract
+    pub share: Uint128,
+    /// Amount of LP tokens withheld as the provide fee
+    pub fee: Uint128,
+    /// Provide slippage. It is zero if the deposit is close to the balanced one
+    pub slippage: Decimal256,
+}
+
+/// This structure holds detailed internal PCL pool state
+#[cw_serde]
+pub struct PoolStateResponse {
+    /// Internal price scale (price of the second asset in terms of the first one)
+    pub price_scale: Decimal256,
+    /// EMA price oracle
+    pub oracle_price: Decimal256,
+    /// Price of the last trade
+    pub last_price: Decimal256,
+    /// Timestamp of the last price update (in seconds)
+    pub last_price_update: u64,
+    /// Virtual price growth including fees
+    pub xcp_profit: Decimal256,
+    /// Virtual price growth which is shared with LPs
+    pub xcp_profit_real: Decimal256,
+    /// Current amplification coefficient
+    pub amp: Decimal,
+    /// Current gamma
+    pub gamma: Decimal,
+    /// Amplification coefficient at the start of the promotion
+    pub initial_amp: Decimal,
+    /// Gamma at the start of the promotion
+    pub initial_gamma: Decimal,
+    /// Promotion start timestamp (in seconds)
+    pub initial_time: u64,
+    /// Target amplification coefficient of the promotion
+    pub future_amp: Decimal,
+    /// Target gamma of the promotion
+    pub future_gamma: Decimal,
+    /// Promotion end timestamp (in seconds)
+    pub future_time: u64,
+    /// Current D invariant. Zero if the pool is empty
+    pub d: Decimal256,
+    /// Pool reserves normalized to 18 decimals
+    pub reserves: Vec<DecimalAsset>,
+}
+
+/// Describes which pool operations are paused
+#[cw_serde]
+#[derive(Default)]
+pub struct PauseFlags {
+    pub provide: bool,
+    /// Also pauses imbalanced and single asset withdrawals as they trade against the pool
+    pub swap: bool,
+    pub withdraw: bool,
+}
+
 #[cw_serde]
 pub struct IsActiveResponse {
     pub is_active: bool,